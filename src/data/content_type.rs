@@ -0,0 +1,80 @@
+use crate::path::{ArchiveType, CompressionType};
+
+/// The number of leading bytes [detect_content_type] needs to detect every content type.
+pub const DETECTION_BYTES: usize = ArchiveType::DETECTION_BYTES;
+
+/// Detect the content type (MIME type) of a file from its leading bytes (magic numbers).
+/// Only common media, document and archive formats are detected.
+///
+/// # Arguments
+/// * `bytes` - The first bytes of the file, up to [DETECTION_BYTES] bytes.
+///
+/// # Returns
+/// The MIME type of the file. None if the bytes match no known format.
+///
+/// # Example
+/// ```
+/// use backup_deduplicator::content_type::detect_content_type;
+///
+/// assert_eq!(detect_content_type(&[0xff, 0xd8, 0xff, 0xe0]), Some("image/jpeg"));
+/// assert_eq!(detect_content_type(b"%PDF-1.7"), Some("application/pdf"));
+/// assert_eq!(detect_content_type(b"\0\0\0\x20ftypisom"), Some("video/mp4"));
+/// assert_eq!(detect_content_type(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+/// assert_eq!(detect_content_type(&[0x1f, 0x8b, 0x08]), Some("application/gzip"));
+/// assert_eq!(detect_content_type(b"plain text"), None);
+/// ```
+pub fn detect_content_type(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (&[0xff, 0xd8, 0xff], "image/jpeg"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"II*\0", "image/tiff"),
+        (b"MM\0*", "image/tiff"),
+        (b"%PDF-", "application/pdf"),
+        (b"ID3", "audio/mpeg"),
+        (b"fLaC", "audio/flac"),
+        (b"OggS", "audio/ogg"),
+        (&[0x1a, 0x45, 0xdf, 0xa3], "video/x-matroska"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"Rar!\x1a\x07", "application/vnd.rar"),
+        (b"SQLite format 3\0", "application/vnd.sqlite3"),
+        (b"\x7fELF", "application/x-executable"),
+    ];
+
+    if let Some((_, content_type)) = SIGNATURES.iter().find(|(magic, _)| bytes.starts_with(magic)) {
+        return Some(content_type);
+    }
+
+    // RIFF containers name their format at offset 8, ISO media files their brand box at offset 4
+    if bytes.len() >= 12 && bytes.starts_with(b"RIFF") {
+        return match &bytes[8..12] {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        };
+    }
+    if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        return match &bytes[8..12] {
+            b"heic" | b"heix" | b"mif1" => Some("image/heic"),
+            b"qt  " => Some("video/quicktime"),
+            _ => Some("video/mp4"),
+        };
+    }
+
+    if let Some(archive) = ArchiveType::from_bytes(bytes) {
+        return Some(match archive {
+            ArchiveType::Zip => "application/zip",
+            ArchiveType::Tar => "application/x-tar",
+        });
+    }
+
+    CompressionType::from_bytes(bytes).map(|compression| match compression {
+        CompressionType::Gzip => "application/gzip",
+        CompressionType::Bzip2 => "application/x-bzip2",
+        CompressionType::Xz => "application/x-xz",
+        CompressionType::Zstd => "application/zstd",
+        CompressionType::Lz4 => "application/x-lz4",
+    })
+}
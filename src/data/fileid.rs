@@ -4,7 +4,7 @@
 use std::io;
 use std::path::Path;
 use file_id::FileId;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// Device id type.
 #[cfg(target_family = "unix")]
@@ -27,7 +27,7 @@ type FileIdType = u128; // high-res file-id
 /// # Fields
 /// * `inode` - The inode of the file.
 /// * `drive` - The device id of the file.
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct HandleIdentifier {
     pub inode: FileIdType,
     pub drive: DeviceIdType,
@@ -1,5 +1,6 @@
 use std::fmt;
 use std::fmt::Display;
+use std::ffi::OsStr;
 use std::path::Path;
 use std::str::FromStr;
 use serde::{Deserialize, Serialize, Serializer};
@@ -117,6 +118,36 @@ impl GeneralHashType {
         
         concatcp!(SHA2, SHA1, XXH, NULL)
     }
+
+    /// Returns the fastest available hash type, for builds that are only used to find duplicates.
+    /// Prefers XXH64, then SHA1, then SHA256, depending on the enabled features.
+    /// NULL if no hash function is enabled.
+    ///
+    /// XXH64 is not a cryptographic hash: files could be crafted to collide, but accidental
+    /// collisions are unlikely, and the analysis only groups files with equal size anyway.
+    ///
+    /// # Returns
+    /// The fastest available hash type.
+    ///
+    /// # Examples
+    /// ```
+    /// use backup_deduplicator::hash::GeneralHashType;
+    ///
+    /// let fastest = GeneralHashType::fastest();
+    /// assert!(GeneralHashType::supported_algorithms().contains(&fastest.to_string()));
+    /// ```
+    pub const fn fastest() -> GeneralHashType {
+        #[cfg(feature = "hash-xxh")]
+        const FASTEST: GeneralHashType = GeneralHashType::XXH64;
+        #[cfg(all(not(feature = "hash-xxh"), feature = "hash-sha1"))]
+        const FASTEST: GeneralHashType = GeneralHashType::SHA1;
+        #[cfg(all(not(feature = "hash-xxh"), not(feature = "hash-sha1"), feature = "hash-sha2"))]
+        const FASTEST: GeneralHashType = GeneralHashType::SHA256;
+        #[cfg(not(any(feature = "hash-xxh", feature = "hash-sha1", feature = "hash-sha2")))]
+        const FASTEST: GeneralHashType = GeneralHashType::NULL;
+
+        FASTEST
+    }
 }
 
 impl FromStr for GeneralHashType {
@@ -180,6 +211,143 @@ impl Display for GeneralHashType {
     }
 }
 
+/// `DirHashMode` describes how the hash of a directory is computed from its children.
+///
+/// * `ContentOnly` - Only the content hashes of the children are hashed. Renaming a child
+///   does not change the directory hash, so directories with equal content but differently
+///   named children are detected as duplicates.
+/// * `ContentAndNames` - The names of the children are hashed together with their content hashes.
+///   Two directories only have the same hash if their structure including all names is equal.
+///
+/// # Examples
+/// ```
+/// use std::str::FromStr;
+/// use backup_deduplicator::hash::DirHashMode;
+///
+/// let mode = DirHashMode::from_str("content-and-names").unwrap();
+/// assert_eq!(mode, DirHashMode::ContentAndNames);
+/// assert_eq!(mode.to_string(), "content-and-names");
+/// assert_eq!(DirHashMode::default(), DirHashMode::ContentOnly);
+/// ```
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum DirHashMode {
+    #[default]
+    ContentOnly,
+    ContentAndNames,
+}
+
+impl DirHashMode {
+    /// Returns if this is the default mode [DirHashMode::ContentOnly].
+    ///
+    /// # Returns
+    /// True if this is [DirHashMode::ContentOnly], false otherwise.
+    pub fn is_content_only(&self) -> bool {
+        *self == DirHashMode::ContentOnly
+    }
+}
+
+impl FromStr for DirHashMode {
+    /// Error type for parsing a `DirHashMode` from a string.
+    type Err = &'static str;
+
+    /// Parses a string into a `DirHashMode`.
+    ///
+    /// # Arguments
+    /// * `s` - The string to parse, either `content-only` or `content-and-names`.
+    ///
+    /// # Returns
+    /// The `DirHashMode` that corresponds to the string or an error.
+    ///
+    /// # Errors
+    /// Returns an error if the string does not correspond to a `DirHashMode`.
+    /// Returns the available modes in the error message.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "content-only" => Ok(DirHashMode::ContentOnly),
+            "content-and-names" => Ok(DirHashMode::ContentAndNames),
+            _ => Err("content-only, content-and-names"),
+        }
+    }
+}
+
+impl Display for DirHashMode {
+    /// Converts a `DirHashMode` into a string.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DirHashMode::ContentOnly => write!(f, "content-only"),
+            DirHashMode::ContentAndNames => write!(f, "content-and-names"),
+        }
+    }
+}
+
+/// `FileIdentity` describes what makes two files equal, i.e. what the hash of a file is computed from.
+///
+/// * `Content` - Only the content is hashed. Files with equal content are duplicates, regardless of their names.
+/// * `ContentAndName` - The file name is mixed into the content hash, see [GeneralHash::hash_with_name].
+///   Files with equal content are only duplicates if their names are equal as well.
+///   Their hashes can not be compared with the hashes of a `Content` hash tree.
+///
+/// # Examples
+/// ```
+/// use std::str::FromStr;
+/// use backup_deduplicator::hash::FileIdentity;
+///
+/// let identity = FileIdentity::from_str("content-and-name").unwrap();
+/// assert_eq!(identity, FileIdentity::ContentAndName);
+/// assert_eq!(identity.to_string(), "content-and-name");
+/// assert_eq!(FileIdentity::default(), FileIdentity::Content);
+/// ```
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum FileIdentity {
+    #[default]
+    Content,
+    ContentAndName,
+}
+
+impl FileIdentity {
+    /// Returns if this is the default identity [FileIdentity::Content].
+    ///
+    /// # Returns
+    /// True if this is [FileIdentity::Content], false otherwise.
+    pub fn is_content(&self) -> bool {
+        *self == FileIdentity::Content
+    }
+}
+
+impl FromStr for FileIdentity {
+    /// Error type for parsing a `FileIdentity` from a string.
+    type Err = &'static str;
+
+    /// Parses a string into a `FileIdentity`.
+    ///
+    /// # Arguments
+    /// * `s` - The string to parse, either `content` or `content-and-name`.
+    ///
+    /// # Returns
+    /// The `FileIdentity` that corresponds to the string or an error.
+    ///
+    /// # Errors
+    /// Returns an error if the string does not correspond to a `FileIdentity`.
+    /// Returns the available identities in the error message.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "content" => Ok(FileIdentity::Content),
+            "content-and-name" => Ok(FileIdentity::ContentAndName),
+            _ => Err("content, content-and-name"),
+        }
+    }
+}
+
+impl Display for FileIdentity {
+    /// Converts a `FileIdentity` into a string.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileIdentity::Content => write!(f, "content"),
+            FileIdentity::ContentAndName => write!(f, "content-and-name"),
+        }
+    }
+}
+
 /// `GeneralHash` is an enum that represents a hash value.
 ///
 /// The hash value is stored as a byte array of a fixed size.
@@ -527,6 +695,33 @@ impl GeneralHash {
         Ok(content_size)
     }
 
+    /// Computes the hash value of data in memory, e.g. of a memory mapped file.
+    ///
+    /// # Arguments
+    /// * `data` - The data to hash.
+    ///
+    /// # Returns
+    /// The size of the data that was hashed.
+    ///
+    /// # Example
+    /// ```
+    /// use backup_deduplicator::hash::{GeneralHash, GeneralHashType};
+    ///
+    /// let mut streamed = GeneralHash::from_type(GeneralHashType::SHA256);
+    /// streamed.hash_file(&b"some content"[..]).unwrap();
+    ///
+    /// let mut in_memory = GeneralHash::from_type(GeneralHashType::SHA256);
+    /// assert_eq!(in_memory.hash_bytes(b"some content"), 12);
+    /// assert_eq!(in_memory, streamed);
+    /// ```
+    pub fn hash_bytes(&mut self, data: &[u8]) -> u64 {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        *self = hasher.finalize();
+
+        data.len() as u64
+    }
+
     /// Computes the hash value of file iterator/directory.
     ///
     /// # Arguments
@@ -552,6 +747,74 @@ impl GeneralHash {
         Ok(content_size)
     }
 
+    /// Computes the hash value of file iterator/directory, including the names of the children.
+    /// Each child contributes its file name (length prefixed) followed by its content hash.
+    ///
+    /// # Arguments
+    /// * `children` - The iterator of files to hash.
+    ///
+    /// # Returns
+    /// The count of files that were hashed.
+    ///
+    /// # Errors
+    /// Does not return an error. Might return an error in the future.
+    ///
+    /// # See also
+    /// * [DirHashMode::ContentAndNames]
+    pub fn hash_directory_with_names<'a>(&mut self, children: impl Iterator<Item = &'a BuildFile>) -> anyhow::Result<u64> {
+        let mut hasher = self.hasher();
+
+        let mut content_size = 0;
+
+        for child in children {
+            content_size += 1;
+            let name = child.get_path().path.last()
+                .and_then(|component| component.path.file_name())
+                .map(|name| name.as_encoded_bytes())
+                .unwrap_or_default();
+            hasher.update(&(name.len() as u64).to_le_bytes());
+            hasher.update(name);
+            hasher.update(child.get_content_hash().as_bytes());
+        }
+
+        *self = hasher.finalize();
+
+        Ok(content_size)
+    }
+
+    /// Mixes a file name into the hash value, for [FileIdentity::ContentAndName]. The new hash value
+    /// is computed from the name (length prefixed) followed by the current hash value.
+    ///
+    /// # Arguments
+    /// * `name` - The file name.
+    ///
+    /// # Example
+    /// ```
+    /// use std::ffi::OsStr;
+    /// use backup_deduplicator::hash::{GeneralHash, GeneralHashType};
+    ///
+    /// let mut content = GeneralHash::from_type(GeneralHashType::SHA256);
+    /// content.hash_bytes(b"some content");
+    ///
+    /// let mut a = content.clone();
+    /// a.hash_with_name(OsStr::new("a.txt"));
+    /// let mut b = content.clone();
+    /// b.hash_with_name(OsStr::new("b.txt"));
+    /// assert_ne!(a, b);
+    /// assert_ne!(a, content);
+    /// assert_eq!(a.hash_type(), GeneralHashType::SHA256);
+    /// ```
+    pub fn hash_with_name(&mut self, name: &OsStr) {
+        let mut hasher = self.hasher();
+
+        let name = name.as_encoded_bytes();
+        hasher.update(&(name.len() as u64).to_le_bytes());
+        hasher.update(name);
+        hasher.update(self.as_bytes());
+
+        *self = hasher.finalize();
+    }
+
     /// Computes the hash value of the specified path.
     ///
     /// # Arguments
@@ -629,6 +892,115 @@ pub trait GeneralHasher {
     fn finalize(self: Box<Self>) -> GeneralHash;
 }
 
+/// `HashingWriter` is a writer that hashes all data written through it while
+/// passing it on to an inner writer. This allows copying data and computing its
+/// hash in one pass.
+///
+/// # Examples
+/// ```
+/// use std::io::Write;
+/// use backup_deduplicator::hash::{GeneralHashType, HashingWriter};
+///
+/// #[cfg(feature = "hash-sha2")]
+/// {
+/// let mut writer = HashingWriter::new(Vec::new(), GeneralHashType::SHA256);
+/// std::io::copy(&mut b"Hello, world!".as_slice(), &mut writer).unwrap();
+///
+/// assert_eq!(writer.bytes_processed(), 13);
+///
+/// let (copy, hash) = writer.into_inner();
+/// assert_eq!(copy, b"Hello, world!");
+/// assert_eq!(hash.to_string(), "SHA256:315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3");
+/// }
+/// ```
+///
+/// # See also
+/// * [GeneralHash::hash_file] - hashing data from a reader.
+pub struct HashingWriter<W: std::io::Write> {
+    inner: W,
+    hasher: Box<dyn GeneralHasher>,
+    bytes_processed: u64,
+}
+
+impl<W: std::io::Write> HashingWriter<W> {
+    /// Creates a new `HashingWriter` writing to `inner`.
+    ///
+    /// # Arguments
+    /// * `inner` - The writer to pass the data on to.
+    /// * `hash_type` - The type of the hash function to use.
+    ///
+    /// # Returns
+    /// A new instance of a `HashingWriter`.
+    pub fn new(inner: W, hash_type: GeneralHashType) -> Self {
+        HashingWriter {
+            inner,
+            hasher: hash_type.hasher(),
+            bytes_processed: 0,
+        }
+    }
+
+    /// Returns the number of bytes written to the inner writer so far.
+    ///
+    /// # Returns
+    /// The number of bytes hashed.
+    pub fn bytes_processed(&self) -> u64 {
+        self.bytes_processed
+    }
+
+    /// Returns a reference to the inner writer.
+    ///
+    /// # Returns
+    /// The inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Finalizes the hash of all data written.
+    /// Consumes the `HashingWriter` instance.
+    ///
+    /// # Returns
+    /// The hash value.
+    pub fn hash(self) -> GeneralHash {
+        self.hasher.finalize()
+    }
+
+    /// Finalizes the hash of all data written and returns the inner writer.
+    /// Consumes the `HashingWriter` instance.
+    ///
+    /// # Returns
+    /// The inner writer and the hash value.
+    pub fn into_inner(self) -> (W, GeneralHash) {
+        (self.inner, self.hasher.finalize())
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for HashingWriter<W> {
+    /// Writes data to the inner writer, hashing the bytes that were accepted.
+    ///
+    /// # Arguments
+    /// * `buf` - The data to write.
+    ///
+    /// # Returns
+    /// The number of bytes written.
+    ///
+    /// # Errors
+    /// If the inner writer errors.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes_processed += written as u64;
+        Ok(written)
+    }
+
+    /// Flushes the inner writer.
+    ///
+    /// # Errors
+    /// If the inner writer errors.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[cfg(feature = "hash-sha1")]
 /// `GeneralHasher` implementation for the SHA1 crate
 mod sha1;
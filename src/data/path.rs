@@ -1,16 +1,108 @@
 use std::ffi::OsString;
 use std::fmt::Formatter;
-use std::path::PathBuf;
+use std::hash::{Hash, Hasher};
+use std::path::{Component, Path, PathBuf};
 use anyhow::{Result};
 use serde::{Deserialize, Serialize};
 
 /// The type of archive.
-#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
 pub enum ArchiveType {
     Tar,
     Zip,
 }
 
+impl ArchiveType {
+    /// The number of leading bytes [ArchiveType::from_bytes] needs to detect every archive type.
+    /// A tar header stores its magic at offset 257, zip files start with their magic.
+    pub const DETECTION_BYTES: usize = 262;
+
+    /// Detect the archive type from the leading bytes of a file.
+    ///
+    /// # Arguments
+    /// * `bytes` - The first bytes of the file. Zip archives are detected from 4 bytes,
+    ///   tar archives need [ArchiveType::DETECTION_BYTES] bytes.
+    ///
+    /// # Returns
+    /// The detected archive type. None if the bytes match no known archive type.
+    /// Compressed archives (e.g. `.tar.gz`) are not detected, see [CompressionType::from_bytes].
+    ///
+    /// # Example
+    /// ```
+    /// use backup_deduplicator::path::ArchiveType;
+    ///
+    /// assert_eq!(ArchiveType::from_bytes(b"PK\x03\x04rest of the file"), Some(ArchiveType::Zip));
+    ///
+    /// let mut tar_header = [0u8; 512];
+    /// tar_header[257..262].copy_from_slice(b"ustar");
+    /// assert_eq!(ArchiveType::from_bytes(&tar_header), Some(ArchiveType::Tar));
+    ///
+    /// assert_eq!(ArchiveType::from_bytes(b"plain text"), None);
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Option<ArchiveType> {
+        if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") || bytes.starts_with(b"PK\x07\x08") {
+            return Some(ArchiveType::Zip);
+        }
+
+        if bytes.len() >= Self::DETECTION_BYTES && &bytes[257..262] == b"ustar" {
+            return Some(ArchiveType::Tar);
+        }
+
+        None
+    }
+}
+
+/// The type of compression of a file.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
+pub enum CompressionType {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    Lz4,
+}
+
+impl CompressionType {
+    /// The number of leading bytes [CompressionType::from_bytes] needs to detect every compression type.
+    pub const DETECTION_BYTES: usize = 6;
+
+    /// Detect the compression type from the leading bytes of a file.
+    ///
+    /// # Arguments
+    /// * `bytes` - The first bytes of the file. Gzip needs 2 bytes, bzip2 3 bytes,
+    ///   zstd and lz4 (frame format) 4 bytes and xz [CompressionType::DETECTION_BYTES] bytes.
+    ///
+    /// # Returns
+    /// The detected compression type. None if the bytes match no known compression type.
+    ///
+    /// # Example
+    /// ```
+    /// use backup_deduplicator::path::CompressionType;
+    ///
+    /// assert_eq!(CompressionType::from_bytes(&[0x1f, 0x8b, 0x08]), Some(CompressionType::Gzip));
+    /// assert_eq!(CompressionType::from_bytes(b"BZh9"), Some(CompressionType::Bzip2));
+    /// assert_eq!(CompressionType::from_bytes(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]), Some(CompressionType::Xz));
+    /// assert_eq!(CompressionType::from_bytes(&[0x28, 0xb5, 0x2f, 0xfd]), Some(CompressionType::Zstd));
+    /// assert_eq!(CompressionType::from_bytes(&[0x04, 0x22, 0x4d, 0x18, 0x64]), Some(CompressionType::Lz4));
+    /// assert_eq!(CompressionType::from_bytes(&[0x1f]), None);
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Option<CompressionType> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(CompressionType::Gzip)
+        } else if bytes.starts_with(b"BZh") {
+            Some(CompressionType::Bzip2)
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(CompressionType::Xz)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(CompressionType::Zstd)
+        } else if bytes.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            Some(CompressionType::Lz4)
+        } else {
+            None
+        }
+    }
+}
+
 /// The target of a path.
 ///
 /// # Fields
@@ -61,7 +153,7 @@ pub struct PathComponent {
 /// let path = FilePath::from_realpath(PathBuf::from("test.txt"));
 ///
 /// ```
-#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FilePath {
     pub path: Vec<PathComponent>
 }
@@ -82,15 +174,31 @@ impl FilePath {
 
     /// Creates a new file path from a real path.
     ///
+    /// The path is normalized: `.` components and redundant separators are dropped.
+    /// `..` components are kept as they are, since resolving them lexically is wrong
+    /// if the preceding component is a symlink.
+    ///
     /// # Arguments
     /// * `path` - The real path.
     ///
     /// # Returns
     /// The file path.
+    ///
+    /// # Example
+    /// ```
+    /// use std::path::PathBuf;
+    /// use backup_deduplicator::path::FilePath;
+    ///
+    /// let path = FilePath::from_realpath(PathBuf::from("./a//./b/"));
+    ///
+    /// assert_eq!(path.path[0].path.as_os_str(), "a/b");
+    /// assert_eq!(FilePath::from_realpath(PathBuf::from("./")).path[0].path.as_os_str(), ".");
+    /// assert_eq!(FilePath::from_realpath(PathBuf::from("a/../b")).path[0].path.as_os_str(), "a/../b");
+    /// ```
     pub fn from_realpath(path: PathBuf) -> Self {
         FilePath {
             path: vec![PathComponent {
-                path,
+                path: normalize_path(path),
                 target: PathTarget::File
             }]
         }
@@ -230,23 +338,204 @@ impl FilePath {
             }
         }
     }
+
+    /// Checks whether the file path is located below (or at) another file path.
+    ///
+    /// Path components match whole names: `a/b` is below `a`, but not below `a/bc`.
+    /// All path components of the prefix except the last one must be equal to the ones of this path,
+    /// the last one may end at a directory of the corresponding component or match it completely.
+    /// Therefore, a path inside an archive is below the archive and below every directory
+    /// containing the archive, but never below a partial path inside another archive.
+    /// `.` components and redundant separators are ignored, see [FilePath::eq].
+    ///
+    /// # Arguments
+    /// * `prefix` - The file path to check against.
+    ///
+    /// # Returns
+    /// Whether the file path starts with the prefix.
+    ///
+    /// # Example
+    /// ```
+    /// use std::path::PathBuf;
+    /// use backup_deduplicator::path::{FilePath, PathComponent, PathTarget};
+    ///
+    /// let path = |components: &[&str]| FilePath::from_pathcomponents(components.iter().map(|component| PathComponent {
+    ///     path: PathBuf::from(component),
+    ///     target: PathTarget::File,
+    /// }).collect());
+    ///
+    /// assert!(path(&["a/b/c"]).starts_with(&path(&["a/b"])));
+    /// assert!(path(&["a/b/c"]).starts_with(&path(&["./a//b/"])));
+    /// assert!(path(&["a/b/c"]).starts_with(&path(&["a/b/c"])));
+    /// assert!(!path(&["a/bc"]).starts_with(&path(&["a/b"])));
+    /// assert!(!path(&["a/b"]).starts_with(&path(&["a/b/c"])));
+    ///
+    /// // a file inside the archive a/archive.tar
+    /// let inner = path(&["a/archive.tar", "dir/file"]);
+    /// assert!(inner.starts_with(&path(&["a"])));
+    /// assert!(inner.starts_with(&path(&["a/archive.tar"])));
+    /// assert!(inner.starts_with(&path(&["a/archive.tar", "dir"])));
+    /// assert!(!inner.starts_with(&path(&["a", "dir"])));
+    /// assert!(!inner.starts_with(&path(&["a/archive.tar", "dir/file", "more"])));
+    /// ```
+    pub fn starts_with(&self, prefix: &FilePath) -> bool {
+        self.strip_prefix(prefix).is_some()
+    }
+
+    /// Removes a prefix from the file path, see [FilePath::starts_with].
+    ///
+    /// The first path component of the result is the rest of the component that the last
+    /// component of the prefix ended in. It is empty if the prefix matched it completely.
+    /// All further path components (e.g. the paths inside an archive) are kept as they are.
+    /// An empty prefix is removed from every file path.
+    ///
+    /// # Arguments
+    /// * `prefix` - The file path to remove.
+    ///
+    /// # Returns
+    /// The file path relative to the prefix. None if the file path does not start with the prefix.
+    ///
+    /// # Example
+    /// ```
+    /// use std::path::PathBuf;
+    /// use backup_deduplicator::path::{FilePath, PathComponent, PathTarget};
+    ///
+    /// let path = |components: &[&str]| FilePath::from_pathcomponents(components.iter().map(|component| PathComponent {
+    ///     path: PathBuf::from(component),
+    ///     target: PathTarget::File,
+    /// }).collect());
+    ///
+    /// assert_eq!(path(&["a/b/c"]).strip_prefix(&path(&["a"])), Some(path(&["b/c"])));
+    /// assert_eq!(path(&["a/b/c"]).strip_prefix(&path(&["a/b/c"])), Some(path(&[""])));
+    /// assert_eq!(path(&["a/bc"]).strip_prefix(&path(&["a/b"])), None);
+    ///
+    /// // a file inside the archive a/archive.tar
+    /// let inner = path(&["a/archive.tar", "dir/file"]);
+    /// assert_eq!(inner.strip_prefix(&path(&["a"])), Some(path(&["archive.tar", "dir/file"])));
+    /// assert_eq!(inner.strip_prefix(&path(&["a/archive.tar"])), Some(path(&["", "dir/file"])));
+    /// assert_eq!(inner.strip_prefix(&path(&["a/archive.tar", "dir"])), Some(path(&["file"])));
+    /// assert_eq!(inner.strip_prefix(&path(&["a", "dir"])), None);
+    /// ```
+    pub fn strip_prefix(&self, prefix: &FilePath) -> Option<FilePath> {
+        let Some((last, leading)) = prefix.path.split_last() else {
+            return Some(self.clone());
+        };
+        if leading.len() >= self.path.len() {
+            return None;
+        }
+
+        let leading_equal = leading.iter().zip(self.path.iter()).all(|(a, b)| {
+            a.target == b.target && normalized_components(&a.path).eq(normalized_components(&b.path))
+        });
+        if !leading_equal {
+            return None;
+        }
+
+        let component = &self.path[leading.len()];
+        let mut rest = normalized_components(&component.path);
+        for expected in normalized_components(&last.path) {
+            if rest.next() != Some(expected) {
+                return None;
+            }
+        }
+
+        let mut result = vec![PathComponent {
+            path: rest.collect(),
+            target: component.target.clone(),
+        }];
+        result.extend_from_slice(&self.path[leading.len() + 1..]);
+
+        Some(FilePath::from_pathcomponents(result))
+    }
+}
+
+/// Drops `.` components and redundant separators from a path. A path consisting only
+/// of `.` components is normalized to `.`.
+///
+/// # Arguments
+/// * `path` - The path to normalize.
+///
+/// # Returns
+/// The normalized path.
+fn normalize_path(path: PathBuf) -> PathBuf {
+    let normalized: PathBuf = normalized_components(&path).collect();
+    
+    if normalized.as_os_str().is_empty() && !path.as_os_str().is_empty() {
+        return PathBuf::from(".");
+    }
+    
+    normalized
+}
+
+/// Iterates over the components of a path, skipping `.` components.
+/// Redundant and trailing separators are already skipped by [Path::components].
+///
+/// # Arguments
+/// * `path` - The path.
+///
+/// # Returns
+/// The significant components of the path.
+fn normalized_components(path: &Path) -> impl Iterator<Item = Component<'_>> {
+    path.components().filter(|component| *component != Component::CurDir)
 }
 
 impl PartialEq for FilePath {
     /// Compares two file paths.
     /// 
+    /// `.` components, redundant and trailing separators are ignored,
+    /// `..` components are compared as they are.
+    /// 
     /// # Arguments
     /// * `other` - The other file path.
     /// 
     /// # Returns
     /// Whether the file paths are equal.
+    /// 
+    /// # Example
+    /// ```
+    /// use std::collections::HashSet;
+    /// use std::path::PathBuf;
+    /// use backup_deduplicator::path::{FilePath, PathComponent, PathTarget};
+    ///
+    /// let unnormalized = |path: &str| FilePath::from_pathcomponents(vec![PathComponent {
+    ///     path: PathBuf::from(path),
+    ///     target: PathTarget::File,
+    /// }]);
+    ///
+    /// assert_eq!(unnormalized("./a/b"), unnormalized("a/b"));
+    /// assert_eq!(unnormalized("a//b"), unnormalized("a/b"));
+    /// assert_eq!(unnormalized("a/b/"), unnormalized("./a/./b"));
+    /// assert_ne!(unnormalized("a/../b"), unnormalized("b"));
+    ///
+    /// let set: HashSet<FilePath> = ["./a/b", "a/b", "a//b"].into_iter().map(unnormalized).collect();
+    /// assert_eq!(set.len(), 1);
+    /// ```
     fn eq(&self, other: &Self) -> bool {
-        self.path.len() == other.path.len() && self.path.iter().zip(other.path.iter()).all(|(a, b)| a == b)
+        self.path.len() == other.path.len() && self.path.iter().zip(other.path.iter()).all(|(a, b)| {
+            a.target == b.target && normalized_components(&a.path).eq(normalized_components(&b.path))
+        })
     }
 }
 
 impl Eq for FilePath {}
 
+impl Hash for FilePath {
+    /// Hashes the file path consistently with [FilePath::eq], ignoring `.` components
+    /// and redundant separators.
+    ///
+    /// # Arguments
+    /// * `state` - The hasher state.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path.len().hash(state);
+        for component in &self.path {
+            component.target.hash(state);
+            for path_component in normalized_components(&component.path) {
+                path_component.hash(state);
+            }
+        }
+    }
+}
+
 impl std::fmt::Display for FilePath {
     /// Formats the file path to a string.
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -0,0 +1,107 @@
+use std::io;
+use std::path::Path;
+use crate::hash::{GeneralHash, GeneralHashType};
+
+/// Hash the extended attributes of a file or directory.
+///
+/// The attributes are sorted by name, so the hash does not depend on the order the
+/// file system lists them in. Each attribute contributes its name and value.
+///
+/// # Arguments
+/// * `path` - The path of the file or directory.
+/// * `hash_type` - The hash algorithm to use.
+/// * `follow_symlinks` - Whether to read the attributes of the symlink target instead of the symlink.
+///
+/// # Returns
+/// The hash of the attributes (also if there are none), or None if the platform does not
+/// support reading extended attributes (only Linux is supported).
+///
+/// # Errors
+/// If the attributes cannot be listed or read, e.g. on file systems without support for
+/// extended attributes.
+#[cfg(target_os = "linux")]
+pub fn hash_xattrs(path: &Path, hash_type: GeneralHashType, follow_symlinks: bool) -> io::Result<Option<GeneralHash>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let names = read_attribute_buffer(|buffer, size| unsafe {
+        // SAFETY: the buffer is valid for `size` bytes, a size of 0 only queries the length
+        match follow_symlinks {
+            true => libc::listxattr(c_path.as_ptr(), buffer as *mut libc::c_char, size),
+            false => libc::llistxattr(c_path.as_ptr(), buffer as *mut libc::c_char, size),
+        }
+    })?;
+
+    let mut names: Vec<&[u8]> = names.split(|byte| *byte == 0).filter(|name| !name.is_empty()).collect();
+    names.sort();
+
+    let mut hasher = hash_type.hasher();
+
+    for name in names {
+        let c_name = CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let value = read_attribute_buffer(|buffer, size| unsafe {
+            // SAFETY: the buffer is valid for `size` bytes, a size of 0 only queries the length
+            match follow_symlinks {
+                true => libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), buffer as *mut libc::c_void, size),
+                false => libc::lgetxattr(c_path.as_ptr(), c_name.as_ptr(), buffer as *mut libc::c_void, size),
+            }
+        })?;
+
+        hasher.update(name);
+        hasher.update(&[0]);
+        hasher.update(&(value.len() as u64).to_le_bytes());
+        hasher.update(&value);
+    }
+
+    Ok(Some(hasher.finalize()))
+}
+
+/// Hash the extended attributes of a file or directory.
+///
+/// # Returns
+/// None, reading extended attributes is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn hash_xattrs(_path: &Path, _hash_type: GeneralHashType, _follow_symlinks: bool) -> io::Result<Option<GeneralHash>> {
+    Ok(None)
+}
+
+/// Read a variable sized attribute buffer (list of names or a value) using the
+/// two-step pattern of the xattr syscalls: query the size, then read.
+/// Retries if the attributes grew in between.
+///
+/// # Arguments
+/// * `read` - Calls the syscall with a buffer and its size.
+///
+/// # Returns
+/// The read bytes.
+///
+/// # Errors
+/// If the syscall fails.
+#[cfg(target_os = "linux")]
+fn read_attribute_buffer<F: Fn(*mut u8, libc::size_t) -> libc::ssize_t>(read: F) -> io::Result<Vec<u8>> {
+    loop {
+        let size = read(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buffer = vec![0u8; size as usize];
+        if buffer.is_empty() {
+            return Ok(buffer);
+        }
+
+        let read_size = read(buffer.as_mut_ptr(), buffer.len());
+        if read_size < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(err);
+        }
+
+        buffer.truncate(read_size as usize);
+        return Ok(buffer);
+    }
+}
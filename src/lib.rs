@@ -11,12 +11,17 @@ pub mod stages {
     pub mod build;
     pub mod analyze;
     pub mod clean;
+    pub mod import;
+    pub mod refresh;
+    pub mod show;
 }
 
 mod data {
     pub mod path;
     pub mod hash;
     pub mod fileid;
+    pub mod xattr;
+    pub mod content_type;
 }
 
 pub use data::*;
@@ -1,13 +1,28 @@
 use std::{env};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 use clap::{arg, Parser, Subcommand};
 use log::{debug, info, LevelFilter, trace};
-use backup_deduplicator::hash::GeneralHashType;
-use backup_deduplicator::stages::analyze::cmd::AnalysisSettings;
-use backup_deduplicator::stages::{analyze, build, clean};
+use backup_deduplicator::hash::{DirHashMode, FileIdentity, GeneralHash, GeneralHashType};
+use backup_deduplicator::stages::analyze::cmd::{AnalysisSettings, CollisionPolicy};
+use backup_deduplicator::stages::analyze::output::{AnalysisFormat, AnalysisOutput};
+use backup_deduplicator::stages::{analyze, build, clean, import, refresh, show};
 use backup_deduplicator::stages::build::cmd::BuildSettings;
+use backup_deduplicator::stages::build::cmd::dry_run::DryRunSettings;
+use backup_deduplicator::stages::build::cmd::output_order::OutputOrder;
+use backup_deduplicator::stages::build::cmd::snapshot::SnapshotKind;
+use backup_deduplicator::stages::build::cmd::watch::ChangeWatcher;
 use backup_deduplicator::stages::clean::cmd::CleanSettings;
+use backup_deduplicator::stages::import::cmd::ImportSettings;
+use backup_deduplicator::stages::refresh::cmd::RefreshSettings;
+use backup_deduplicator::stages::show::cmd::ShowSettings;
 use backup_deduplicator::utils;
+use backup_deduplicator::utils::ReplacementFile;
 
 /// A simple command line tool to deduplicate backups.
 #[derive(Parser, Debug)]
@@ -24,18 +39,32 @@ struct Arguments {
     /// Debug, if set, the tool will print debug information (including debug implies setting verbose). Setting the RUST_LOG env var overrides this flag.
     #[arg(short, long, default_value = "false")]
     debug: bool,
+    /// Write at most this many trace lines per second (with RUST_LOG=trace), e.g. to keep the per-file lines of a
+    /// directory with millions of files readable. The suppressed lines are counted. Other levels are unaffected
+    #[arg(long="trace-rate-limit", value_name = "LINES", value_parser = clap::value_parser!(u64).range(1..))]
+    trace_rate_limit: Option<u64>,
     /// The subcommand to run
     #[command(subcommand)]
     command: Command,
 }
 
+// parsed once at startup, the size of the build arguments does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 enum Command {
     /// Build a hash-tree for the given directory
     Build {
         /// The directory to analyze
-        #[arg()]
-        directory: String,
+        #[arg(required_unless_present = "files_from", conflicts_with = "files_from")]
+        directory: Option<String>,
+        /// Hash exactly the files and directories listed in the given file (`-` for stdin) instead of a directory,
+        /// e.g. the output of `find`. One path per line, or NUL separated with -0. Listed paths that do not exist
+        /// get error entries (removed by the clean step unless --noclean is set).
+        #[arg(long="files-from", value_name = "FILE", conflicts_with_all = ["one_filesystem", "dry_run"])]
+        files_from: Option<String>,
+        /// The paths of --files-from are separated by NUL bytes instead of newlines (like `find -print0`)
+        #[arg(short = '0', long = "null", default_value = "false", requires = "files_from")]
+        null_separated: bool,
         /* /// Traverse into archives
         #[arg(short, long)]
         archives: bool, */
@@ -55,9 +84,149 @@ enum Command {
         /// Force overwrite, if set, the tool will overwrite the output file if it exists. If not set, the tool will continue an existing analysis
         #[arg(long="overwrite", default_value = "false")]
         recreate_output: bool,
-        /// Hash algorithm to use
+        /// Hash algorithm to use. `auto` picks the fastest available algorithm (XXH64 if enabled),
+        /// which is enough to find duplicates but not suitable against deliberately crafted collisions.
         #[arg(long="hash", default_value = "sha256")]
         hash_type: String,
+        /// Hash algorithm to use for files with a specific extension, e.g. `iso=sha512`. Can be given multiple times.
+        /// Entries hashed with different algorithms can not be compared to each other by the analyze command.
+        #[arg(long="hash-algorithm-per-extension", value_name = "EXT=HASH")]
+        hash_type_by_extension: Vec<String>,
+        /// How directory hashes are computed: `content-only` ignores the names of the children (renaming a child
+        /// keeps the directory hash), `content-and-names` also hashes the names of the children.
+        #[arg(long="dir-hash-mode", default_value = "content-only")]
+        dir_hash_mode: String,
+        /// What makes files equal: `content` hashes only the content, `content-and-name` mixes the file name into the
+        /// hash, so files with equal content but different names are no duplicates. Such hash trees can not be
+        /// compared with `content` hash trees.
+        #[arg(long, default_value = "content")]
+        identity: String,
+        /// Count the files discovered while listing directories to log the progress in percent and an estimated remaining time.
+        /// Requires reading the metadata of every directory entry. The progress is logged with --verbose.
+        #[arg(long, default_value = "false")]
+        eta: bool,
+        /// Show a progress bar with the processed bytes and the current file if stderr is a terminal, instead of
+        /// logging the progress. Shows the percentage and remaining time together with --eta.
+        #[arg(long, default_value = "false")]
+        progress: bool,
+        /// Interval in milliseconds in which the progress is logged with --verbose, if no progress bar is shown
+        #[arg(long="progress-interval", value_name = "MS", default_value = "10000", value_parser = clap::value_parser!(u64).range(1..))]
+        progress_interval: u64,
+        /// Write a checkpoint marker and sync the output file to disk every given number of seconds. On a power loss
+        /// at most the entries written since the last checkpoint are lost. Lower values cost more throughput.
+        /// Continuing the build reuses every complete entry of the output file, also those written after the last
+        /// checkpoint, and truncates a last line that was only partially written.
+        #[arg(long="checkpoint-interval", value_name = "SECS")]
+        checkpoint_interval: Option<u64>,
+        /// After the build, re-read the output file and check that the header and all entries can be loaded.
+        /// Fails if a line can not be parsed or entries are missing.
+        #[arg(long="verify-after", default_value = "false")]
+        verify_after: bool,
+        /// Stay on the filesystem of the target directory, do not descend into directories of other mounted filesystems (like find -xdev)
+        #[arg(long="one-filesystem", default_value = "false")]
+        one_filesystem: bool,
+        /// Do not descend into directories on the same device (filesystem) as the given path, e.g. a mount point of a
+        /// huge network share. Can be given multiple times.
+        #[arg(long="exclude-device", value_name = "PATH")]
+        exclude_device: Vec<String>,
+        /// Record the size allocated on disk for every file (Unix only, ignored elsewhere). Differs from the
+        /// file size for sparse files like VM images; see `analyze --physical-size`.
+        #[arg(long="capture-physical-size", default_value = "false")]
+        capture_physical_size: bool,
+        /// Maximum number of files and directories the workers keep open at the same time, independent of --threads.
+        /// Defaults to half of the soft open file limit (RLIMIT_NOFILE) on Unix, unbounded elsewhere.
+        #[arg(long="max-open-files", value_name = "N")]
+        max_open_files: Option<usize>,
+        /// Memory hint in MiB for the entries of the previous build kept while continuing a hash tree. If the hash tree
+        /// file is larger, only the offsets of the entries are kept in memory and the entries are read from the file
+        /// when needed. Slower, but uses a fraction of the memory for huge trees.
+        #[arg(long="max-memory", value_name = "MIB")]
+        max_memory: Option<u64>,
+        /// Record a hash of the extended attributes of every file and directory, to detect attribute changes.
+        /// Only supported on Linux and on file systems with extended attributes, ignored elsewhere.
+        #[arg(long="capture-xattrs", default_value = "false")]
+        capture_xattrs: bool,
+        /// Record the inode and device of every file, so that hardlinks of the same file can be told apart
+        /// from independent copies; see `analyze --group-hardlinks`.
+        #[arg(long="capture-file-ids", default_value = "false")]
+        capture_file_ids: bool,
+        /// Record the creation (birth) time of every file and directory. Left out where the platform or
+        /// file system does not record it.
+        #[arg(long="capture-btime", default_value = "false")]
+        capture_btime: bool,
+        /// List all files first and only hash files whose size is shared by another file, the others can not have
+        /// duplicates and get a placeholder hash. Saves reading files of unique size, but the hash tree is only
+        /// valid for finding duplicates within itself (not for verification or --unique-to) and can not be continued.
+        #[arg(long="dedup-optimized", default_value = "false", conflicts_with = "watch")]
+        dedup_optimized: bool,
+        /// Detect the type of every file (e.g. `image/jpeg`) from its first bytes, so that the analysis can report
+        /// the duplicated bytes per type. The bytes are read while hashing anyway, the detection costs no extra IO.
+        #[arg(long="detect-content-type", default_value = "false")]
+        detect_content_type: bool,
+        /// When continuing, treat files whose modification time differs from the hash tree by at most this many
+        /// seconds as unchanged. Avoids rehashing files copied between systems with skewed clocks.
+        #[arg(long="mtime-tolerance", value_name = "SECS", default_value = "0")]
+        mtime_tolerance: u64,
+        /// Only hash files modified at or after this time, given as seconds since the Unix epoch or as ISO 8601 date
+        /// (`2024-05-01`, `2024-05-01T12:30:00Z`, UTC without offset). Older files are recorded as `Other` entries
+        /// without a hash, unless the continued hash tree has them unchanged. Directories are always traversed
+        #[arg(long, value_name = "TIME")]
+        since: Option<String>,
+        /// Write a JSON summary of the build to this file: the processed files, directories, symlinks and others,
+        /// the hashed bytes, the entries reused from the hash tree, the errors, the duration and the hash type.
+        #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+        summary: Option<String>,
+        /// Memory map files of at least this size in bytes for hashing instead of reading them through a buffer,
+        /// which can be faster for large files on fast storage. Files modified within the last minute are always
+        /// read, since truncating a mapped file terminates the process. Requires the mmap feature.
+        #[arg(long="mmap-threshold", value_name = "BYTES")]
+        mmap_threshold: Option<u64>,
+        /// Record symlinks to regular files outside of the target directory as files with the hash of their
+        /// target's content, as if the target was copied into the tree. Symlinks within the target directory,
+        /// to directories or to missing files are recorded as symlinks. Beware that a single link can pull
+        /// in a huge external file (or a device) that is read completely.
+        #[arg(long="follow-symlinks-external-as-copy", default_value = "false", conflicts_with = "follow_symlinks")]
+        follow_symlinks_external_as_copy: bool,
+        /// After the build, additionally split the hash tree into this many shard files by hash prefix
+        /// (`hash_tree.0.bdd`, `hash_tree.1.bdd`, ...), e.g. to process them on several machines.
+        /// The unsplit output file is kept and continued by later builds, the shards are rewritten after every build.
+        #[arg(long, value_name = "N", conflicts_with = "dry_run", value_parser = clap::value_parser!(u32).range(2..=65536))]
+        shards: Option<u32>,
+        /// Estimate the size of the hash tree file by listing the directory before the build and reserve the disk
+        /// space for it (Linux only, ignored elsewhere), which reduces fragmentation of large hash tree files.
+        #[arg(long, default_value = "false", conflicts_with = "dry_run")]
+        preallocate: bool,
+        /// Pin every worker thread to its own CPU core, spread over the available cores. Can improve the throughput
+        /// on machines with many cores (e.g. NUMA). Requires the pin-threads feature, ignored where unsupported.
+        #[arg(long="pin-threads", default_value = "false")]
+        pin_threads: bool,
+        /// Take a snapshot of the directory with this tool (btrfs, zfs) and read the files from it, so that files
+        /// changing during the build do not make the hash tree inconsistent. The hash tree keeps the original paths.
+        /// Requires the tool and the permissions to take snapshots, falls back to reading the directory directly.
+        #[arg(long, value_name = "TOOL", conflicts_with_all = ["dry_run", "files_from"])]
+        snapshot: Option<String>,
+        /// The order the entries are written in: `arrival` as they are hashed, `sorted` by path or `discovery`
+        /// in directory traversal order (a directory before its children). `sorted` and `discovery` keep all
+        /// entries in memory until the build ends
+        #[arg(long="output-order", value_name = "ORDER", default_value = "arrival")]
+        output_order: String,
+        /// Write every file and directory that could not be read to this file, as JSON array of
+        /// `{path, occurred_at, reason}`. Such files are recorded as `Other` entries in the hash tree.
+        #[arg(long="report-unreadable", value_name = "FILE", conflicts_with = "dry_run")]
+        report_unreadable: Option<String>,
+        /// Only list the target directory and print how many files and bytes would be hashed, without hashing
+        /// or writing the output file.
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+        /// Print every file that would be hashed (requires --dry-run)
+        #[arg(long, default_value = "false", requires = "dry_run")]
+        list_files: bool,
+        /// Keep running after the build and update the hash tree when files change, at most once per given number of
+        /// seconds. Changes are reported by the file system (polled every interval if the directory can not be watched).
+        /// Each update continues the hash tree: only new or modified files are hashed, deleted files are removed by the
+        /// clean step. Stop with Ctrl-C.
+        #[arg(long, value_name = "SECS", conflicts_with = "dry_run", value_parser = clap::value_parser!(u64).range(1..))]
+        watch: Option<u64>,
         /// Disable database clean after run, if set the tool will not clean the database after the creation
         #[arg(long="noclean", default_value = "false")]
         no_clean: bool,
@@ -82,18 +251,140 @@ enum Command {
         /// Follow symlinks, if set, the tool will not follow symlinks
         #[arg(long)]
         follow_symlinks: bool,
+        /// Replace every path of the output by an opaque token derived from a secret salt, keeping hashes and sizes,
+        /// e.g. to share the hash tree without revealing file names. The salt is read from the given file, or
+        /// generated and written to it if the file does not exist. Keep it private and reuse it for comparable trees.
+        #[arg(long="anonymize-paths", value_name = "SALT_FILE")]
+        anonymize_paths: Option<String>,
+        /// Replace the leading directories FROM of every path by TO, e.g. `/mnt/old=/mnt/new` after the backup moved.
+        /// FROM is matched as written in the hash tree. Can be given multiple times, the first matching prefix is used.
+        #[arg(long="rewrite-prefix", value_name = "FROM=TO")]
+        rewrite_prefix: Vec<String>,
+        /// Validate every entry and fail on the first malformed one (e.g. a file with children or a foreign hash type)
+        /// instead of keeping it. Useful for hash trees produced by other tools or possibly corrupt files.
+        #[arg(long, default_value = "false")]
+        strict: bool,
+        /// Log the number of checked and removed entries every few seconds. Checking the entries can take long
+        /// on network file systems, since the metadata of every file is read.
+        #[arg(long, default_value = "false")]
+        progress: bool,
+        /// Only remove entries that repeat another entry byte for byte (same path, hash, size, modification time,
+        /// ...), e.g. the unchanged entries appended again by continued builds, and report how many were removed.
+        /// Unlike the default clean, older entries of the same path and entries of deleted files are kept.
+        #[arg(long="dedupe-identical-lines", default_value = "false")]
+        dedupe_identical_lines: bool,
+    },
+    /// Update a hash-tree file in place without listing the directories again. Files whose size or modification
+    /// time changed are hashed again, deleted files are removed and the directory hashes above them are updated.
+    /// Files added since the build are not found, run a build to add them.
+    Refresh {
+        /// The hash tree file to refresh
+        #[arg(short, long, default_value = "hash_tree.bdd")]
+        input: String,
+        /// Working directory, if set, the tool will use the current working directory as the base for relative paths.
+        #[arg(short, long)]
+        working_directory: Option<String>,
+        /// Treat files whose modification time differs from the hash tree by at most this many seconds as unchanged
+        #[arg(long="mtime-tolerance", value_name = "SECS", default_value = "0")]
+        mtime_tolerance: u64,
     },
     /// Find duplicates and output them as analysis result
     Analyze {
         /// The hash tree file to analyze
         #[arg(short, long, default_value = "hash_tree.bdd")]
         input: String,
-        /// Output file for the analysis result
+        /// Output file for the analysis result. Can be given multiple times to write several formats at once
         #[arg(short, long, default_value = "analysis.json")]
-        output: String,
-        /// Overwrite the output file
+        output: Vec<String>,
+        /// Format of the output file at the same position (json, csv, tsv). Outputs without a format are written as json
+        #[arg(long, value_name = "FORMAT")]
+        format: Vec<String>,
+        /// Overwrite the output files
         #[arg(long="overwrite", default_value = "false")]
         overwrite: bool,
+        /// Instead of finding duplicates, report the files whose content does not appear anywhere in this hash tree file
+        #[arg(long="unique-to")]
+        unique_to: Option<String>,
+        /// Files smaller than this size in bytes are not reported as unique
+        #[arg(long="min-size", default_value = "0", requires = "unique_to")]
+        min_size: u64,
+        /// Write indented JSON for inspecting the result by hand. Each entry then spans multiple lines.
+        #[arg(long, default_value = "false")]
+        pretty: bool,
+        /// Compute the duplicated bytes from the size allocated on disk instead of the file size,
+        /// for files built with --capture-physical-size. Sparse files then count with their actual disk usage.
+        #[arg(long="physical-size", default_value = "false")]
+        physical_size: bool,
+        /// Ignore entries whose hash is listed in this file (one hash per line, e.g. `SHA256:0123...` or plain hex),
+        /// like known operating system or library files. The hashes must use the hash type of the hash tree.
+        #[arg(long="exclude-hashes", value_name = "FILE")]
+        exclude_hashes: Option<String>,
+        /// How to handle files with equal hashes but different sizes (a hash collision or a broken hash tree):
+        /// `warn` logs them and leaves them out of the duplicate sets, `error` aborts, `ignore` groups them by size.
+        #[arg(long="on-collision", default_value = "warn")]
+        on_collision: String,
+        /// Validate every entry and fail on the first malformed one (e.g. a file with children or a foreign hash type)
+        /// instead of loading it. Useful for hash trees produced by other tools or possibly corrupt files.
+        #[arg(long, default_value = "false")]
+        strict: bool,
+        /// Group the files of every duplicate set by the file they link to, to tell hardlinks (which take no
+        /// extra space) apart from true duplicates. Requires a hash tree built with --capture-file-ids.
+        #[arg(long="group-hardlinks", default_value = "false")]
+        group_hardlinks: bool,
+        /// Allow --unique-to to compare hash trees built with different hash types. Files are then only compared
+        /// by path, size and modification time, so equal content can not be confirmed.
+        #[arg(long="allow-mixed-hash", default_value = "false", requires = "unique_to")]
+        allow_mixed_hash: bool,
+        /// Read the hash tree from this many shard files written by `build --shards`. The input is the path of
+        /// the unsplit hash tree file, which does not need to exist.
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        shards: Option<u32>,
+        /// Also group entries without a content hash (the `NULL` hash, e.g. unreadable files, sockets and devices)
+        /// as duplicates. By default they are skipped, since they are not duplicates of each other.
+        #[arg(long="include-null-hashes", default_value = "false")]
+        include_null_hashes: bool,
+        /// Write only the N duplicate sets with the most reclaimable bytes, largest first, e.g. to keep the output
+        /// of a tree with millions of tiny duplicates manageable. The omitted sets are counted in the summary.
+        #[arg(long="max-groups", value_name = "N", conflicts_with = "unique_to", value_parser = clap::value_parser!(u64).range(1..))]
+        max_groups: Option<u64>,
+        /// Write a JSON summary of the analysis to this file: the found and written duplicate sets and the duplicated
+        /// bytes, or the number of unique files with --unique-to.
+        #[arg(long, value_name = "FILE")]
+        summary: Option<String>,
+    },
+    /// Print a hash-tree file as an indented tree, showing sizes and hashes
+    Show {
+        /// The hash tree file to show
+        #[arg(short, long, default_value = "hash_tree.bdd")]
+        input: String,
+        /// Maximum number of levels to print below each root
+        #[arg(long)]
+        depth: Option<usize>,
+    },
+    /// Hash a file or stdin with the algorithms used for hash-tree files and print the hash as stored in hash-tree entries
+    Hash {
+        /// The file to hash, if not set stdin is hashed
+        file: Option<String>,
+        /// Hash algorithm to use
+        #[arg(long="hash", visible_alias = "algorithm", default_value = "sha256")]
+        hash_type: String,
+    },
+    /// Append file entries to a hash-tree file from an existing checksum manifest (e.g. written by sha256sum) instead of hashing the files
+    Import {
+        /// The checksum manifest, one `HASH  PATH` entry per line
+        manifest: String,
+        /// The directory the paths in the manifest are relative to
+        #[arg(short, long)]
+        base: String,
+        /// The hash tree file to append to, created if it does not exist
+        #[arg(short, long, default_value = "hash_tree.bdd")]
+        output: String,
+        /// Working directory, if set, the tool will use the current working directory as the base for relative paths.
+        #[arg(short, long)]
+        working_directory: Option<String>,
+        /// Hash algorithm the checksums in the manifest were computed with
+        #[arg(long="hash", default_value = "sha256")]
+        hash_type: String,
     },
 }
 
@@ -111,7 +402,7 @@ fn main() {
         env::set_var("RUST_LOG", format!("{}", log_level));
     }
 
-    env_logger::init();
+    utils::main::init_logger(args.trace_rate_limit);
 
     trace!("Initializing program");
     
@@ -128,6 +419,8 @@ fn main() {
     match args.command {
         Command::Build {
             directory,
+            files_from,
+            null_separated,
             // archives,
             follow_symlinks,
             output,
@@ -135,6 +428,38 @@ fn main() {
             working_directory,
             recreate_output,
             hash_type,
+            hash_type_by_extension,
+            dir_hash_mode,
+            identity,
+            eta,
+            progress,
+            progress_interval,
+            checkpoint_interval,
+            verify_after,
+            one_filesystem,
+            exclude_device,
+            capture_physical_size,
+            max_open_files,
+            max_memory,
+            capture_xattrs,
+            capture_file_ids,
+            capture_btime,
+            dedup_optimized,
+            detect_content_type,
+            mtime_tolerance,
+            since,
+            summary,
+            mmap_threshold,
+            follow_symlinks_external_as_copy,
+            shards,
+            preallocate,
+            pin_threads,
+            snapshot,
+            output_order,
+            report_unreadable,
+            dry_run,
+            list_files,
+            watch,
             no_clean
         } => {
             debug!("Running build command");
@@ -143,22 +468,106 @@ fn main() {
             
             let hash_type = match GeneralHashType::from_str(hash_type.as_str()) {
                 Ok(hash) => hash,
+                Err(_) if hash_type.eq_ignore_ascii_case("auto") => {
+                    let fastest = GeneralHashType::fastest();
+                    info!("Hash algorithm auto: using {}, the fastest available algorithm. Duplicates are only found among files of equal size, which makes accidental collisions negligible", fastest);
+                    fastest
+                },
                 Err(supported) => {
                     eprintln!("Unsupported hash type: {}. The values {} are supported.", hash_type.as_str(), supported);
                     std::process::exit(exitcode::CONFIG);
                 }
             };
+            
+            let hash_type_by_extension = hash_type_by_extension.iter().map(|mapping| {
+                let (extension, extension_hash_type) = mapping.split_once('=').unwrap_or_else(|| {
+                    eprintln!("Invalid hash algorithm override: {}. Expected the format EXT=HASH.", mapping);
+                    std::process::exit(exitcode::CONFIG);
+                });
+                let extension_hash_type = GeneralHashType::from_str(extension_hash_type).unwrap_or_else(|supported| {
+                    eprintln!("Unsupported hash type: {}. The values {} are supported.", extension_hash_type, supported);
+                    std::process::exit(exitcode::CONFIG);
+                });
+                (extension.trim_start_matches('*').trim_start_matches('.').to_lowercase(), extension_hash_type)
+            }).collect::<HashMap<String, GeneralHashType>>();
+            
+            let dir_hash_mode = match DirHashMode::from_str(dir_hash_mode.as_str()) {
+                Ok(mode) => mode,
+                Err(supported) => {
+                    eprintln!("Unsupported directory hash mode: {}. The values {} are supported.", dir_hash_mode.as_str(), supported);
+                    std::process::exit(exitcode::CONFIG);
+                }
+            };
+            
+            let file_identity = match FileIdentity::from_str(identity.as_str()) {
+                Ok(identity) => identity,
+                Err(supported) => {
+                    eprintln!("Unsupported file identity: {}. The values {} are supported.", identity, supported);
+                    std::process::exit(exitcode::CONFIG);
+                }
+            };
+            
+            let snapshot = snapshot.map(|snapshot| match SnapshotKind::from_str(snapshot.as_str()) {
+                Ok(kind) => kind,
+                Err(supported) => {
+                    eprintln!("Unsupported snapshot tool: {}. The values {} are supported.", snapshot, supported);
+                    std::process::exit(exitcode::CONFIG);
+                }
+            });
+
+            let since = since.map(|since| match utils::parse_timestamp(since.as_str()) {
+                Ok(since) => since,
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    std::process::exit(exitcode::CONFIG);
+                }
+            });
+            
+            let output_order = match OutputOrder::from_str(output_order.as_str()) {
+                Ok(order) => order,
+                Err(supported) => {
+                    eprintln!("Unsupported output order: {}. The values {} are supported.", output_order, supported);
+                    std::process::exit(exitcode::CONFIG);
+                }
+            };
 
             // Convert to paths and check if they exist
 
-            let directory = utils::main::parse_path(directory.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting);
+            let directory = utils::main::parse_path(directory.as_deref().unwrap_or("."), utils::main::ParsePathKind::AbsoluteNonExisting);
             let output = utils::main::parse_path(output.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting);
+            let report_unreadable = report_unreadable.map(|path| utils::main::parse_path(path.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting));
+            let summary = summary.map(|path| utils::main::parse_path(path.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting));
             let working_directory = working_directory.map(|w| utils::main::parse_path(w.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting));
+            let exclude_devices = exclude_device.iter().map(|path| utils::main::parse_path(path.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting)).collect::<Vec<PathBuf>>();
+            // the files written next to the hash tree are not hashed if they are below the directory
+            let exclude_files = summary.iter().chain(&report_unreadable).cloned()
+                .chain((0..shards.unwrap_or(0)).map(|shard| build::output::shard::shard_path(&output, shard)))
+                .collect::<Vec<PathBuf>>();
 
             if !directory.exists() {
                 eprintln!("Target directory does not exist: {}", directory.display());
                 std::process::exit(exitcode::CONFIG);
             }
+            
+            let files = files_from.map(|files_from| {
+                let list = match files_from.as_str() {
+                    "-" => build::cmd::file_list::read_file_list(std::io::stdin().lock(), null_separated),
+                    path => match fs::File::open(path) {
+                        Ok(file) => build::cmd::file_list::read_file_list(std::io::BufReader::new(file), null_separated),
+                        Err(err) => {
+                            eprintln!("Failed to open file list {}: {}", path, err);
+                            std::process::exit(exitcode::NOINPUT);
+                        }
+                    }
+                };
+                match list {
+                    Ok(list) => list.into_iter().map(|path| utils::main::to_lexical_absolute(path, false)).collect::<Vec<PathBuf>>(),
+                    Err(err) => {
+                        eprintln!("Error: {:?}", err);
+                        std::process::exit(exitcode::IOERR);
+                    }
+                }
+            });
 
             match output.parent().map(|p| p.exists()) {
                 Some(false) => {
@@ -184,51 +593,191 @@ fn main() {
                 eprintln!("IO error, could not resolve target directory relative to working directory");
                 std::process::exit(exitcode::CONFIG);
             });
+            
+            let files = files.map(|files| files.iter().map(|file| match file.strip_prefix(&working_directory) {
+                Ok(file) => file.to_path_buf(),
+                Err(_) => {
+                    eprintln!("IO error, could not resolve listed path {} relative to working directory", file.display());
+                    std::process::exit(exitcode::CONFIG);
+                }
+            }).collect::<Vec<PathBuf>>());
 
-            info!("Target directory: {:?}", directory);
+            match &files {
+                Some(files) => info!("Listed files: {}", files.len()),
+                None => info!("Target directory: {:?}", directory),
+            }
             // info!("Archives: {:?}", archives);
             info!("Follow symlinks: {:?}", follow_symlinks);
             info!("Output: {:?}", output);
             // info!("Absolute paths: {:?}", absolute_paths);
             info!("Working directory: {:?}", working_directory);
+            info!("Hash algorithm overrides: {:?}", hash_type_by_extension);
+            
+            let max_open_files = match max_open_files {
+                Some(0) => {
+                    eprintln!("--max-open-files must be at least 1");
+                    std::process::exit(exitcode::CONFIG);
+                },
+                Some(max_open_files) => Some(max_open_files),
+                None => utils::open_files_limit().map(|limit| (limit / 2).max(1) as usize),
+            };
+            info!("Max open files: {:?}", max_open_files);
 
-            // Run the command
+            if dry_run {
+                match build::cmd::dry_run::run(DryRunSettings {
+                    directory: directory.to_path_buf(),
+                    follow_symlinks,
+                    list_files,
+                    one_filesystem,
+                    exclude_devices: exclude_devices.clone(),
+                    exclude_files: std::iter::once(output.clone()).chain(exclude_files.iter().cloned()).collect(),
+                }) {
+                    Ok(_) => {
+                        info!("Dry run completed successfully");
+                        std::process::exit(exitcode::OK);
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {:?}", e);
+                        std::process::exit(utils::main::error_exit_code(&e));
+                    }
+                }
+            }
 
-            match build::cmd::run(BuildSettings {
-                directory: directory.to_path_buf(),
-                //into_archives: archives,
-                follow_symlinks,
-                output: output.clone(),
-                // absolute_paths,
-                threads: args.threads,
-                continue_file: !recreate_output,
-                hash_type
-            }) {
-                Ok(_) => {
-                    info!("Build command completed successfully");
-                    
-                    if !no_clean {
-                        info!("Executing clean command");
-                        match clean::cmd::run(CleanSettings {
-                            input: output.clone(),
-                            output: output,
-                            root: None,
-                            follow_symlinks
-                        }) {
-                            Ok(_) => {
-                                info!("Clean command completed successfully");
-                                std::process::exit(exitcode::OK);
+            // Run the command
+            
+            let mut continue_file = !recreate_output;
+            let cancel = utils::main::cancel_on_interrupt();
+            // the files written by the build and the temporary files they are written to
+            let watcher = watch.map(|interval| (Duration::from_secs(interval), ChangeWatcher::new(
+                &files.clone().unwrap_or_else(|| vec![directory.to_path_buf()]),
+                std::iter::once(&output).chain(&exclude_files)
+                    .flat_map(|path| [path.clone(), ReplacementFile::temp_path(path)])
+                    .collect(),
+            )));
+            
+            loop {
+                let outcome = match build::cmd::run(BuildSettings {
+                    directory: directory.to_path_buf(),
+                    files: files.clone(),
+                    //into_archives: archives,
+                    follow_symlinks,
+                    output: output.clone(),
+                    // absolute_paths,
+                    threads: args.threads,
+                    continue_file,
+                    hash_type,
+                    hash_type_by_extension: hash_type_by_extension.clone(),
+                    dir_hash_mode,
+                    file_identity,
+                    eta,
+                    progress_bar: progress,
+                    progress_interval: Duration::from_millis(progress_interval),
+                    checkpoint_interval: checkpoint_interval.map(Duration::from_secs),
+                    verify_after,
+                    one_filesystem,
+                    exclude_devices: exclude_devices.clone(),
+                    exclude_files: exclude_files.clone(),
+                    capture_physical_size,
+                    max_open_files,
+                    max_memory: max_memory.map(|mib| mib * 1024 * 1024),
+                    capture_xattrs,
+                    capture_file_ids,
+                    capture_btime,
+                    dedup_optimized,
+                    detect_content_type,
+                    mtime_tolerance,
+                    since,
+                    mmap_threshold,
+                    external_symlinks_as_copy: follow_symlinks_external_as_copy,
+                    preallocate,
+                    pin_threads,
+                    snapshot,
+                    output_order,
+                    sinks: Vec::new(),
+                    warnings: None,
+                    cancel: Some(Arc::clone(&cancel)),
+                }) {
+                    Ok(outcome) => {
+                        info!("Build command completed successfully");
+                        
+                        if let Some(summary) = &summary {
+                            if let Err(e) = outcome.summary.save(summary) {
+                                eprintln!("Error: failed to write the build summary: {:?}", e);
+                                std::process::exit(utils::main::error_exit_code(&e));
+                            }
+                        }
+                        
+                        if let Some(report_unreadable) = &report_unreadable {
+                            if let Err(e) = outcome.save_unreadable(report_unreadable) {
+                                eprintln!("Error: failed to write the unreadable files: {:?}", e);
+                                std::process::exit(utils::main::error_exit_code(&e));
+                            }
+                        }
+                        
+                        // a cancelled build is continued by the next run, which cleans the hash tree
+                        if cancel.load(Ordering::Relaxed) {
+                            info!("Build stopped, the hash tree can be continued by the next build");
+                            std::process::exit(utils::main::EXIT_INTERRUPTED);
+                        }
+                        
+                        if !no_clean {
+                            info!("Executing clean command");
+                            match clean::cmd::run(CleanSettings {
+                                input: output.clone(),
+                                output: output.clone(),
+                                root: None,
+                                follow_symlinks,
+                                anonymize_salt: None,
+                                rewrite_prefixes: Vec::new(),
+                                strict: false,
+                                warnings: None,
+                                progress,
+                                dedupe_identical_lines: false,
+                            }) {
+                                Ok(_) => {
+                                    info!("Clean command completed successfully");
+                                }
+                                Err(e) => {
+                                    eprintln!("Error: {:?}", e);
+                                    std::process::exit(utils::main::error_exit_code(&e));
+                                }
                             }
-                            Err(e) => {
-                                eprintln!("Error: {:?}", e);
-                                std::process::exit(exitcode::SOFTWARE);
+                        }
+                        
+                        if cancel.load(Ordering::Relaxed) {
+                            info!("Build stopped, the shards are written by the next build");
+                            std::process::exit(utils::main::EXIT_INTERRUPTED);
+                        }
+                        
+                        if let Some(shards) = shards {
+                            match build::output::shard::write_shards(&output, shards) {
+                                Ok(paths) => info!("Split the hash tree into {} shards", paths.len()),
+                                Err(e) => {
+                                    eprintln!("Error: failed to write the shards: {:?}", e);
+                                    std::process::exit(utils::main::error_exit_code(&e));
+                                }
                             }
                         }
+                        
+                        outcome
                     }
-                }
-                Err(e) => {
-                    eprintln!("Error: {:?}", e);
-                    std::process::exit(exitcode::SOFTWARE);
+                    Err(e) => {
+                        eprintln!("Error: {:?}", e);
+                        std::process::exit(utils::main::error_exit_code(&e));
+                    }
+                };
+                
+                match &watcher {
+                    Some((interval, watcher)) => {
+                        info!("Watching for changes, the next update starts once a file changed, at the earliest in {} seconds", interval.as_secs());
+                        if !watcher.wait(*interval, &cancel) {
+                            info!("Stopped watching for changes");
+                            std::process::exit(utils::main::EXIT_INTERRUPTED);
+                        }
+                        continue_file = true;
+                    },
+                    None if outcome.summary.errors > 0 => std::process::exit(utils::main::EXIT_PARTIAL),
+                    None => std::process::exit(exitcode::OK),
                 }
             }
         },
@@ -238,10 +787,26 @@ fn main() {
             overwrite,
             root,
             working_directory,
-            follow_symlinks
+            follow_symlinks,
+            anonymize_paths,
+            rewrite_prefix,
+            strict,
+            progress,
+            dedupe_identical_lines,
         } => {
+            let rewrite_prefixes = rewrite_prefix.iter().map(|rewrite| {
+                match rewrite.split_once('=') {
+                    Some((from, to)) if !from.is_empty() && !to.is_empty() => (PathBuf::from(from), PathBuf::from(to)),
+                    _ => {
+                        eprintln!("Invalid prefix rewrite: {}. Expected the format FROM=TO.", rewrite);
+                        std::process::exit(exitcode::CONFIG);
+                    }
+                }
+            }).collect::<Vec<(PathBuf, PathBuf)>>();
+
             let input = utils::main::parse_path(input.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting);
             let output = utils::main::parse_path(output.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting);
+            let anonymize_salt = anonymize_paths.map(|path| utils::main::parse_path(path.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting));
 
             // Change working directory
             trace!("Changing working directory");
@@ -254,7 +819,7 @@ fn main() {
             }
             
             if output.exists() && !overwrite {
-                eprintln!("Output file already exists: {:?}. Set --override to override its content", output);
+                eprintln!("Output file already exists: {:?}. Set --overwrite to overwrite its content", output);
                 std::process::exit(exitcode::CONFIG);
             }
             
@@ -262,7 +827,13 @@ fn main() {
                 input,
                 output,
                 root,
-                follow_symlinks
+                follow_symlinks,
+                anonymize_salt,
+                rewrite_prefixes,
+                strict,
+                warnings: None,
+                progress,
+                dedupe_identical_lines,
             }) {
                 Ok(_) => {
                     info!("Clean command completed successfully");
@@ -270,40 +841,264 @@ fn main() {
                 }
                 Err(e) => {
                     eprintln!("Error: {:?}", e);
-                    std::process::exit(exitcode::SOFTWARE);
+                    std::process::exit(utils::main::error_exit_code(&e));
+                }
+            }
+        },
+        Command::Refresh {
+            input,
+            working_directory,
+            mtime_tolerance,
+        } => {
+            let input = utils::main::parse_path(input.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting);
+
+            // Change working directory
+            trace!("Changing working directory");
+
+            utils::main::change_working_directory(working_directory.map(|w| utils::main::parse_path(w.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting)));
+
+            if !input.exists() {
+                eprintln!("Input file does not exist: {:?}", input);
+                std::process::exit(exitcode::CONFIG);
+            }
+
+            match refresh::cmd::run(RefreshSettings {
+                input,
+                mtime_tolerance,
+            }) {
+                Ok(_) => {
+                    info!("Refresh command completed successfully");
+                    std::process::exit(exitcode::OK);
+                }
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    std::process::exit(utils::main::error_exit_code(&e));
                 }
             }
         },
         Command::Analyze {
             input,
             output,
-            overwrite
+            format,
+            overwrite,
+            unique_to,
+            min_size,
+            pretty,
+            physical_size,
+            exclude_hashes,
+            on_collision,
+            strict,
+            group_hardlinks,
+            allow_mixed_hash,
+            shards,
+            include_null_hashes,
+            max_groups,
+            summary,
         } => {
-            let input = utils::main::parse_path(input.as_str(), utils::main::ParsePathKind::AbsoluteExisting);
-            let output = utils::main::parse_path(output.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting);
+            let on_collision = match CollisionPolicy::from_str(on_collision.as_str()) {
+                Ok(policy) => policy,
+                Err(supported) => {
+                    eprintln!("Unsupported collision policy: {}. The values {} are supported.", on_collision, supported);
+                    std::process::exit(exitcode::CONFIG);
+                }
+            };
 
-            if !input.exists() {
+            let input = match shards {
+                Some(_) => utils::main::parse_path(input.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting),
+                None => utils::main::parse_path(input.as_str(), utils::main::ParsePathKind::AbsoluteExisting),
+            };
+            let unique_to = unique_to.map(|path| utils::main::parse_path(path.as_str(), utils::main::ParsePathKind::AbsoluteExisting));
+            let exclude_hashes = exclude_hashes.map(|path| utils::main::parse_path(path.as_str(), utils::main::ParsePathKind::AbsoluteExisting));
+            let summary = summary.map(|path| utils::main::parse_path(path.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting));
+
+            if shards.is_none() && !input.exists() {
                 eprintln!("Input file does not exist: {:?}", input);
                 std::process::exit(exitcode::CONFIG);
             }
             
-            if output.exists() && !overwrite {
-                eprintln!("Output file already exists: {:?}. Set --override to override its content", output);
+            if let Some(unique_to) = &unique_to {
+                if !unique_to.exists() {
+                    eprintln!("Hash tree file to compare against does not exist: {:?}", unique_to);
+                    std::process::exit(exitcode::CONFIG);
+                }
+            }
+            
+            if format.len() > output.len() {
+                eprintln!("More formats ({}) than output files ({}) given", format.len(), output.len());
                 std::process::exit(exitcode::CONFIG);
             }
 
+            let mut outputs = Vec::with_capacity(output.len());
+            for (index, path) in output.iter().enumerate() {
+                let path = utils::main::parse_path(path.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting);
+
+                if path.exists() && !overwrite {
+                    eprintln!("Output file already exists: {:?}. Set --overwrite to overwrite its content", path);
+                    std::process::exit(exitcode::CONFIG);
+                }
+                
+                if outputs.iter().any(|output: &AnalysisOutput| output.path == path) {
+                    eprintln!("Output file given multiple times: {:?}", path);
+                    std::process::exit(exitcode::CONFIG);
+                }
+
+                let format = match format.get(index) {
+                    Some(format) => match AnalysisFormat::from_str(format) {
+                        Ok(format) => format,
+                        Err(err) => {
+                            eprintln!("Invalid output format: {}. Available formats: {}", format, err);
+                            std::process::exit(exitcode::CONFIG);
+                        }
+                    },
+                    None => AnalysisFormat::default(),
+                };
+
+                outputs.push(AnalysisOutput { path, format });
+            }
+
             match analyze::cmd::run(AnalysisSettings {
                 input,
-                output,
+                outputs,
                 threads: args.threads,
+                unique_to,
+                min_size,
+                pretty,
+                physical_size,
+                exclude_hashes,
+                on_collision,
+                strict,
+                group_hardlinks,
+                allow_mixed_hash,
+                shards,
+                include_null_hashes,
+                max_groups,
             }) {
-                Ok(_) => {
+                Ok(result) => {
                     info!("Analyze command completed successfully");
+                    if let Some(summary) = &summary {
+                        if let Err(e) = result.save(summary) {
+                            eprintln!("Error: failed to write the analysis summary: {:?}", e);
+                            std::process::exit(utils::main::error_exit_code(&e));
+                        }
+                    }
+                    match result.no_duplicates() {
+                        true => std::process::exit(utils::main::EXIT_NO_DUPLICATES),
+                        false => std::process::exit(exitcode::OK),
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    std::process::exit(utils::main::error_exit_code(&e));
+                }
+            }
+        },
+        Command::Hash {
+            file,
+            hash_type
+        } => {
+            let hash_type = match GeneralHashType::from_str(hash_type.as_str()) {
+                Ok(hash) => hash,
+                Err(supported) => {
+                    eprintln!("Unsupported hash type: {}. The values {} are supported.", hash_type.as_str(), supported);
+                    std::process::exit(exitcode::CONFIG);
+                }
+            };
+            
+            let mut hash = GeneralHash::from_type(hash_type);
+            let result = match file {
+                Some(file) => {
+                    let file = utils::main::parse_path(file.as_str(), utils::main::ParsePathKind::AbsoluteExisting);
+                    match fs::File::open(&file) {
+                        Ok(file) => hash.hash_file(std::io::BufReader::new(file)),
+                        Err(e) => {
+                            eprintln!("Unable to open file {:?}: {}", file, e);
+                            std::process::exit(exitcode::CONFIG);
+                        }
+                    }
+                }
+                None => hash.hash_file(std::io::stdin().lock()),
+            };
+            
+            match result {
+                Ok(_) => {
+                    println!("{}", hash);
+                    std::process::exit(exitcode::OK);
+                }
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    std::process::exit(exitcode::IOERR);
+                }
+            }
+        },
+        Command::Import {
+            manifest,
+            base,
+            output,
+            working_directory,
+            hash_type
+        } => {
+            let hash_type = match GeneralHashType::from_str(hash_type.as_str()) {
+                Ok(hash) => hash,
+                Err(supported) => {
+                    eprintln!("Unsupported hash type: {}. The values {} are supported.", hash_type.as_str(), supported);
+                    std::process::exit(exitcode::CONFIG);
+                }
+            };
+
+            let manifest = utils::main::parse_path(manifest.as_str(), utils::main::ParsePathKind::AbsoluteExisting);
+            let base = utils::main::parse_path(base.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting);
+            let output = utils::main::parse_path(output.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting);
+            let working_directory = working_directory.map(|w| utils::main::parse_path(w.as_str(), utils::main::ParsePathKind::AbsoluteNonExisting));
+
+            if !base.is_dir() {
+                eprintln!("Base directory does not exist: {}", base.display());
+                std::process::exit(exitcode::CONFIG);
+            }
+
+            // Change working directory
+            trace!("Changing working directory");
+
+            let working_directory = utils::main::change_working_directory(working_directory);
+
+            // Convert paths to relative path to working directory
+
+            let base = base.strip_prefix(&working_directory).unwrap_or_else(|_| {
+                eprintln!("IO error, could not resolve base directory relative to working directory");
+                std::process::exit(exitcode::CONFIG);
+            });
+
+            match import::cmd::run(ImportSettings {
+                manifest,
+                base: base.to_path_buf(),
+                output,
+                hash_type,
+            }) {
+                Ok(_) => {
+                    info!("Import command completed successfully");
+                    std::process::exit(exitcode::OK);
+                }
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    std::process::exit(utils::main::error_exit_code(&e));
+                }
+            }
+        },
+        Command::Show {
+            input,
+            depth
+        } => {
+            let input = utils::main::parse_path(input.as_str(), utils::main::ParsePathKind::AbsoluteExisting);
+
+            match show::cmd::run(ShowSettings {
+                input,
+                depth,
+            }) {
+                Ok(_) => {
                     std::process::exit(exitcode::OK);
                 }
                 Err(e) => {
                     eprintln!("Error: {:?}", e);
-                    std::process::exit(exitcode::SOFTWARE);
+                    std::process::exit(utils::main::error_exit_code(&e));
                 }
             }
         },
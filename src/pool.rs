@@ -52,11 +52,15 @@ impl Worker {
     /// * `job_publish` - A sender to publish new jobs to the thread pool.
     /// * `func` - The worker entry function to process jobs.
     /// * `arg` - The arguments passed to the worker thread via the thread pool creation.
+    /// * `pin_thread` - Whether to pin the worker thread to a CPU core, see [pin_current_thread].
     /// 
     /// # Returns
     /// * `Worker` - The worker struct with the worker thread handle.
-    fn new<Job: JobTrait + Send + 'static, Result: ResultTrait + Send + 'static, Argument: Send + 'static>(id: usize, job_receive: Arc<Mutex<Receiver<Job>>>, result_publish: Sender<Result>, job_publish: Sender<Job>, func: WorkerEntry<Job, Result, Argument>, arg: Argument) -> Worker {
+    fn new<Job: JobTrait + Send + 'static, Result: ResultTrait + Send + 'static, Argument: Send + 'static>(id: usize, job_receive: Arc<Mutex<Receiver<Job>>>, result_publish: Sender<Result>, job_publish: Sender<Job>, func: WorkerEntry<Job, Result, Argument>, arg: Argument, pin_thread: bool) -> Worker {
         let thread = thread::spawn(move || {
+            if pin_thread {
+                pin_current_thread(id);
+            }
             Worker::worker_entry(id, job_receive, result_publish, job_publish, func, arg);
         });
 
@@ -136,7 +140,22 @@ impl<Job: Send + JobTrait + 'static, Result: Send + ResultTrait + 'static> Threa
     /// # Template Parameters
     /// * `Argument` - The argument type that should be passed to the worker threads.
     /// The argument type must implement the `Send` trait.
-    pub fn new<Argument: Send + 'static>(mut args: Vec<Argument>, func: WorkerEntry<Job, Result, Argument>) -> ThreadPool<Job, Result> {
+    pub fn new<Argument: Send + 'static>(args: Vec<Argument>, func: WorkerEntry<Job, Result, Argument>) -> ThreadPool<Job, Result> {
+        ThreadPool::new_pinned(args, func, false)
+    }
+
+    /// Create a new thread pool like [ThreadPool::new], optionally pinning every worker thread
+    /// to its own CPU core (spread over the available cores), which improves the cache locality
+    /// of the workers on machines with many cores. See [pin_current_thread].
+    /// 
+    /// # Arguments
+    /// * `args` - A vector of arguments that should be passed to the worker threads.
+    /// * `func` - The worker entry function to process jobs.
+    /// * `pin_threads` - Whether to pin the worker threads to CPU cores.
+    /// 
+    /// # Returns
+    /// * `ThreadPool` - The thread pool struct with the worker threads.
+    pub fn new_pinned<Argument: Send + 'static>(mut args: Vec<Argument>, func: WorkerEntry<Job, Result, Argument>, pin_threads: bool) -> ThreadPool<Job, Result> {
         assert!(args.len() > 0);
 
         let mut workers = Vec::with_capacity(args.len());
@@ -149,7 +168,7 @@ impl<Job: Send + JobTrait + 'static, Result: Send + ResultTrait + 'static> Threa
 
         let mut id = 0;
         while let Some(arg) = args.pop() {
-            workers.push(Worker::new(id, Arc::clone(&job_receive), result_publish.clone(), thread_publish_job.clone(), func, arg));
+            workers.push(Worker::new(id, Arc::clone(&job_receive), result_publish.clone(), thread_publish_job.clone(), func, arg, pin_threads));
             id += 1;
         }
 
@@ -252,6 +271,62 @@ impl<Job: Send + JobTrait + 'static, Result: Send + ResultTrait + 'static> Threa
     pub fn receive_timeout(&self, timeout: Duration) -> std::result::Result<Result, RecvTimeoutError> {
         self.result_receive.recv_timeout(timeout)
     }
+
+    /// Process a batch of independent jobs and wait for all of them to finish.
+    /// Publishes every job and receives one result per job.
+    ///
+    /// The worker entry function must publish exactly one result per job and must not publish
+    /// follow-up jobs, otherwise results are mixed up with later calls or this function never returns.
+    ///
+    /// # Arguments
+    /// * `jobs` - The jobs that should be processed by the worker threads.
+    ///
+    /// # Returns
+    /// * `Vec<Result>` - The results in the order the jobs finished, not in the order of the jobs.
+    ///
+    /// # Errors
+    /// * If all worker threads panicked, therefore the pipe is closed
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::mpsc::Sender;
+    /// use backup_deduplicator::pool::{JobTrait, ResultTrait, ThreadPool};
+    ///
+    /// struct Square(usize);
+    /// impl JobTrait for Square {
+    ///     fn job_id(&self) -> usize { self.0 }
+    /// }
+    ///
+    /// struct Squared(usize);
+    /// impl ResultTrait for Squared {}
+    ///
+    /// fn worker(_id: usize, job: Square, result_publish: &Sender<Squared>, _job_publish: &Sender<Square>, _arg: &mut ()) {
+    ///     result_publish.send(Squared(job.0 * job.0)).unwrap();
+    /// }
+    ///
+    /// let pool = ThreadPool::new(vec![(), (), ()], worker);
+    ///
+    /// let mut squares: Vec<usize> = pool.map((1..=5).map(Square).collect()).unwrap().into_iter().map(|result| result.0).collect();
+    /// squares.sort();
+    /// assert_eq!(squares, vec![1, 4, 9, 16, 25]);
+    ///
+    /// // the pool can be reused
+    /// assert_eq!(pool.map(vec![Square(6)]).unwrap().len(), 1);
+    /// ```
+    pub fn map(&self, jobs: Vec<Job>) -> std::result::Result<Vec<Result>, mpsc::RecvError> {
+        let count = jobs.len();
+
+        for job in jobs {
+            self.publish(job);
+        }
+
+        let mut results = Vec::with_capacity(count);
+        while results.len() < count {
+            results.push(self.receive()?);
+        }
+
+        Ok(results)
+    }
 }
 
 impl<Job: Send, Result: Send> Drop for ThreadPool<Job, Result> {
@@ -285,3 +360,31 @@ impl<Job: Send, Result: Send> Drop for ThreadPool<Job, Result> {
         }
     }
 }
+
+/// Pin the current thread to a CPU core. Worker `id` is pinned to the `id`-th available core,
+/// wrapping around if there are more workers than cores.
+///
+/// # Arguments
+/// * `id` - The worker id.
+#[cfg(feature = "pin-threads")]
+fn pin_current_thread(id: usize) {
+    let cores = core_affinity::get_core_ids().unwrap_or_default();
+    if cores.is_empty() {
+        warn!("Worker {} can not be pinned, the CPU cores are not available on this platform", id);
+        return;
+    }
+
+    let core = cores[id % cores.len()];
+    match core_affinity::set_for_current(core) {
+        true => debug!("Pinned worker {} to core {}", id, core.id),
+        false => warn!("Failed to pin worker {} to core {}", id, core.id),
+    }
+}
+
+/// Pin the current thread to a CPU core.
+/// Built without the `pin-threads` feature, does nothing.
+///
+/// # Arguments
+/// * `id` - The worker id.
+#[cfg(not(feature = "pin-threads"))]
+fn pin_current_thread(_id: usize) {}
@@ -1,8 +1,12 @@
 
 pub mod output {
     mod dupset_file;
+    mod result_writer;
+    mod unique_file;
     
     pub use dupset_file::*;
+    pub use result_writer::*;
+    pub use unique_file::*;
 }
 
 pub mod cmd;
@@ -1,31 +1,182 @@
 use crate::stages::analyze::worker::AnalysisIntermediaryFile;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
 use std::fs;
-use std::io::Write;
+use std::io::{BufRead, BufReader};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use anyhow::{anyhow, Result};
-use log::{error, info, trace};
+use anyhow::{anyhow, Context, Result};
+use log::{error, info, trace, warn};
+use serde::Serialize;
+use crate::fileid::HandleIdentifier;
 use crate::hash::{GeneralHash, GeneralHashType};
+use crate::path::FilePath;
 use crate::pool::ThreadPool;
 use crate::stages::analyze::intermediary_analysis_data::AnalysisFile;
-use crate::stages::analyze::output::{DupSetEntryRef};
+use crate::stages::analyze::output::{AnalysisOutput, AnalysisResultWriter, DupSetEntryRef, UniqueEntryRef};
 use crate::stages::analyze::worker::{AnalysisJob, AnalysisResult, worker_run, AnalysisWorkerArgument};
 use crate::stages::build::output::{HashTreeFile, HashTreeFileEntry, HashTreeFileEntryType};
+use crate::stages::build::output::shard::open_shards;
 use crate::utils::NullWriter;
 
 /// The settings for the analysis cmd.
 ///
 /// # Fields
 /// * `input` - The input file to analyze.
-/// * `output` - The output file to write the results to.
+/// * `outputs` - The output files to write the results to, each in its own format.
 /// * `threads` - The number of threads to use for the analysis. If None, the number of threads is equal to the number of CPUs.
+/// * `unique_to` - If set, instead of finding duplicates, find the files whose content is not present in this hash tree file.
+/// * `min_size` - Files smaller than this size in bytes are not reported as unique.
+/// * `pretty` - Whether to write indented JSON outputs. Entries then span multiple lines instead of one line each.
+/// * `physical_size` - Whether to compute the duplicated bytes from the size allocated on disk,
+///   for files whose physical size was captured during the build.
+/// * `exclude_hashes` - If set, entries whose hash is listed in this file are ignored, see [load_excluded_hashes].
+/// * `strict` - Whether to fail on malformed entries of the hash tree files, see [HashTreeFileEntry::validate].
+/// * `on_collision` - How to handle files with equal hashes but different sizes, see [find_size_collisions].
+/// * `group_hardlinks` - Whether to group the files of a duplicate set by the file they link to, see [group_hardlinks].
+///   Only hardlinks of hash trees built with captured file ids are recognized.
+/// * `allow_mixed_hash` - Whether `unique_to` may compare hash trees built with different hash types.
+///   Files are then compared by path, size and modification time instead of their content.
+/// * `shards` - If set, the hash tree is read from this many shard files of `input`, see [open_shards].
+/// * `include_null_hashes` - Whether entries without a content hash (e.g. unreadable or special files) are
+///   grouped as duplicates, see [retain_duplicate_candidates].
+/// * `max_groups` - If set, only this many duplicate sets with the most reclaimable bytes are written,
+///   see [keep_largest_sets]. The duplicated bytes still count all sets.
 pub struct AnalysisSettings {
     pub input: PathBuf,
-    pub output: PathBuf,
+    pub outputs: Vec<AnalysisOutput>,
     pub threads: Option<usize>,
+    pub unique_to: Option<PathBuf>,
+    pub min_size: u64,
+    pub pretty: bool,
+    pub physical_size: bool,
+    pub exclude_hashes: Option<PathBuf>,
+    pub on_collision: CollisionPolicy,
+    pub strict: bool,
+    pub group_hardlinks: bool,
+    pub allow_mixed_hash: bool,
+    pub shards: Option<u32>,
+    pub include_null_hashes: bool,
+    pub max_groups: Option<u64>,
+}
+
+/// A machine-readable summary of an analysis, e.g. to skip further processing if there are no duplicates.
+///
+/// # Fields
+/// * `duplicate_sets` - The number of found duplicate sets.
+/// * `written_sets` - The number of duplicate sets written to the outputs, less than `duplicate_sets` with `max_groups`.
+/// * `duplicated_bytes` - The bytes that could be reclaimed by keeping one file of every duplicate set.
+/// * `unique_files` - The number of reported files when comparing against another hash tree with `unique_to`,
+///   `None` when searching duplicates.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct AnalysisSummary {
+    pub duplicate_sets: u64,
+    pub written_sets: u64,
+    pub duplicated_bytes: u64,
+    pub unique_files: Option<u64>,
+}
+
+impl AnalysisSummary {
+    /// Whether duplicates were searched and none were found.
+    ///
+    /// # Returns
+    /// True if no duplicate set was found, false if there are duplicates or files were compared with `unique_to`.
+    ///
+    /// # Example
+    /// ```
+    /// use backup_deduplicator::stages::analyze::cmd::AnalysisSummary;
+    ///
+    /// assert!(AnalysisSummary::default().no_duplicates());
+    /// assert!(!AnalysisSummary { duplicate_sets: 2, written_sets: 2, ..Default::default() }.no_duplicates());
+    /// assert!(!AnalysisSummary { unique_files: Some(0), ..Default::default() }.no_duplicates());
+    /// ```
+    pub fn no_duplicates(&self) -> bool {
+        self.unique_files.is_none() && self.duplicate_sets == 0
+    }
+
+    /// Write the summary as JSON document to a file, replacing its content.
+    ///
+    /// # Arguments
+    /// * `path` - The file to write to.
+    ///
+    /// # Errors
+    /// If the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json + "\n")?;
+
+        Ok(())
+    }
+}
+
+/// How the analysis handles files that share a hash but differ in size. Such files can not
+/// have the same content, so either the hash collided (likely with short hashes like XXH32)
+/// or the hash tree is broken. Acting on such a set would be dangerous.
+///
+/// # Variants
+/// * `Warn` - Log the files and leave their hash out of the duplicate sets (default).
+/// * `Error` - Abort the analysis.
+/// * `Ignore` - Group the files by size as if the hashes were different.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    #[default]
+    Warn,
+    Error,
+    Ignore,
+}
+
+impl FromStr for CollisionPolicy {
+    /// Error type for parsing a `CollisionPolicy` from a string.
+    type Err = &'static str;
+
+    /// Parses a string into a `CollisionPolicy`.
+    ///
+    /// # Arguments
+    /// * `s` - The string to parse.
+    ///
+    /// # Returns
+    /// The `CollisionPolicy` that corresponds to the string or an error.
+    ///
+    /// # Errors
+    /// Returns the available policies if the string does not correspond to a `CollisionPolicy`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::str::FromStr;
+    /// use backup_deduplicator::stages::analyze::cmd::CollisionPolicy;
+    ///
+    /// assert_eq!(CollisionPolicy::from_str("error"), Ok(CollisionPolicy::Error));
+    /// assert_eq!(CollisionPolicy::from_str("Warn"), Ok(CollisionPolicy::Warn));
+    /// assert!(CollisionPolicy::from_str("skip").is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "warn" => Ok(CollisionPolicy::Warn),
+            "error" => Ok(CollisionPolicy::Error),
+            "ignore" => Ok(CollisionPolicy::Ignore),
+            _ => Err("warn, error, ignore"),
+        }
+    }
+}
+
+impl Display for CollisionPolicy {
+    /// Converts a `CollisionPolicy` into a string.
+    ///
+    /// # Arguments
+    /// * `f` - The formatter to write to.
+    ///
+    /// # Returns
+    /// A result indicating whether the operation was successful.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CollisionPolicy::Warn => write!(f, "warn"),
+            CollisionPolicy::Error => write!(f, "error"),
+            CollisionPolicy::Ignore => write!(f, "ignore"),
+        }
+    }
 }
 
 /// Run the analysis cmd.
@@ -34,44 +185,46 @@ pub struct AnalysisSettings {
 /// * `analysis_settings` - The settings for the analysis cmd.
 ///
 /// # Returns
-/// Nothing
+/// The summary of the analysis.
 ///
 /// # Errors
 /// * If the input file cannot be opened.
-/// * If the output file cannot be opened.
+/// * If an output file cannot be opened.
 /// * If the header of the input file cannot be loaded.
 /// * If an error occurs while loading entries from the input file.
-/// * If writing to the output file fails.
-pub fn run(analysis_settings: AnalysisSettings) -> Result<()> {
-    let mut input_file_options = fs::File::options();
-    input_file_options.read(true);
-    input_file_options.write(false);
-
-    let mut output_file_options = fs::File::options();
-    output_file_options.create(true);
-    output_file_options.write(true);
-    output_file_options.truncate(true);
-
-    let input_file = match input_file_options.open(analysis_settings.input) {
-        Ok(file) => file,
-        Err(err) => {
-            return Err(anyhow!("Failed to open input file: {}", err));
-        }
+/// * If the file of excluded hashes cannot be loaded.
+/// * If an entry is malformed and `strict` is set.
+/// * If files share a hash but differ in size and `on_collision` is [CollisionPolicy::Error].
+/// * If writing to an output file fails.
+pub fn run(analysis_settings: AnalysisSettings) -> Result<AnalysisSummary> {
+    let input_reader: Box<dyn BufRead> = match analysis_settings.shards {
+        Some(shards) => Box::new(open_shards(&analysis_settings.input, shards)?),
+        None => Box::new(BufReader::new(fs::File::open(&analysis_settings.input).context("Failed to open input file")?)),
     };
 
-    let output_file = match output_file_options.open(analysis_settings.output) {
-        Ok(file) => file,
-        Err(err) => {
-            return Err(anyhow!("Failed to open output file: {}", err));
-        }
-    };
+    let mut output_writer = AnalysisResultWriter::create(&analysis_settings.outputs, analysis_settings.pretty)?;
+
+    if let Some(unique_to) = analysis_settings.unique_to {
+        return run_unique_to(input_reader, &mut output_writer, unique_to, analysis_settings.min_size, analysis_settings.exclude_hashes, analysis_settings.strict, analysis_settings.allow_mixed_hash);
+    }
 
-    let mut input_buf_reader = std::io::BufReader::new(&input_file);
+    output_writer.write_csv_header(&["ftype", "size", "hash", "set", "path"])?;
+
+    let mut input_buf_reader = input_reader;
     let mut null_out_writer = NullWriter::new();
-    let mut output_buf_writer = std::io::BufWriter::new(&output_file);
 
     let mut save_file = HashTreeFile::new(&mut null_out_writer, &mut input_buf_reader, GeneralHashType::NULL, true, true, true);
+    save_file.set_strict(analysis_settings.strict);
     save_file.load_header()?;
+    
+    if !save_file.header.file_identity.is_content() {
+        info!("The hash tree was built with file identity {}, files with equal content but different names are no duplicates", save_file.header.file_identity);
+    }
+    
+    let excluded_hashes = match &analysis_settings.exclude_hashes {
+        Some(path) => load_excluded_hashes(path, save_file.header.hash_type)?,
+        None => HashSet::new(),
+    };
 
     save_file.load_all_entries_no_filter()?;
     
@@ -88,11 +241,30 @@ pub fn run(analysis_settings: AnalysisSettings) -> Result<()> {
     }
     drop(file_by_path);
     
-    // delete all entries with no collision
+    // delete all entries with no collision, all excluded entries and entries without content hash
+    
+    retain_duplicate_candidates(&mut file_by_hash, &excluded_hashes, analysis_settings.include_null_hashes);
+    
+    // handle hashes of files with different sizes
+    
+    let collisions: Vec<GeneralHash> = find_size_collisions(&file_by_hash).into_iter().cloned().collect();
+    if !collisions.is_empty() {
+        match analysis_settings.on_collision {
+            CollisionPolicy::Error => {
+                return Err(anyhow!("{} hashes are shared by files of different sizes, the first is {}. The hash collided or the hash tree is broken", collisions.len(), collisions[0]));
+            },
+            CollisionPolicy::Warn => {
+                for hash in &collisions {
+                    let paths: Vec<String> = file_by_hash[hash].iter().map(|entry| format!("{} ({} bytes)", entry.path, entry.size)).collect();
+                    warn!("Files with hash {} differ in size, they are not reported as duplicates: {}", hash, paths.join(", "));
+                }
+                let collisions: HashSet<GeneralHash> = collisions.into_iter().collect();
+                file_by_hash.retain(|hash, _| !collisions.contains(hash));
+            },
+            CollisionPolicy::Ignore => {},
+        }
+    }
     
-    file_by_hash.retain(|_, entry| {
-        entry.len() >= 2
-    });
     file_by_hash.shrink_to_fit();
     
     // delete all entries with no collision
@@ -131,7 +303,8 @@ pub fn run(analysis_settings: AnalysisSettings) -> Result<()> {
 
     drop(pool);
     
-    let mut duplicated_bytes: u64 = 0;
+    let mut duplicate_sets = Vec::new();
+    let mut duplicated_bytes_by_type: HashMap<String, u64> = HashMap::new();
 
     for entry in &all_files {
         trace!("File: {}", entry.path);
@@ -177,11 +350,11 @@ pub fn run(analysis_settings: AnalysisSettings) -> Result<()> {
                     }
 
                     if !parent_conflicting {
-                        duplicated_bytes += write_result_entry(file, &file_by_hash, &mut output_buf_writer);
+                        duplicate_sets.extend(find_duplicate_sets(file, &file_by_hash, analysis_settings.physical_size, analysis_settings.group_hardlinks, &mut duplicated_bytes_by_type));
                     }
                 }
                 None => {
-                    duplicated_bytes += write_result_entry(file, &file_by_hash, &mut output_buf_writer);
+                    duplicate_sets.extend(find_duplicate_sets(file, &file_by_hash, analysis_settings.physical_size, analysis_settings.group_hardlinks, &mut duplicated_bytes_by_type));
                 }
             }
         } else {
@@ -189,11 +362,274 @@ pub fn run(analysis_settings: AnalysisSettings) -> Result<()> {
         }
     }
 
-    output_buf_writer.flush().expect("Unable to flush file");
+    let duplicated_bytes: u64 = duplicate_sets.iter().map(|(_, bytes)| bytes).sum();
+    let total_sets = duplicate_sets.len();
+    if let Some(max_groups) = analysis_settings.max_groups {
+        keep_largest_sets(&mut duplicate_sets, max_groups as usize);
+    }
+    
+    for (set, _) in &duplicate_sets {
+        output_writer.write_duplicate_set(set)?;
+    }
+
+    output_writer.flush().expect("Unable to flush file");
+    
+    // only available for hash trees built with content type detection
+    let mut duplicated_bytes_by_type: Vec<(String, u64)> = duplicated_bytes_by_type.into_iter().collect();
+    duplicated_bytes_by_type.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for (content_type, bytes) in duplicated_bytes_by_type {
+        info!("{} MB of duplicated {} files", bytes / 1024 / 1024, content_type);
+    }
     
-    print!("There are {} GB of duplicated files", duplicated_bytes / 1024 / 1024 / 1024);
+    if total_sets == 0 {
+        print!("No duplicates found");
+    } else {
+        print!("There are {} GB of duplicated files", duplicated_bytes / 1024 / 1024 / 1024);
+    }
+    if duplicate_sets.len() < total_sets {
+        print!(", {} of {} duplicate sets were omitted by --max-groups", total_sets - duplicate_sets.len(), total_sets);
+    }
 
-    Ok(())
+    Ok(AnalysisSummary {
+        duplicate_sets: total_sets as u64,
+        written_sets: duplicate_sets.len() as u64,
+        duplicated_bytes,
+        unique_files: None,
+    })
+}
+
+/// Find the files of the input hash tree whose content does not appear anywhere in
+/// another hash tree, regardless of their paths. Writes one [UniqueEntryRef] per file.
+///
+/// # Arguments
+/// * `input_reader` - The reader of the hash tree to report files from.
+/// * `output_writer` - The output files to write the results to.
+/// * `other` - The hash tree file to compare against.
+/// * `min_size` - Files smaller than this size in bytes are not reported.
+/// * `exclude_hashes` - If set, files whose hash is listed in this file are not reported.
+/// * `strict` - Whether to fail on malformed entries of the hash tree files.
+/// * `allow_mixed_hash` - Whether to compare hash trees with different hash types or file identities by path, size
+///   and modification time. A file is then reported unless the other tree has an entry with the
+///   same path, size and modification time; equal content can not be confirmed.
+///
+/// # Returns
+/// The summary with the number of reported files.
+///
+/// # Errors
+/// * If the other hash tree file cannot be opened.
+/// * If the headers or entries of the hash tree files cannot be loaded.
+/// * If the hash tree files use different hash types or file identities and `allow_mixed_hash` is not set.
+/// * If an entry is malformed and `strict` is set.
+/// * If the file of excluded hashes cannot be loaded.
+/// * If writing to an output file fails.
+fn run_unique_to(input_reader: Box<dyn BufRead>, output_writer: &mut AnalysisResultWriter, other: PathBuf, min_size: u64, exclude_hashes: Option<PathBuf>, strict: bool, allow_mixed_hash: bool) -> Result<AnalysisSummary> {
+    let other_file = fs::File::open(other).context("Failed to open hash tree file to compare against")?;
+
+    let mut other_buf_reader = std::io::BufReader::new(&other_file);
+    let mut null_out_writer = NullWriter::new();
+
+    let mut other_save_file = HashTreeFile::new(&mut null_out_writer, &mut other_buf_reader, GeneralHashType::NULL, true, allow_mixed_hash, false);
+    other_save_file.set_strict(strict);
+    other_save_file.load_header()?;
+    other_save_file.load_all_entries_no_filter()?;
+    let other_hash_type = other_save_file.header.hash_type;
+    let other_file_identity = other_save_file.header.file_identity;
+    let other_dedup_optimized = other_save_file.header.dedup_optimized;
+    let other_metadata: HashSet<(FilePath, u64, u64)> = match allow_mixed_hash {
+        true => other_save_file.file_by_path.values().map(|entry| (entry.path.clone(), entry.size, entry.modified)).collect(),
+        false => HashSet::new(),
+    };
+    let other_hashes: HashSet<GeneralHash> = other_save_file.file_by_hash.into_keys().collect();
+
+    let mut input_buf_reader = input_reader;
+    let mut null_out_writer = NullWriter::new();
+
+    let mut save_file = HashTreeFile::new(&mut null_out_writer, &mut input_buf_reader, GeneralHashType::NULL, false, true, false);
+    save_file.set_strict(strict);
+    save_file.load_header()?;
+
+    // files of unique size only have placeholder hashes, they would all be reported as unique
+    if save_file.header.dedup_optimized || other_dedup_optimized {
+        return Err(anyhow!("Dedup optimized hash trees can only be used to find duplicates within themselves, not to compare against another tree"));
+    }
+    
+    // file hashes with and without the file name mixed in never match
+    let mixed_reason = if save_file.header.hash_type != other_hash_type {
+        Some(format!("use different hash types ({} and {})", save_file.header.hash_type, other_hash_type))
+    } else if save_file.header.file_identity != other_file_identity {
+        Some(format!("were built with different file identities ({} and {})", save_file.header.file_identity, other_file_identity))
+    } else {
+        None
+    };
+    let mixed_hash = mixed_reason.is_some();
+    if let Some(reason) = &mixed_reason {
+        if !allow_mixed_hash {
+            return Err(anyhow!("The hash tree files {}, their hashes can not be compared", reason));
+        }
+        
+        warn!("The hash tree files {}, their hashes can not be compared", reason);
+        warn!("Comparing files by path, size and modification time only. Equal content can NOT be confirmed:");
+        warn!("moved or renamed files are reported as unique, changed files with the same size and modification time are not");
+    }
+    
+    let excluded_hashes = match &exclude_hashes {
+        Some(path) => load_excluded_hashes(path, save_file.header.hash_type)?,
+        None => HashSet::new(),
+    };
+
+    save_file.load_all_entries_no_filter()?;
+
+    let mut unique: Vec<&Arc<HashTreeFileEntry>> = save_file.file_by_path.values()
+        .filter(|entry| entry.file_type == HashTreeFileEntryType::File)
+        .filter(|entry| entry.size >= min_size)
+        .filter(|entry| match mixed_hash {
+            true => !other_metadata.contains(&(entry.path.clone(), entry.size, entry.modified)),
+            false => !other_hashes.contains(&entry.hash),
+        })
+        .filter(|entry| !excluded_hashes.contains(&entry.hash))
+        .collect();
+    unique.sort_by_cached_key(|entry| entry.path.to_string());
+
+    let mut unique_bytes: u64 = 0;
+
+    output_writer.write_csv_header(&["ftype", "size", "hash", "path"])?;
+
+    for entry in &unique {
+        let result = UniqueEntryRef {
+            ftype: &entry.file_type,
+            size: entry.size,
+            hash: &entry.hash,
+            path: &entry.path,
+        };
+        output_writer.write_unique(&result)?;
+
+        unique_bytes += entry.size;
+    }
+
+    output_writer.flush()?;
+
+    print!("There are {} unique files with {} MB", unique.len(), unique_bytes / 1024 / 1024);
+
+    Ok(AnalysisSummary {
+        unique_files: Some(unique.len() as u64),
+        ..Default::default()
+    })
+}
+
+/// Load a list of hashes to exclude from the analysis, e.g. of known operating system files.
+///
+/// The file contains one hash per line, either in the format of the hash tree file
+/// (`SHA256:0123...`) or as plain hex string of the hash type of the hash tree.
+/// Empty lines and lines starting with `#` are skipped.
+///
+/// # Arguments
+/// * `path` - The file to load.
+/// * `hash_type` - The hash type of the analyzed hash tree.
+///
+/// # Returns
+/// The set of excluded hashes.
+///
+/// # Errors
+/// * If the file cannot be read.
+/// * If a line is not a valid hash or its hash type differs from `hash_type`.
+fn load_excluded_hashes(path: &PathBuf, hash_type: GeneralHashType) -> Result<HashSet<GeneralHash>> {
+    let file = fs::File::open(path).context("Failed to open file of excluded hashes")?;
+
+    let mut excluded = HashSet::new();
+
+    for (index, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| anyhow!("Failed to read line {} of excluded hashes: {}", index + 1, err))?;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let hash = match line.contains(':') {
+            true => GeneralHash::from_str(line),
+            false => GeneralHash::from_str(format!("{}:{}", hash_type, line.to_lowercase()).as_str()),
+        }.map_err(|err| anyhow!("Invalid hash in line {} of excluded hashes: {}", index + 1, err))?;
+
+        if hash.hash_type() != hash_type {
+            return Err(anyhow!("The hash in line {} of excluded hashes is a {} hash, but the hash tree uses {}", index + 1, hash.hash_type(), hash_type));
+        }
+
+        excluded.insert(hash);
+    }
+
+    info!("Loaded {} excluded hashes", excluded.len());
+
+    Ok(excluded)
+}
+
+/// Group the files of a duplicate set by the file they link to. Files with the same
+/// captured file id (inode and device) are hardlinks of each other, files without an
+/// id are assumed to be independent copies.
+///
+/// # Arguments
+/// * `files` - The files of a duplicate set.
+///
+/// # Returns
+/// The groups of hardlinks in the order of their first file. A set with more than one
+/// group contains true duplicates.
+pub(crate) fn group_hardlinks<'a>(files: &[&'a HashTreeFileEntry]) -> Vec<Vec<&'a HashTreeFileEntry>> {
+    let mut groups: Vec<Vec<&HashTreeFileEntry>> = Vec::new();
+    let mut group_by_id: HashMap<&HandleIdentifier, usize> = HashMap::new();
+
+    for file in files {
+        match &file.file_id {
+            Some(file_id) => match group_by_id.get(file_id) {
+                Some(index) => groups[*index].push(*file),
+                None => {
+                    group_by_id.insert(file_id, groups.len());
+                    groups.push(vec![*file]);
+                }
+            },
+            None => groups.push(vec![*file]),
+        }
+    }
+
+    groups
+}
+
+/// Remove the hashes that can not form a duplicate set: hashes of a single entry, excluded hashes and,
+/// unless included explicitly, the `NULL` hash. Entries without a content hash (e.g. unreadable files,
+/// sockets or devices recorded as `Other`) all share the `NULL` hash, but are not duplicates of each other.
+///
+/// # Arguments
+/// * `file_by_hash` - The entries of the hash tree grouped by hash.
+/// * `excluded_hashes` - The hashes to remove.
+/// * `include_null` - Whether to keep the entries with the `NULL` hash.
+pub(crate) fn retain_duplicate_candidates(file_by_hash: &mut HashMap<GeneralHash, Vec<Arc<HashTreeFileEntry>>>, excluded_hashes: &HashSet<GeneralHash>, include_null: bool) {
+    file_by_hash.retain(|hash, entry| {
+        entry.len() >= 2 && !excluded_hashes.contains(hash) && (include_null || *hash != GeneralHash::NULL)
+    });
+}
+
+/// Find the hashes shared by files of different sizes. Files with equal content
+/// always have equal sizes, so these hashes collided or the hash tree is broken.
+/// Only regular files are compared, the size of a directory is its number of children.
+///
+/// # Arguments
+/// * `file_by_hash` - The entries of the hash tree grouped by hash.
+///
+/// # Returns
+/// The hashes shared by files of different sizes, sorted.
+pub(crate) fn find_size_collisions(file_by_hash: &HashMap<GeneralHash, Vec<Arc<HashTreeFileEntry>>>) -> Vec<&GeneralHash> {
+    let mut collisions: Vec<&GeneralHash> = file_by_hash.iter()
+        .filter(|(_, entries)| {
+            let mut sizes = entries.iter()
+                .filter(|entry| entry.file_type == HashTreeFileEntryType::File)
+                .map(|entry| entry.size);
+            match sizes.next() {
+                Some(first) => sizes.any(|size| size != first),
+                None => false,
+            }
+        })
+        .map(|(hash, _)| hash)
+        .collect();
+    collisions.sort_by_cached_key(|hash| hash.to_string());
+    collisions
 }
 
 /// Used to find duplicates of entries in the hash tree file.
@@ -203,22 +639,27 @@ struct SetKey<'a> {
     ftype: &'a HashTreeFileEntryType,
     children: &'a Vec<GeneralHash>,
 }
-/// Write the result entry to the output file. Find all duplicates of the file and write them to the output file.
-/// If called for every file, it will write all duplicates to the output file.
-/// Writing each file only once
-fn write_result_entry(file: &AnalysisFile, file_by_hash: &HashMap<GeneralHash, Vec<Arc<HashTreeFileEntry>>>, output_buf_writer: &mut std::io::BufWriter<&fs::File>) -> u64 {
+/// Find all duplicate sets of the file. If called for every file, it will find all duplicates,
+/// each set only once.
+/// 
+/// Returns the sets with the number of bytes that could be reclaimed by keeping one file per set. With `physical_size`
+/// the size allocated on disk is used where it was captured, assuming the largest copy is kept.
+/// With `group_hardlinks` the hardlinks of a set are grouped and each group counts only once.
+/// The reclaimable bytes of files with a detected content type are also added to `bytes_by_type`.
+fn find_duplicate_sets<'a>(file: &AnalysisFile, file_by_hash: &'a HashMap<GeneralHash, Vec<Arc<HashTreeFileEntry>>>, physical_size: bool, group_hardlinks: bool, bytes_by_type: &mut HashMap<String, u64>) -> Vec<(DupSetEntryRef<'a, 'a, 'a>, u64)> {
     let hash = match file {
         AnalysisFile::File(info) => &info.content_hash,
         AnalysisFile::Directory(info) => &info.content_hash,
         AnalysisFile::Symlink(info) => &info.content_hash,
         AnalysisFile::Other(_) => {
-            return 0;
+            return Vec::new();
         }
     };
+    let (hash, files) = file_by_hash.get_key_value(hash).unwrap();
     
     let mut sets: HashMap<SetKey, Vec<&HashTreeFileEntry>> = HashMap::new();
 
-    for file in file_by_hash.get(hash).unwrap() {
+    for file in files {
         sets.entry(SetKey {
             size: file.size,
             ftype: &file.file_type,
@@ -226,7 +667,7 @@ fn write_result_entry(file: &AnalysisFile, file_by_hash: &HashMap<GeneralHash, V
         }).or_insert(Vec::new()).push(file);
     }
     
-    let mut result_size: u64 = 0;
+    let mut result = Vec::new();
     
     for set in &sets {
         if set.1.len() <= 1 {
@@ -243,17 +684,64 @@ fn write_result_entry(file: &AnalysisFile, file_by_hash: &HashMap<GeneralHash, V
             conflicting.push(&file.path);
         }
         
-        let result = DupSetEntryRef {
-            ftype: &set.0.ftype,
+        let groups = match group_hardlinks {
+            true => Some(self::group_hardlinks(set.1)),
+            false => None,
+        };
+        
+        // only one file per group of hardlinks occupies space
+        let stored: Vec<&HashTreeFileEntry> = match &groups {
+            Some(groups) => groups.iter().map(|group| group[0]).collect(),
+            None => set.1.clone(),
+        };
+        
+        let set_size = match physical_size {
+            true => {
+                let sizes = stored.iter().map(|file| file.physical_size.unwrap_or(file.size));
+                sizes.clone().sum::<u64>() - sizes.max().unwrap_or(0)
+            },
+            false => set.0.size * (stored.len() as u64 - 1),
+        };
+        
+        if let Some(content_type) = &set.1[0].content_type {
+            *bytes_by_type.entry(content_type.clone()).or_insert(0) += set_size;
+        }
+
+        result.push((DupSetEntryRef {
+            ftype: set.0.ftype,
             size: set.0.size,
             hash,
             conflicting,
-        };
-        output_buf_writer.write(serde_json::to_string(&result).unwrap().as_bytes()).expect("Unable to write to file");
-        output_buf_writer.write('\n'.to_string().as_bytes()).expect("Unable to write to file");
-
-        result_size += result.size * (result.conflicting.len() as u64 - 1);
+            hardlinks: groups.map(|groups| groups.iter().map(|group| group.iter().map(|file| &file.path).collect()).collect()),
+        }, set_size));
     }
     
-    return result_size;
+    return result;
+}
+
+/// Keep only the sets with the most reclaimable bytes, ordered by them (largest first).
+/// Sets with equal bytes keep their order. Does nothing if there are not more sets than allowed.
+///
+/// # Arguments
+/// * `sets` - The sets with their reclaimable bytes.
+/// * `max_sets` - The maximum number of sets to keep.
+///
+/// # Example
+/// ```
+/// use backup_deduplicator::stages::analyze::cmd::keep_largest_sets;
+///
+/// let mut sets = vec![("a", 10), ("b", 30), ("c", 20), ("d", 30)];
+/// keep_largest_sets(&mut sets, 3);
+/// assert_eq!(sets, vec![("b", 30), ("d", 30), ("c", 20)]);
+///
+/// keep_largest_sets(&mut sets, 5);
+/// assert_eq!(sets.len(), 3);
+/// ```
+pub fn keep_largest_sets<T>(sets: &mut Vec<(T, u64)>, max_sets: usize) {
+    if sets.len() <= max_sets {
+        return;
+    }
+
+    sets.sort_by_key(|set| std::cmp::Reverse(set.1));
+    sets.truncate(max_sets);
 }
@@ -10,10 +10,14 @@ use crate::stages::build::output::HashTreeFileEntryType;
 /// * `size` - The size of the file.
 /// * `hash` - The hash of the file content.
 /// * `conflicting` - The conflicting files.
+/// * `hardlinks` - The conflicting files grouped by the file they link to, if requested. Every group
+///   is stored only once on disk; files of different groups are true duplicates.
 #[derive(Debug, Serialize)]
 pub struct DupSetEntryRef<'a, 'b, 'c> {
     pub ftype: &'a HashTreeFileEntryType,
     pub size: u64,
     pub hash: &'b GeneralHash,
     pub conflicting: Vec<&'c FilePath>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardlinks: Option<Vec<Vec<&'c FilePath>>>,
 }
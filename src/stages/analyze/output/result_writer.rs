@@ -0,0 +1,281 @@
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use crate::stages::analyze::output::{DupSetEntryRef, UniqueEntryRef};
+
+/// The format of an analysis output file.
+///
+/// # Variants
+/// * `Json` - One JSON document per result entry (native format).
+/// * `Csv` - A CSV table with one row per file, for reading by humans or spreadsheets.
+/// * `Tsv` - A tab separated table with one row per file and the columns [TSV_COLUMNS], see [tsv_field].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnalysisFormat {
+    #[default]
+    Json,
+    Csv,
+    Tsv,
+}
+
+/// The columns of TSV outputs, the same for duplicate sets and unique files.
+pub const TSV_COLUMNS: [&str; 4] = ["hash", "size", "type", "path"];
+
+impl FromStr for AnalysisFormat {
+    /// Error type for parsing an `AnalysisFormat` from a string.
+    type Err = &'static str;
+
+    /// Parses a string into an `AnalysisFormat`.
+    ///
+    /// # Arguments
+    /// * `s` - The string to parse.
+    ///
+    /// # Returns
+    /// The `AnalysisFormat` that corresponds to the string or an error.
+    ///
+    /// # Errors
+    /// Returns the available formats if the string does not correspond to an `AnalysisFormat`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::str::FromStr;
+    /// use backup_deduplicator::stages::analyze::output::AnalysisFormat;
+    ///
+    /// assert_eq!(AnalysisFormat::from_str("CSV"), Ok(AnalysisFormat::Csv));
+    /// assert_eq!(AnalysisFormat::from_str("json"), Ok(AnalysisFormat::Json));
+    /// assert_eq!(AnalysisFormat::from_str("tsv"), Ok(AnalysisFormat::Tsv));
+    /// assert!(AnalysisFormat::from_str("xml").is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(AnalysisFormat::Json),
+            "csv" => Ok(AnalysisFormat::Csv),
+            "tsv" => Ok(AnalysisFormat::Tsv),
+            _ => Err("json, csv, tsv"),
+        }
+    }
+}
+
+impl Display for AnalysisFormat {
+    /// Converts an `AnalysisFormat` into a string.
+    ///
+    /// # Arguments
+    /// * `f` - The formatter to write to.
+    ///
+    /// # Returns
+    /// A result indicating whether the operation was successful.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalysisFormat::Json => write!(f, "json"),
+            AnalysisFormat::Csv => write!(f, "csv"),
+            AnalysisFormat::Tsv => write!(f, "tsv"),
+        }
+    }
+}
+
+/// An analysis output file.
+///
+/// # Fields
+/// * `path` - The path of the output file.
+/// * `format` - The format to write.
+#[derive(Debug, Clone)]
+pub struct AnalysisOutput {
+    pub path: PathBuf,
+    pub format: AnalysisFormat,
+}
+
+/// Writes analysis results to several output files at once, each in its own format.
+/// The results are computed once and serialized for every output.
+///
+/// # Fields
+/// * `outputs` - The opened output files and their formats.
+/// * `pretty` - Whether to write indented JSON.
+/// * `sets` - The number of duplicate sets written so far, used as set id in CSV outputs.
+pub struct AnalysisResultWriter {
+    outputs: Vec<(BufWriter<fs::File>, AnalysisFormat)>,
+    pretty: bool,
+    sets: usize,
+}
+
+impl AnalysisResultWriter {
+    /// Create (or truncate) all output files.
+    ///
+    /// # Arguments
+    /// * `outputs` - The output files to write.
+    /// * `pretty` - Whether to write indented JSON. Each entry then spans multiple lines.
+    ///
+    /// # Returns
+    /// The result writer.
+    ///
+    /// # Errors
+    /// If an output file cannot be opened.
+    pub fn create(outputs: &[AnalysisOutput], pretty: bool) -> Result<Self> {
+        let mut files = Vec::with_capacity(outputs.len());
+
+        for output in outputs {
+            let file = fs::File::create(&output.path).with_context(|| format!("Failed to open output file {:?}", output.path))?;
+            files.push((BufWriter::new(file), output.format));
+        }
+
+        Ok(AnalysisResultWriter {
+            outputs: files,
+            pretty,
+            sets: 0,
+        })
+    }
+
+    /// Write the header row of all CSV and TSV outputs. TSV outputs always have the columns [TSV_COLUMNS].
+    ///
+    /// # Arguments
+    /// * `columns` - The column names of CSV outputs.
+    ///
+    /// # Errors
+    /// If writing to an output file fails.
+    pub fn write_csv_header(&mut self, columns: &[&str]) -> Result<()> {
+        for (writer, format) in &mut self.outputs {
+            match format {
+                AnalysisFormat::Json => {},
+                AnalysisFormat::Csv => writeln!(writer, "{}", columns.join(","))?,
+                AnalysisFormat::Tsv => writeln!(writer, "{}", TSV_COLUMNS.join("\t"))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write a duplicate set. CSV outputs get one row per conflicting file
+    /// with the columns `ftype,size,hash,set,path`, TSV outputs one row per conflicting file
+    /// with the columns [TSV_COLUMNS].
+    ///
+    /// # Arguments
+    /// * `set` - The duplicate set.
+    ///
+    /// # Errors
+    /// If writing to an output file fails.
+    pub fn write_duplicate_set(&mut self, set: &DupSetEntryRef) -> Result<()> {
+        self.sets += 1;
+
+        for (writer, format) in &mut self.outputs {
+            match format {
+                AnalysisFormat::Json => write_json(writer, set, self.pretty)?,
+                AnalysisFormat::Csv => {
+                    for path in &set.conflicting {
+                        writeln!(writer, "{:?},{},{},{},{}", set.ftype, set.size, set.hash, self.sets, csv_field(&path.to_string()))?;
+                    }
+                },
+                AnalysisFormat::Tsv => {
+                    for path in &set.conflicting {
+                        writeln!(writer, "{}\t{}\t{:?}\t{}", set.hash, set.size, set.ftype, tsv_field(&path.to_string()))?;
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write a file that is unique to the analyzed hash tree. CSV outputs get
+    /// a row with the columns `ftype,size,hash,path`, TSV outputs a row with the columns [TSV_COLUMNS].
+    ///
+    /// # Arguments
+    /// * `entry` - The unique file.
+    ///
+    /// # Errors
+    /// If writing to an output file fails.
+    pub fn write_unique(&mut self, entry: &UniqueEntryRef) -> Result<()> {
+        for (writer, format) in &mut self.outputs {
+            match format {
+                AnalysisFormat::Json => write_json(writer, entry, self.pretty)?,
+                AnalysisFormat::Csv => writeln!(writer, "{:?},{},{},{}", entry.ftype, entry.size, entry.hash, csv_field(&entry.path.to_string()))?,
+                AnalysisFormat::Tsv => writeln!(writer, "{}\t{}\t{:?}\t{}", entry.hash, entry.size, entry.ftype, tsv_field(&entry.path.to_string()))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flush all output files.
+    ///
+    /// # Errors
+    /// If flushing an output file fails.
+    pub fn flush(&mut self) -> Result<()> {
+        for (writer, _) in &mut self.outputs {
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write an entry as JSON followed by a newline.
+///
+/// # Arguments
+/// * `writer` - The writer to write to.
+/// * `value` - The entry to write.
+/// * `pretty` - Whether to write indented JSON spanning multiple lines.
+///
+/// # Errors
+/// If the entry cannot be serialized or written.
+fn write_json<W: Write, T: Serialize>(writer: &mut W, value: &T, pretty: bool) -> Result<()> {
+    match pretty {
+        true => serde_json::to_writer_pretty(&mut *writer, value)?,
+        false => serde_json::to_writer(&mut *writer, value)?,
+    }
+    writer.write_all(b"\n")?;
+
+    Ok(())
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break.
+///
+/// # Arguments
+/// * `value` - The field value.
+///
+/// # Returns
+/// The field as it can be written to a CSV file.
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Escape a TSV field. Backslashes, tabs and line breaks are written as `\\`, `\t`, `\n` and `\r`,
+/// so that every row is one line with exactly one tab between two fields.
+///
+/// # Arguments
+/// * `value` - The field value.
+///
+/// # Returns
+/// The field as it can be written to a TSV file.
+///
+/// # Example
+/// ```
+/// use backup_deduplicator::stages::analyze::output::tsv_field;
+///
+/// assert_eq!(tsv_field("photos, 2024/a.jpg"), "photos, 2024/a.jpg");
+/// assert_eq!(tsv_field("a\tb\nc"), "a\\tb\\nc");
+/// assert_eq!(tsv_field("C:\\temp"), "C:\\\\temp");
+/// ```
+pub fn tsv_field(value: &str) -> Cow<'_, str> {
+    if !value.contains(['\\', '\t', '\n', '\r']) {
+        return Cow::Borrowed(value);
+    }
+
+    let mut escaped = String::with_capacity(value.len() + 2);
+    for character in value.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            character => escaped.push(character),
+        }
+    }
+    Cow::Owned(escaped)
+}
@@ -0,0 +1,19 @@
+use serde::{Serialize};
+use crate::hash::GeneralHash;
+use crate::path::FilePath;
+use crate::stages::build::output::HashTreeFileEntryType;
+
+/// A file whose content is not present in the compared hash tree.
+/// 
+/// # Fields
+/// * `ftype` - The type of the file.
+/// * `size` - The size of the file.
+/// * `hash` - The hash of the file content.
+/// * `path` - The path of the file.
+#[derive(Debug, Serialize)]
+pub struct UniqueEntryRef<'a> {
+    pub ftype: &'a HashTreeFileEntryType,
+    pub size: u64,
+    pub hash: &'a GeneralHash,
+    pub path: &'a FilePath,
+}
@@ -2,13 +2,28 @@
 pub mod output {
     pub mod converter;
     mod hashtreefile;
+    pub mod shard;
+    pub mod sink;
     
     pub use hashtreefile::*;
 }
 
 pub mod cmd {
+    pub mod cache;
     mod cmd;
+    pub mod device_filter;
+    pub mod dry_run;
+    pub mod exclude;
+    pub mod file_list;
+    pub mod preallocate;
     pub mod job;
+    pub mod output_order;
+    pub mod progress;
+    pub mod size_filter;
+    pub mod snapshot;
+    pub mod walk;
+    pub mod warning;
+    pub mod watch;
     pub mod worker;
     
     pub use cmd::*;
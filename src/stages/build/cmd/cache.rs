@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufRead, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, Result};
+use log::warn;
+use crate::path::FilePath;
+use crate::stages::build::output::HashTreeFileEntry;
+
+/// The entries of a previous build, looked up by path to skip hashing unchanged files.
+pub trait BuildCache: Send + Sync {
+    /// Look up the last entry of a path.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the file/directory.
+    ///
+    /// # Returns
+    /// The entry or None if the path is not cached.
+    fn get(&self, path: &FilePath) -> Option<Arc<HashTreeFileEntry>>;
+}
+
+/// Keeps all entries of the previous build in memory. The default.
+impl BuildCache for HashMap<FilePath, Arc<HashTreeFileEntry>> {
+    fn get(&self, path: &FilePath) -> Option<Arc<HashTreeFileEntry>> {
+        HashMap::get(self, path).cloned()
+    }
+}
+
+/// Keeps only the offsets of the entries of the previous build in memory and reads
+/// an entry from the hash tree file when it is looked up.
+///
+/// The index is keyed by a 64-bit hash of the path. If two paths share a hash, only
+/// the later one is found, the other one is hashed again like an uncached file.
+///
+/// # Fields
+/// * `offsets` - The offset of the last entry per path hash.
+/// * `file` - The hash tree file the entries are read from.
+pub struct DiskBuildCache {
+    offsets: HashMap<u64, u64>,
+    file: Mutex<std::io::BufReader<fs::File>>,
+}
+
+impl DiskBuildCache {
+    /// Open a hash tree file for reading cached entries.
+    ///
+    /// # Arguments
+    /// * `path` - The hash tree file. Entries appended later do not affect the indexed entries.
+    ///
+    /// # Returns
+    /// The cache without any indexed entry.
+    ///
+    /// # Errors
+    /// If the file cannot be opened.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = fs::File::open(path).map_err(|err| anyhow!("Failed to open result file for the build cache: {}", err))?;
+
+        Ok(DiskBuildCache {
+            offsets: HashMap::new(),
+            file: Mutex::new(std::io::BufReader::new(file)),
+        })
+    }
+
+    /// Index an entry. A later entry of the same path replaces the earlier one.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the entry.
+    /// * `offset` - The offset of the entry line in the hash tree file.
+    pub fn insert(&mut self, path: &FilePath, offset: u64) {
+        self.offsets.insert(path_key(path), offset);
+    }
+
+    /// Get the number of indexed entries.
+    ///
+    /// # Returns
+    /// The number of indexed entries.
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Check if no entry is indexed.
+    ///
+    /// # Returns
+    /// True if no entry is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Read the entry at an offset.
+    ///
+    /// # Arguments
+    /// * `offset` - The offset of the entry line.
+    ///
+    /// # Returns
+    /// The entry.
+    ///
+    /// # Errors
+    /// If the file cannot be read or the line is not an entry.
+    fn read_entry(&self, offset: u64) -> Result<HashTreeFileEntry> {
+        let mut file = self.file.lock().map_err(|_| anyhow!("Failed to lock the build cache file"))?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut line = String::new();
+        file.read_line(&mut line)?;
+
+        Ok(serde_json::from_str(line.as_str())?)
+    }
+}
+
+impl BuildCache for DiskBuildCache {
+    fn get(&self, path: &FilePath) -> Option<Arc<HashTreeFileEntry>> {
+        let offset = *self.offsets.get(&path_key(path))?;
+
+        match self.read_entry(offset) {
+            Ok(entry) if &entry.path == path => Some(Arc::new(entry)),
+            Ok(_) => None,
+            Err(err) => {
+                warn!("Failed to read cached entry of {:?}: {}", path, err);
+                None
+            }
+        }
+    }
+}
+
+/// Hash a path for the index of the [DiskBuildCache].
+///
+/// # Arguments
+/// * `path` - The path.
+///
+/// # Returns
+/// The hash of the path. Equal (normalized) paths have equal hashes.
+fn path_key(path: &FilePath) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    hasher.finish()
+}
@@ -1,26 +1,96 @@
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fs;
-use std::path::{PathBuf};
-use std::sync::Arc;
-use anyhow::{anyhow, Result};
-use crate::hash::GeneralHashType;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Context, Result};
+use log::{error, info, warn};
+use serde::Serialize;
+use crate::hash::{DirHashMode, FileIdentity, GeneralHashType};
 use crate::path::{FilePath};
 use crate::pool::ThreadPool;
+use crate::stages::build::cmd::cache::{BuildCache, DiskBuildCache};
+use crate::stages::build::cmd::device_filter::DeviceFilter;
+use crate::stages::build::cmd::exclude::{is_excluded, resolve_excluded};
 use crate::stages::build::cmd::job::{BuildJob, JobResult};
+use crate::stages::build::cmd::output_order::{sort_entries, OutputOrder};
+use crate::stages::build::cmd::preallocate::{estimate_output_size, preallocate};
+use crate::stages::build::cmd::snapshot::{Snapshot, SnapshotKind};
+use crate::stages::build::cmd::progress::{BuildProgress, ProgressReporter};
+use crate::stages::build::cmd::size_filter::collect_shared_sizes;
+use crate::stages::build::cmd::walk::Walk;
+use crate::stages::build::cmd::warning::WarningSink;
 use crate::stages::build::cmd::worker::{worker_run, WorkerArgument};
+use crate::stages::build::intermediary_build_data::BuildFile;
 use crate::stages::build::output::{HashTreeFile, HashTreeFileEntry, HashTreeFileEntryRef};
+use crate::stages::build::output::sink::{write_to_sinks, EntrySink};
+use crate::utils::{NullWriter, Semaphore};
 
 /// The settings for the build command.
 /// 
 /// # Fields
 /// * `directory` - The directory to build.
+/// * `files` - If set, exactly these files and directories are hashed instead of `directory`, each as its own root
+///   without a common parent entry. Paths that do not exist get error entries.
 /// * `follow_symlinks` - Whether to follow symlinks when traversing the file system.
 /// * `output` - The output file to write the hash tree to.
 /// * `threads` - The number of threads to use for building the hash tree. None = number of logical CPUs.
 /// * `hash_type` - The hash algorithm to use for hashing files.
 /// * `continue_file` - Whether to continue an existing hash tree file.
+/// * `hash_type_by_extension` - Hash algorithm overrides by lowercase file extension (without leading dot).
+///   If not empty, the hash tree file is marked as containing mixed hash types.
+/// * `dir_hash_mode` - How directory hashes are computed from their children.
+/// * `file_identity` - What file hashes are computed from, i.e. whether the file name is mixed into the content hash.
+/// * `checkpoint_interval` - If set, a checkpoint marker is written and the output file is synced to disk in this interval.
+/// * `one_filesystem` - Whether to skip directories on another filesystem (device) than the build directory.
+/// * `exclude_devices` - Directories on the same device as one of these paths are skipped.
+/// * `exclude_files` - Further files written by the caller next to the output (e.g. shards or a summary) that are not
+///   hashed if they are below a root, see [is_excluded]. The output itself is never hashed.
+/// * `verify_after` - Whether to re-read the output file after the build and check that all entries can be loaded.
+/// * `capture_physical_size` - Whether to record the size allocated on disk for every file (Unix only).
+/// * `capture_xattrs` - Whether to record a hash of the extended attributes of every file and directory (Linux only).
+/// * `capture_file_ids` - Whether to record the inode and device of every file, to recognize hardlinks in the analysis.
+/// * `capture_btime` - Whether to record the creation (birth) time of every file and directory, where supported.
+/// * `dedup_optimized` - Whether to collect the sizes of all files first and only hash files whose size is shared
+///   by another file, see [collect_shared_sizes]. The result can only be used to find duplicates within the tree.
+/// * `detect_content_type` - Whether to detect the MIME type of every file from its leading bytes, see [detect_content_type](crate::content_type::detect_content_type).
+/// * `mtime_tolerance` - Modification times that differ from the ones of the continued hash tree by at most this
+///   many seconds are treated as unchanged, see [modified_matches](crate::stages::build::cmd::worker::modified_matches).
+/// * `since` - If set, files last modified before this time (in seconds since the Unix epoch) are not hashed but
+///   recorded as `Other` entries. Unchanged files are still taken from the continued hash tree, directories are
+///   always traversed.
+/// * `mmap_threshold` - If set, files of at least this size in bytes are memory mapped for hashing instead of being read
+///   through a buffer. Recently modified files are always read. Requires the `mmap` feature.
+/// * `external_symlinks_as_copy` - Whether symlinks to regular files outside of the build roots are recorded
+///   as files with the content hash of their target, as if the target was copied into the tree.
+/// * `preallocate` - Whether to estimate the size of the result file before the build and reserve the disk space
+///   for it (Linux only), see [estimate_output_size]. The unused space is released at the end of the build.
+/// * `pin_threads` - Whether to pin every worker thread to its own CPU core, see [ThreadPool::new_pinned].
+///   Requires the `pin-threads` feature.
+/// * `snapshot` - If set, every build root is read from a snapshot taken with this tool, released after the
+///   build, see [Snapshot]. Roots that can not be snapshotted are read directly.
+/// * `output_order` - The order the entries of this build are written in, see [OutputOrder]. Every order except
+///   `Arrival` keeps all entries in memory and writes them when the build ends or is cancelled, so checkpoints
+///   do not contain them and a crash loses them.
+/// * `sinks` - Further destinations every entry written to the result file is also passed to, e.g. to stream
+///   the hash tree into a database, see [EntrySink]. Called on the thread running the build.
+/// * `warnings` - If set, notable problems the build recovered from (e.g. unreadable files or duplicate entries in the
+///   continued hash tree) are sent to this channel in addition to logging them, see [BuildWarning].
+/// * `cancel` - If set, the build stops as soon as the flag becomes true, e.g. set from another thread by an
+///   application embedding the build. See [run] for the state of the result file.
+/// * `max_memory` - If set and the result file to continue is larger than this size in bytes, the entries of the
+///   previous build are read from disk when looked up instead of being kept in memory, see [DiskBuildCache].
+/// * `max_open_files` - If set, the number of files and directories opened by the workers at the same time
+///   is bounded to this number, regardless of the number of threads.
+/// * `progress_bar` - Whether to render a progress bar on stderr instead of logging the progress, if stderr is a terminal.
+/// * `progress_interval` - The interval in which the progress is logged if no progress bar is rendered.
+/// * `eta` - Whether to count the files discovered while listing directories, to report the progress in percent and an estimated remaining time.
 pub struct BuildSettings {
     pub directory: PathBuf,
+    pub files: Option<Vec<PathBuf>>,
     // pub into_archives: bool,
     pub follow_symlinks: bool,
     pub output: PathBuf,
@@ -29,23 +99,286 @@ pub struct BuildSettings {
     
     pub hash_type: GeneralHashType,
     pub continue_file: bool,
+    pub hash_type_by_extension: HashMap<String, GeneralHashType>,
+    pub dir_hash_mode: DirHashMode,
+    pub file_identity: FileIdentity,
+    pub eta: bool,
+    pub progress_bar: bool,
+    pub progress_interval: Duration,
+    pub checkpoint_interval: Option<Duration>,
+    pub verify_after: bool,
+    pub one_filesystem: bool,
+    pub exclude_devices: Vec<PathBuf>,
+    pub exclude_files: Vec<PathBuf>,
+    pub capture_physical_size: bool,
+    pub max_open_files: Option<usize>,
+    pub max_memory: Option<u64>,
+    pub capture_xattrs: bool,
+    pub capture_file_ids: bool,
+    pub capture_btime: bool,
+    pub dedup_optimized: bool,
+    pub detect_content_type: bool,
+    pub mtime_tolerance: u64,
+    pub since: Option<u64>,
+    pub mmap_threshold: Option<u64>,
+    pub external_symlinks_as_copy: bool,
+    pub preallocate: bool,
+    pub pin_threads: bool,
+    pub snapshot: Option<SnapshotKind>,
+    pub output_order: OutputOrder,
+    pub sinks: Vec<Box<dyn EntrySink>>,
+    pub warnings: WarningSink,
+    pub cancel: Option<Arc<AtomicBool>>,
 }
 
+/// The outcome of a completed build.
+///
+/// # Fields
+/// * `cancelled` - Whether the build was cancelled before it completed.
+/// * `summary` - What the build processed, see [BuildSummary].
+/// * `unreadable` - The files and directories that could not be read, in the order they occurred.
+#[derive(Debug, Clone)]
+pub struct BuildOutcome {
+    pub cancelled: bool,
+    pub summary: BuildSummary,
+    pub unreadable: Vec<UnreadableFile>,
+}
+
+impl BuildOutcome {
+    /// Write the files and directories that could not be read as JSON array to a file, replacing its content.
+    ///
+    /// # Arguments
+    /// * `path` - The file to write to.
+    ///
+    /// # Errors
+    /// If the file cannot be written.
+    pub fn save_unreadable(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.unreadable)?;
+        fs::write(path, json + "\n")?;
+
+        Ok(())
+    }
+}
+
+/// A file or directory that could not be read during the build. It is recorded as `Other` entry.
+///
+/// # Fields
+/// * `path` - The path of the file or directory.
+/// * `occurred_at` - The time of the error in unix time.
+/// * `reason` - The description of the error, e.g. a missing permission.
+#[derive(Debug, Clone, Serialize)]
+pub struct UnreadableFile {
+    pub path: FilePath,
+    pub occurred_at: u64,
+    pub reason: String,
+}
+
+/// A machine-readable summary of what a build processed, e.g. to monitor incremental builds.
+///
+/// # Fields
+/// * `hash_type` - The hash algorithm used for hashing files.
+/// * `files` - The number of processed files, including the ones reused from the continued hash tree.
+/// * `directories` - The number of processed directories.
+/// * `symlinks` - The number of processed symlinks.
+/// * `others` - The number of other entries, like devices or entries that could not be read.
+/// * `bytes` - The total size of the processed files in bytes.
+/// * `cached` - The number of entries reused unchanged from the continued hash tree, without rehashing.
+/// * `errors` - The number of files and directories that could not be read. They are
+///   recorded as `Other` entries and their parent directories hash them as unreadable.
+/// * `elapsed_seconds` - The duration of the build.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BuildSummary {
+    pub hash_type: GeneralHashType,
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub others: u64,
+    pub bytes: u64,
+    pub cached: u64,
+    pub errors: u64,
+    pub elapsed_seconds: f64,
+}
+
+impl BuildSummary {
+    /// Create an empty summary.
+    ///
+    /// # Arguments
+    /// * `hash_type` - The hash algorithm used for hashing files.
+    ///
+    /// # Returns
+    /// The summary without any processed entry.
+    fn new(hash_type: GeneralHashType) -> Self {
+        BuildSummary {
+            hash_type,
+            files: 0,
+            directories: 0,
+            symlinks: 0,
+            others: 0,
+            bytes: 0,
+            cached: 0,
+            errors: 0,
+            elapsed_seconds: 0.0,
+        }
+    }
+
+    /// Count a processed entry.
+    ///
+    /// # Arguments
+    /// * `file` - The processed entry.
+    /// * `cached` - Whether the entry was reused from the continued hash tree.
+    fn add(&mut self, file: &BuildFile, cached: bool) {
+        match file {
+            BuildFile::File(info) => {
+                self.files += 1;
+                self.bytes += info.content_size;
+            },
+            BuildFile::Directory(_) => self.directories += 1,
+            BuildFile::Symlink(_) => self.symlinks += 1,
+            BuildFile::Other(_) => self.others += 1,
+            BuildFile::Stub(_) => {},
+        }
+
+        if cached {
+            self.cached += 1;
+        }
+    }
+
+    /// Log the summary at info level.
+    pub fn log(&self) {
+        info!("Build summary: {} files ({} MB), {} directories, {} symlinks, {} others, {} reused from the hash tree, {} errors, {:.1}s ({})",
+            self.files, self.bytes / 1024 / 1024, self.directories, self.symlinks, self.others, self.cached, self.errors, self.elapsed_seconds, self.hash_type);
+    }
+
+    /// Write the summary as JSON document to a file, replacing its content.
+    ///
+    /// # Arguments
+    /// * `path` - The file to write to.
+    ///
+    /// # Errors
+    /// If the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json + "\n")?;
+        
+        Ok(())
+    }
+}
+
+/// Interval in which a running build checks whether it was cancelled while waiting for results.
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
 /// Runs the build command. Hashes a directory and produces a hash tree file.
 /// 
+/// If the build is cancelled (see [BuildSettings::cancel]), the workers skip their remaining jobs
+/// and the function returns as soon as the running jobs finished. Every entry written so far is
+/// complete, so the result file can be continued by a later build that only hashes the rest.
+/// 
 /// # Arguments
 /// * `build_settings` - The settings for the build command.
 /// 
 /// # Returns
-/// Nothing
+/// The outcome of the build, see [BuildOutcome].
 /// 
 /// # Errors
 /// * If the output file cannot be opened.
 /// * If the header cannot be loaded from the output file (if the file is continued).
 /// * If the output file cannot be written to.
+/// * If a worker panics while processing a file.
+/// * If the verification of the output file fails (if enabled).
+/// 
+/// # Example
+/// ```
+/// use std::collections::HashMap;
+/// use std::fs;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::time::Duration;
+/// use backup_deduplicator::hash::{DirHashMode, FileIdentity, GeneralHashType};
+/// use backup_deduplicator::stages::build::cmd::{run, BuildSettings};
+/// use backup_deduplicator::stages::build::cmd::output_order::OutputOrder;
+///
+/// let directory = std::env::temp_dir().join(format!("bdd-cancel-example-{}", std::process::id()));
+/// for dir in 0..20 {
+///     fs::create_dir_all(directory.join(dir.to_string())).unwrap();
+///     for file in 0..20 {
+///         fs::write(directory.join(dir.to_string()).join(file.to_string()), vec![file as u8; 4096]).unwrap();
+///     }
+/// }
+/// let output = directory.with_extension("bdd");
+///
+/// let settings = |cancel: Arc<AtomicBool>| BuildSettings {
+///     directory: directory.clone(),
+///     files: None,
+///     follow_symlinks: false,
+///     output: output.clone(),
+///     threads: Some(2),
+///     hash_type: GeneralHashType::NULL,
+///     continue_file: true,
+///     hash_type_by_extension: HashMap::new(),
+///     dir_hash_mode: DirHashMode::ContentOnly,
+///     file_identity: FileIdentity::Content,
+///     eta: false,
+///     progress_bar: false,
+///     progress_interval: Duration::from_secs(10),
+///     checkpoint_interval: None,
+///     verify_after: true,
+///     one_filesystem: false,
+///     exclude_devices: Vec::new(),
+///     exclude_files: Vec::new(),
+///     capture_physical_size: false,
+///     max_open_files: None,
+///     max_memory: None,
+///     capture_xattrs: false,
+///     capture_file_ids: false,
+///     capture_btime: false,
+///     dedup_optimized: false,
+///     detect_content_type: false,
+///     mtime_tolerance: 0,
+///     since: None,
+///     mmap_threshold: None,
+///     external_symlinks_as_copy: false,
+///     preallocate: false,
+///     pin_threads: false,
+///     snapshot: None,
+///     output_order: OutputOrder::Arrival,
+///     sinks: Vec::new(),
+///     warnings: None,
+///     cancel: Some(cancel),
+/// };
+///
+/// // cancel from another thread while the build is running
+/// let cancel = Arc::new(AtomicBool::new(false));
+/// let canceller = {
+///     let cancel = Arc::clone(&cancel);
+///     std::thread::spawn(move || {
+///         std::thread::sleep(Duration::from_millis(1));
+///         cancel.store(true, Ordering::Relaxed);
+///     })
+/// };
+/// let outcome = run(settings(cancel)).unwrap();
+/// canceller.join().unwrap();
+/// assert_eq!(outcome.summary.errors, 0);
+///
+/// // a build that is cancelled right away returns without hashing anything
+/// let outcome = run(settings(Arc::new(AtomicBool::new(true)))).unwrap();
+/// assert!(outcome.cancelled);
+///
+/// // the result file is continued
+/// let outcome = run(settings(Arc::new(AtomicBool::new(false)))).unwrap();
+/// assert!(!outcome.cancelled);
+/// assert_eq!(outcome.summary.files, 400);
+/// assert_eq!(outcome.summary.directories, 21);
+///
+/// // nothing changed, every entry is reused
+/// let outcome = run(settings(Arc::new(AtomicBool::new(false)))).unwrap();
+/// assert_eq!(outcome.summary.cached, 421);
+///
+/// fs::remove_dir_all(&directory).unwrap();
+/// fs::remove_file(&output).unwrap();
+/// ```
 pub fn run(
     build_settings: BuildSettings,
-) -> Result<()> {
+) -> Result<BuildOutcome> {
     let existed = build_settings.output.exists();
     let mut result_file_options = fs::File::options();
     
@@ -57,23 +390,48 @@ pub fn run(
         result_file_options.write(true);
     }
     
-    let result_file = match result_file_options.open(build_settings.output) {
-        Ok(file) => file,
-        Err(err) => {
-            return Err(anyhow!("Failed to open result file: {}", err));
-        }
-    };
+    let result_file = result_file_options.open(&build_settings.output).context("Failed to open result file")?;
     
     // create buf reader and writer
     let mut result_in = std::io::BufReader::new(&result_file);
     let mut result_out = std::io::BufWriter::new(&result_file);
     
-    let mut save_file = HashTreeFile::new(&mut result_out, &mut result_in, build_settings.hash_type, false, true, false);
+    let mixed_hashes = !build_settings.hash_type_by_extension.is_empty();
+    
+    // the in-memory cache holds every entry, estimate its size by the size of the file
+    let use_disk_cache = match build_settings.max_memory {
+        Some(max_memory) => build_settings.continue_file && existed && fs::metadata(&build_settings.output).map(|metadata| metadata.len() > max_memory).unwrap_or(false),
+        None => false,
+    };
+    
+    let mut save_file = HashTreeFile::new(&mut result_out, &mut result_in, build_settings.hash_type, false, !use_disk_cache, false);
+    save_file.header.mixed_hashes = mixed_hashes;
+    save_file.header.dir_hash_mode = build_settings.dir_hash_mode;
+    save_file.header.file_identity = build_settings.file_identity;
+    save_file.header.dedup_optimized = build_settings.dedup_optimized;
+    save_file.set_warnings(build_settings.warnings.clone());
     match save_file.load_header() {
-        Ok(_) => {},
+        Ok(_) => {
+            if save_file.header.anonymized {
+                return Err(anyhow!("The result file contains anonymized paths and can not be continued. Delete the output file or provide the --overwrite flag to overwrite it"));
+            }
+            // a file with a unique size in the last build may have a duplicate now
+            if save_file.header.dedup_optimized || build_settings.dedup_optimized {
+                return Err(anyhow!("Dedup optimized builds can not be continued. Delete the output file or provide the --overwrite flag to overwrite it"));
+            }
+            if mixed_hashes && !save_file.header.mixed_hashes {
+                return Err(anyhow!("The result file was not created with mixed hash types, hash algorithm overrides can not be used. Delete the output file or provide the --overwrite flag to overwrite it"));
+            }
+            if save_file.header.dir_hash_mode != build_settings.dir_hash_mode {
+                return Err(anyhow!("The result file was created with directory hash mode {}, but {} was requested. Delete the output file or provide the --overwrite flag to overwrite it", save_file.header.dir_hash_mode, build_settings.dir_hash_mode));
+            }
+            if save_file.header.file_identity != build_settings.file_identity {
+                return Err(anyhow!("The result file was created with file identity {}, but {} was requested. Delete the output file or provide the --overwrite flag to overwrite it", save_file.header.file_identity, build_settings.file_identity));
+            }
+        },
         Err(err) => {
             if build_settings.continue_file && existed {
-                return Err(anyhow!("Failed to load header from result file: {}. Delete the output file or provide the --override flag to override", err));
+                return Err(anyhow!("Failed to load header from result file: {}. Delete the output file or provide the --overwrite flag to overwrite it", err));
             } else {
                 save_file.save_header()?;
             }
@@ -81,22 +439,136 @@ pub fn run(
     }
     
     // load all existing entries from the hash tree file
-    match save_file.load_all_entries_no_filter() {
+    let loaded_entries = Cell::new(0usize);
+    let count_entry = |_: &HashTreeFileEntry| {
+        loaded_entries.set(loaded_entries.get() + 1);
+        true
+    };
+    
+    let mut disk_cache = match use_disk_cache {
+        true => Some(DiskBuildCache::open(&build_settings.output)?),
+        false => None,
+    };
+    
+    let loaded = match &mut disk_cache {
+        Some(disk_cache) => {
+            info!("The result file exceeds the memory limit, reading cached entries from disk");
+            loop {
+                match save_file.load_entry(count_entry) {
+                    Ok(Some(entry)) => disk_cache.insert(&entry.path, save_file.get_last_entry_offset() as u64),
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            }
+        },
+        None => save_file.load_all_entries(count_entry),
+    };
+    
+    match loaded {
         Ok(_) => {},
         Err(err) => {
-            return Err(anyhow!("Failed to load entries from result file: {}. Delete the output file or provide the --override flag to override", err));
+            return Err(anyhow!("Failed to load entries from result file: {}. Delete the output file or provide the --overwrite flag to overwrite it", err));
         }
     }
+    
+    // drop an entry that was only partially written when the last run was interrupted
+    if build_settings.continue_file && save_file.has_incomplete_tail() {
+        warn!("Truncating the incomplete last entry of the result file");
+        result_file.set_len(save_file.get_read_bytes() as u64)?;
+    }
 
     // dont need hash -> file mapping
     save_file.empty_file_by_hash();
     save_file.empty_entry_list();
     
-    let mut file_by_hash: HashMap<FilePath, HashTreeFileEntry> = HashMap::with_capacity(save_file.file_by_hash.len());
-    save_file.file_by_path.drain().for_each(|(k, v)| {
-        file_by_hash.insert(k, Arc::into_inner(v).expect("There should be no further references to the entry"));
-    });
-    let file_by_hash = Arc::new(file_by_hash);
+    let cache: Arc<dyn BuildCache> = match disk_cache {
+        Some(disk_cache) => Arc::new(disk_cache),
+        None => Arc::new(std::mem::take(&mut save_file.file_by_path)),
+    };
+    let hash_type_by_extension = Arc::new(build_settings.hash_type_by_extension);
+    
+    let device_filter = Arc::new(DeviceFilter::new(&build_settings.directory, build_settings.one_filesystem, &build_settings.exclude_devices)?);
+
+    let open_files = build_settings.max_open_files.map(|permits| Arc::new(Semaphore::new(permits)));
+
+    let roots = match build_settings.files {
+        Some(files) => files,
+        None => vec![build_settings.directory.clone()],
+    };
+    
+    // the result file and the files written next to it are never hashed, also if they are below a root
+    let written_files = std::iter::once(&build_settings.output).chain(&build_settings.exclude_files).collect::<Vec<_>>();
+    let excluded = written_files.iter().flat_map(|path| resolve_excluded(path, &[])).collect::<Vec<_>>();
+    let roots = roots.into_iter().filter(|root| match root.file_name() {
+        Some(name) if is_excluded(&excluded, root, name) => {
+            warn!("Not hashing {:?}, it is written by the build", root);
+            false
+        },
+        _ => true,
+    }).collect::<Vec<_>>();
+    
+    let mut progress = BuildProgress::new(build_settings.eta);
+    progress.set_roots(&roots);
+    let progress = Arc::new(progress);
+    
+    let walk = Walk { follow_symlinks: build_settings.follow_symlinks, device_filter: &device_filter, excluded: &excluded };
+    
+    let shared_sizes = match build_settings.dedup_optimized {
+        true => {
+            info!("Collecting file sizes to skip hashing files with a unique size");
+            Some(Arc::new(collect_shared_sizes(&roots, &walk)?))
+        },
+        false => None,
+    };
+
+    if build_settings.preallocate {
+        let estimate = estimate_output_size(&roots, &walk, build_settings.hash_type)?;
+        let length = result_file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        info!("Estimated the result file at {} MB, reserving space for it", estimate / 1024 / 1024);
+        if let Err(err) = preallocate(&result_file, length, estimate.saturating_sub(length)) {
+            warn!("Failed to reserve space for the result file: {}", err);
+        }
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    if build_settings.mmap_threshold.is_some() {
+        warn!("Built without the mmap feature, reading all files through a buffer");
+    }
+
+    #[cfg(not(feature = "pin-threads"))]
+    if build_settings.pin_threads {
+        warn!("Built without the pin-threads feature, the worker threads are not pinned");
+    }
+
+    // read the roots from snapshots where possible, the others from the live file system
+    let snapshots: Vec<Snapshot> = match build_settings.snapshot {
+        Some(kind) => roots.iter().enumerate().filter_map(|(index, root)| match Snapshot::create(root, kind, index) {
+            Ok(snapshot) => Some(snapshot),
+            Err(err) => {
+                warn!("Failed to take a {} snapshot of {:?}, reading it directly: {}", kind, root, err);
+                None
+            },
+        }).collect(),
+        None => Vec::new(),
+    };
+    let snapshot_paths = match snapshots.is_empty() {
+        true => None,
+        false => Some(Arc::new(snapshots.iter().map(|snapshot| (snapshot.root.clone(), snapshot.path.clone())).collect::<Vec<_>>())),
+    };
+
+    let excluded = Arc::new(written_files.iter().flat_map(|path| resolve_excluded(path, &snapshots)).collect::<Vec<_>>());
+
+    let external_roots = match build_settings.external_symlinks_as_copy {
+        true => Some(Arc::new(roots.iter()
+            .chain(snapshots.iter().map(|snapshot| &snapshot.path))
+            .map(|root| fs::canonicalize(root).unwrap_or_else(|_| root.clone()))
+            .collect::<Vec<_>>())),
+        false => None,
+    };
+
+    let cancel = build_settings.cancel.clone().unwrap_or_default();
+    let unreadable = Arc::new(Mutex::new(Vec::new()));
+    let mut cancelled = false;
 
     // create thread pool
 
@@ -105,18 +577,60 @@ pub fn run(
         args.push(WorkerArgument {
             follow_symlinks: build_settings.follow_symlinks,
             hash_type: build_settings.hash_type,
-            save_file_by_path: Arc::clone(&file_by_hash),
+            save_file_by_path: Arc::clone(&cache),
+            hash_type_by_extension: Arc::clone(&hash_type_by_extension),
+            dir_hash_mode: build_settings.dir_hash_mode,
+            file_identity: build_settings.file_identity,
+            progress: Arc::clone(&progress),
+            device_filter: Arc::clone(&device_filter),
+            capture_physical_size: build_settings.capture_physical_size,
+            open_files: open_files.clone(),
+            capture_xattrs: build_settings.capture_xattrs,
+            capture_file_ids: build_settings.capture_file_ids,
+            capture_btime: build_settings.capture_btime,
+            shared_sizes: shared_sizes.clone(),
+            detect_content_type: build_settings.detect_content_type,
+            mtime_tolerance: build_settings.mtime_tolerance,
+            since: build_settings.since,
+            mmap_threshold: build_settings.mmap_threshold,
+            external_roots: external_roots.clone(),
+            snapshots: snapshot_paths.clone(),
+            excluded: Arc::clone(&excluded),
+            warnings: build_settings.warnings.clone(),
+            unreadable: Arc::clone(&unreadable),
+            cancel: Arc::clone(&cancel),
         });
     }
     
-    let pool: ThreadPool<BuildJob, JobResult> = ThreadPool::new(args, worker_run);
-
-    let root_file = FilePath::from_realpath(build_settings.directory);
-    let root_job = BuildJob::new(None, root_file);
+    let pool: ThreadPool<BuildJob, JobResult> = ThreadPool::new_pinned(args, worker_run, build_settings.pin_threads);
+    
+    for root in &roots {
+        let root_file = FilePath::from_realpath(root.clone());
+        pool.publish(BuildJob::new(None, root_file));
+    }
+    
+    let mut pending_roots = roots.len();
     
-    pool.publish(root_job);
+    let mut reporter = ProgressReporter::new(build_settings.progress_bar, &progress, build_settings.progress_interval);
+    let mut last_checkpoint = Instant::now();
+    let mut written_entries = 0usize;
+    let mut summary = BuildSummary::new(build_settings.hash_type);
+    let mut buffered_entries: Vec<HashTreeFileEntry> = Vec::new();
+    let mut sinks = build_settings.sinks;
 
-    while let Ok(result) = pool.receive() {
+    while pending_roots > 0 {
+        if cancel.load(Ordering::Relaxed) {
+            info!("Build cancelled, the result file can be continued later");
+            cancelled = true;
+            break;
+        }
+        
+        let result = match pool.receive_timeout(CANCEL_CHECK_INTERVAL) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        
         let finished;
         let result = match result {
             JobResult::Intermediate(inner) => {
@@ -127,17 +641,199 @@ pub fn run(
                 finished = true;
                 inner
             },
+            JobResult::Panicked(info) => {
+                write_buffered_entries(&save_file, &mut sinks, buffered_entries, build_settings.output_order, &roots)?;
+                return Err(anyhow!("A worker panicked while processing {}: {}. The entries processed so far are kept in the result file", info.path, info.message));
+            },
         };
         
+        if let BuildFile::File(info) = &result.content {
+            progress.add_processed(&info.path, info.content_size);
+        }
+        summary.add(&result.content, result.already_cached);
+        
         if !result.already_cached {
-            let entry = HashTreeFileEntryRef::from(&result.content);
-            save_file.write_entry_ref(&entry)?;
+            match build_settings.output_order.is_buffered() {
+                true => buffered_entries.push(HashTreeFileEntry::from(result.content.clone())),
+                false => {
+                    let entry = HashTreeFileEntryRef::from(&result.content);
+                    save_file.write_entry_ref(&entry).map_err(output_write_error)?;
+                    if !sinks.is_empty() {
+                        write_to_sinks(&mut sinks, &HashTreeFileEntry::from(result.content.clone()))?;
+                    }
+                },
+            }
+            written_entries += 1;
         }
         
         if finished {
-            break;
+            progress.finish_root(result.content.get_path());
+            pending_roots -= 1;
+            continue;
+        }
+        
+        if let Some(checkpoint_interval) = build_settings.checkpoint_interval {
+            if last_checkpoint.elapsed() >= checkpoint_interval {
+                save_file.write_checkpoint().map_err(output_write_error)?;
+                result_file.sync_data().map_err(output_write_error)?;
+                last_checkpoint = Instant::now();
+            }
         }
+        
+        reporter.update(&progress, result.content.get_path());
+    }
+    
+    reporter.finish(&progress);
+    
+    drop(pool);
+    drop(snapshots);
+    write_buffered_entries(&save_file, &mut sinks, buffered_entries, build_settings.output_order, &roots)?;
+    // a cancelled build may not have written any entry after the header yet
+    save_file.flush().map_err(output_write_error)?;
+    for sink in sinks.iter_mut() {
+        sink.finish()?;
+    }
+    drop(save_file);
+    
+    if build_settings.preallocate {
+        // release the reserved space that was not used
+        let length = result_file.metadata()?.len();
+        result_file.set_len(length)?;
+    }
+    
+    if build_settings.verify_after {
+        verify(&build_settings.output, loaded_entries.get() + written_entries)?;
+    }
+    
+    if progress.errors() > 0 {
+        warn!("{} files or directories could not be read", progress.errors());
     }
     
-    return Ok(());
+    summary.errors = progress.errors();
+    summary.elapsed_seconds = progress.elapsed().as_secs_f64();
+    summary.log();
+    
+    // the workers are shut down, no one else holds the list anymore
+    let unreadable = Arc::try_unwrap(unreadable).ok()
+        .and_then(|unreadable| unreadable.into_inner().ok())
+        .unwrap_or_default();
+    
+    return Ok(BuildOutcome {
+        cancelled,
+        summary,
+        unreadable,
+    });
+}
+
+/// Write the entries buffered for an output order other than arrival, see [OutputOrder].
+/// 
+/// # Arguments
+/// * `save_file` - The result file to write to.
+/// * `sinks` - The further sinks to pass the entries to.
+/// * `entries` - The buffered entries in the order they arrived.
+/// * `order` - The order to write the entries in.
+/// * `roots` - The build roots in the order they were given.
+/// 
+/// # Errors
+/// If writing to the result file or a sink fails.
+fn write_buffered_entries<W: std::io::Write, R: std::io::BufRead>(save_file: &HashTreeFile<'_, W, R>, sinks: &mut [Box<dyn EntrySink>], mut entries: Vec<HashTreeFileEntry>, order: OutputOrder, roots: &[PathBuf]) -> Result<()> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    
+    info!("Writing {} entries in {} order", entries.len(), order);
+    sort_entries(&mut entries, order, roots);
+    for entry in &entries {
+        save_file.write_entry(entry).map_err(output_write_error)?;
+        write_to_sinks(sinks, entry)?;
+    }
+    
+    Ok(())
+}
+
+/// Describe an error while writing the result file. If the file system of the result file
+/// is full or the file reached the size limit of the file system (e.g. 4 GiB on FAT32), the
+/// error explains how to continue the build, since the entries written so far are kept.
+/// 
+/// # Arguments
+/// * `err` - The error while writing the result file.
+/// 
+/// # Returns
+/// The error with a description of the failed write.
+fn output_write_error<E: Into<anyhow::Error>>(err: E) -> anyhow::Error {
+    let err = err.into();
+    let kind = err.chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .map(std::io::Error::kind)
+        .next();
+    
+    match kind {
+        Some(std::io::ErrorKind::StorageFull) => err.context("The file system of the result file is full. The entries written so far are kept: free some space or move the result file to another disk and run the build again to continue it"),
+        Some(std::io::ErrorKind::FileTooLarge) => err.context("The result file reached the size limit of its file system. The entries written so far are kept: move the result file to another disk and run the build again to continue it"),
+        _ => err.context("Failed to write to the result file"),
+    }
+}
+
+/// Re-read a hash tree file and check that the header and all entries can be loaded.
+/// Reports every unparseable line.
+/// 
+/// # Arguments
+/// * `path` - The hash tree file to verify.
+/// * `expected_entries` - The number of entries the file should contain.
+/// 
+/// # Errors
+/// * If the file cannot be opened or read.
+/// * If the header cannot be loaded.
+/// * If a line cannot be parsed or the last entry is incomplete.
+/// * If the number of entries does not match `expected_entries`.
+fn verify(path: &PathBuf, expected_entries: usize) -> Result<()> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(anyhow!("Verification failed, unable to open result file: {}", err));
+        }
+    };
+    
+    let mut file_in = std::io::BufReader::new(&file);
+    let mut null_out_writer = NullWriter::new();
+    
+    let mut save_file = HashTreeFile::new(&mut null_out_writer, &mut file_in, GeneralHashType::NULL, false, false, false);
+    if let Err(err) = save_file.load_header() {
+        return Err(anyhow!("Verification failed, unable to load the header of the result file: {}", err));
+    }
+    
+    let mut entries = 0usize;
+    let mut invalid_lines = 0usize;
+    
+    loop {
+        match save_file.load_entry_no_filter() {
+            Ok(Some(_)) => entries += 1,
+            Ok(None) => break,
+            Err(err) => match err.downcast_ref::<serde_json::Error>() {
+                Some(_) => {
+                    error!("Unparseable line after entry {} of the result file: {}", entries, err);
+                    invalid_lines += 1;
+                },
+                None => {
+                    return Err(anyhow!("Verification failed, unable to read the result file: {}", err));
+                }
+            },
+        }
+    }
+    
+    if save_file.has_incomplete_tail() {
+        invalid_lines += 1;
+    }
+    
+    if invalid_lines > 0 {
+        return Err(anyhow!("Verification failed, {} lines of the result file can not be parsed", invalid_lines));
+    }
+    
+    if entries != expected_entries {
+        return Err(anyhow!("Verification failed, the result file contains {} entries but {} were expected", entries, expected_entries));
+    }
+    
+    info!("Verified {} entries of the result file", entries);
+    
+    Ok(())
 }
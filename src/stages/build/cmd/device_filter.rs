@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use log::warn;
+use crate::fileid::HandleIdentifier;
+
+/// Decides which directories the build does not descend into, based on the device
+/// (filesystem) they are on.
+///
+/// # Fields
+/// * `root_device` - If set, only directories on this device are traversed (`--one-filesystem`).
+/// * `excluded_devices` - Directories on these devices are not traversed (`--exclude-device`).
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    pub root_device: Option<u64>,
+    pub excluded_devices: Vec<u64>,
+}
+
+impl DeviceFilter {
+    /// Create a device filter for a build.
+    ///
+    /// # Arguments
+    /// * `directory` - The build directory.
+    /// * `one_filesystem` - Whether to stay on the device of the build directory.
+    /// * `exclude_devices` - Paths on the devices to exclude, e.g. a mount point.
+    ///
+    /// # Returns
+    /// The device filter.
+    ///
+    /// # Errors
+    /// If the device id of the build directory (with `one_filesystem`) or of a path to exclude can not be read.
+    pub fn new(directory: &PathBuf, one_filesystem: bool, exclude_devices: &[PathBuf]) -> Result<Self> {
+        let root_device = match one_filesystem {
+            true => Some(HandleIdentifier::from_path(directory)
+                .map_err(|err| anyhow!("Failed to read the device id of the build directory: {}", err))?.drive),
+            false => None,
+        };
+
+        let excluded_devices = exclude_devices.iter().map(|path| {
+            HandleIdentifier::from_path(path)
+                .map(|handle| handle.drive)
+                .map_err(|err| anyhow!("Failed to read the device id of {:?} to exclude: {}", path, err))
+        }).collect::<Result<Vec<u64>>>()?;
+
+        Ok(DeviceFilter {
+            root_device,
+            excluded_devices,
+        })
+    }
+
+    /// Check if the filter can skip any directory. If not, reading the device ids can be skipped.
+    ///
+    /// # Returns
+    /// True if a root device or an excluded device is set.
+    pub fn is_active(&self) -> bool {
+        self.root_device.is_some() || !self.excluded_devices.is_empty()
+    }
+
+    /// Check if a directory on a device is skipped.
+    ///
+    /// # Arguments
+    /// * `device` - The device id of the directory.
+    ///
+    /// # Returns
+    /// True if the directory is on another device than the root device or on an excluded device.
+    ///
+    /// # Example
+    /// ```
+    /// use backup_deduplicator::stages::build::cmd::device_filter::DeviceFilter;
+    ///
+    /// let filter = DeviceFilter { root_device: None, excluded_devices: vec![42] };
+    /// assert!(filter.skips(42));
+    /// assert!(!filter.skips(7));
+    ///
+    /// let filter = DeviceFilter { root_device: Some(7), excluded_devices: vec![42] };
+    /// assert!(!filter.skips(7));
+    /// assert!(filter.skips(8));
+    /// assert!(filter.skips(42));
+    ///
+    /// assert!(!DeviceFilter::default().skips(42));
+    /// ```
+    pub fn skips(&self, device: u64) -> bool {
+        if let Some(root_device) = self.root_device {
+            if device != root_device {
+                return true;
+            }
+        }
+
+        self.excluded_devices.contains(&device)
+    }
+
+    /// Check if a directory is on a device skipped by the filter. The device id is read
+    /// following symlinks, so a directory reached through a followed symlink is checked
+    /// against the device it is actually on.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the directory.
+    ///
+    /// # Returns
+    /// True if the directory is skipped, see [DeviceFilter::skips]. False if the filter is not
+    /// active or the device id can not be read, the directory is then traversed as usual.
+    pub fn skips_path(&self, path: &Path) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+
+        match HandleIdentifier::from_path(path) {
+            Ok(handle) => self.skips(handle.drive),
+            Err(err) => {
+                warn!("Failed to read device id of {:?}: {}", path, err);
+                false
+            }
+        }
+    }
+}
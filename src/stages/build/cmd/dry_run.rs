@@ -0,0 +1,101 @@
+use std::io::Write;
+use std::path::PathBuf;
+use anyhow::{anyhow, Result};
+use crate::stages::build::cmd::device_filter::DeviceFilter;
+use crate::stages::build::cmd::exclude::resolve_excluded;
+use crate::stages::build::cmd::walk::{Walk, WalkEntry};
+
+/// The settings for a dry run of the build command.
+///
+/// # Fields
+/// * `directory` - The directory that would be built.
+/// * `follow_symlinks` - Whether to follow symlinks when traversing the file system.
+/// * `list_files` - Whether to print every file that would be hashed.
+/// * `one_filesystem` - Whether to skip directories on another filesystem (device) than `directory`.
+/// * `exclude_devices` - Directories on the same device as one of these paths are skipped.
+/// * `exclude_files` - The files the build would write (the result file and the files next to it), they are not
+///   counted if they are below `directory`.
+pub struct DryRunSettings {
+    pub directory: PathBuf,
+    pub follow_symlinks: bool,
+    pub list_files: bool,
+    pub one_filesystem: bool,
+    pub exclude_devices: Vec<PathBuf>,
+    pub exclude_files: Vec<PathBuf>,
+}
+
+/// The counts collected by a dry run.
+///
+/// # Fields
+/// * `files` - The number of files that would be hashed.
+/// * `file_bytes` - The total size of the files that would be hashed.
+/// * `directories` - The number of directories.
+/// * `symlinks` - The number of symlinks (only if symlinks are not followed).
+/// * `other` - The number of other entries (devices, sockets, ...).
+/// * `errors` - The number of entries whose metadata could not be read.
+/// * `other_filesystems` - The number of directories skipped since they are on another filesystem or an excluded device.
+#[derive(Debug, Default)]
+pub struct DryRunSummary {
+    pub files: u64,
+    pub file_bytes: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub other: u64,
+    pub errors: u64,
+    pub other_filesystems: u64,
+}
+
+/// Runs the discovery of the build command without hashing anything.
+/// Lists the directories and classifies their entries the same way the build does (see [Walk]),
+/// then prints a summary (and optionally the list of files) to stdout.
+///
+/// # Arguments
+/// * `settings` - The settings for the dry run.
+///
+/// # Returns
+/// The collected counts.
+///
+/// # Errors
+/// * If the device id of the directory (if `one_filesystem` is set) or of a path to exclude can not be read.
+/// * If writing to stdout fails.
+pub fn run(settings: DryRunSettings) -> Result<DryRunSummary> {
+    let mut summary = DryRunSummary::default();
+    let mut stdout = std::io::stdout().lock();
+    
+    let device_filter = DeviceFilter::new(&settings.directory, settings.one_filesystem, &settings.exclude_devices)?;
+    let excluded = settings.exclude_files.iter().flat_map(|path| resolve_excluded(path, &[])).collect::<Vec<_>>();
+    let walk = Walk { follow_symlinks: settings.follow_symlinks, device_filter: &device_filter, excluded: &excluded };
+
+    walk.run(&[settings.directory], |path, entry| {
+        match entry {
+            WalkEntry::Directory(_) => summary.directories += 1,
+            WalkEntry::UnreadableDirectory(_) => {
+                summary.directories += 1;
+                summary.errors += 1;
+            },
+            WalkEntry::SkippedDirectory => summary.other_filesystems += 1,
+            WalkEntry::Unreadable(_) => summary.errors += 1,
+            WalkEntry::Leaf(metadata) if metadata.is_symlink() => summary.symlinks += 1,
+            WalkEntry::Leaf(metadata) if metadata.is_file() => {
+                summary.files += 1;
+                summary.file_bytes += metadata.len();
+
+                if settings.list_files {
+                    writeln!(stdout, "{}", path.display()).map_err(|err| anyhow!("Failed to write to stdout: {}", err))?;
+                }
+            },
+            WalkEntry::Leaf(_) => summary.other += 1,
+        }
+        Ok(())
+    })?;
+
+    writeln!(stdout, "Files: {} ({} bytes)", summary.files, summary.file_bytes)
+        .and_then(|_| writeln!(stdout, "Directories: {}", summary.directories))
+        .and_then(|_| writeln!(stdout, "Symlinks: {}", summary.symlinks))
+        .and_then(|_| writeln!(stdout, "Other: {}", summary.other))
+        .and_then(|_| writeln!(stdout, "Unreadable: {}", summary.errors))
+        .and_then(|_| writeln!(stdout, "Skipped other filesystems: {}", summary.other_filesystems))
+        .map_err(|err| anyhow!("Failed to write to stdout: {}", err))?;
+
+    Ok(summary)
+}
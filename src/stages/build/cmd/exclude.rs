@@ -0,0 +1,79 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::stages::build::cmd::snapshot::Snapshot;
+
+/// Resolve a file the build writes (e.g. the result file) to the canonical paths it is found at during the
+/// traversal, so that it is not hashed if it is below a build root. Files that do not exist yet are resolved
+/// through their parent directory. Roots read from a snapshot contain the file at the same place within the snapshot.
+///
+/// # Arguments
+/// * `path` - The path of the written file.
+/// * `snapshots` - The snapshots the roots are read from.
+///
+/// # Returns
+/// The canonical path of the file and its paths within the snapshots containing it.
+pub fn resolve_excluded(path: &Path, snapshots: &[Snapshot]) -> Vec<PathBuf> {
+    let canonical = match fs::canonicalize(path) {
+        Ok(canonical) => canonical,
+        Err(_) => match (path.parent().and_then(|parent| fs::canonicalize(parent).ok()), path.file_name()) {
+            (Some(parent), Some(name)) => parent.join(name),
+            _ => path.to_path_buf(),
+        },
+    };
+
+    let mut paths = Vec::with_capacity(1);
+    for snapshot in snapshots {
+        let (Ok(root), Ok(snapshot_root)) = (fs::canonicalize(&snapshot.root), fs::canonicalize(&snapshot.path)) else {
+            continue;
+        };
+        if let Ok(relative) = canonical.strip_prefix(root) {
+            paths.push(snapshot_root.join(relative));
+        }
+    }
+    paths.push(canonical);
+    paths
+}
+
+/// Check whether a path found during the traversal is one of the files written by the build.
+/// Only paths with the file name of an excluded file are resolved, so that the check is cheap for all other files.
+///
+/// # Arguments
+/// * `excluded` - The canonical paths of the excluded files, see [resolve_excluded].
+/// * `path` - The path found during the traversal.
+/// * `file_name` - The file name of the path.
+///
+/// # Returns
+/// True if the path is an excluded file.
+///
+/// # Example
+/// ```
+/// use std::fs;
+/// use std::path::PathBuf;
+/// use backup_deduplicator::stages::build::cmd::exclude::{is_excluded, resolve_excluded};
+///
+/// let directory = std::env::temp_dir().join(format!("bdd-exclude-example-{}", std::process::id()));
+/// fs::create_dir_all(directory.join("sub")).unwrap();
+/// fs::write(directory.join("hash_tree.bdd"), "").unwrap();
+/// fs::write(directory.join("sub").join("hash_tree.bdd"), "").unwrap();
+///
+/// let excluded = resolve_excluded(&directory.join("sub").join("..").join("hash_tree.bdd"), &[]);
+/// assert!(is_excluded(&excluded, &directory.join("hash_tree.bdd"), "hash_tree.bdd".as_ref()));
+/// assert!(!is_excluded(&excluded, &directory.join("sub").join("hash_tree.bdd"), "hash_tree.bdd".as_ref()));
+///
+/// // files written after the build are resolved through their parent directory
+/// let excluded = resolve_excluded(&directory.join("summary.json"), &[]);
+/// fs::write(directory.join("summary.json"), "").unwrap();
+/// assert!(is_excluded(&excluded, &directory.join("summary.json"), "summary.json".as_ref()));
+/// # fs::remove_dir_all(&directory).unwrap();
+/// ```
+pub fn is_excluded(excluded: &[PathBuf], path: &Path, file_name: &OsStr) -> bool {
+    if !excluded.iter().any(|excluded| excluded.file_name() == Some(file_name)) {
+        return false;
+    }
+
+    match fs::canonicalize(path) {
+        Ok(canonical) => excluded.contains(&canonical),
+        Err(_) => false,
+    }
+}
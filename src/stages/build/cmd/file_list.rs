@@ -0,0 +1,95 @@
+use std::io::BufRead;
+use std::path::PathBuf;
+use anyhow::{anyhow, Result};
+
+/// Read a list of paths, e.g. the output of `find` or `find -print0`.
+/// Empty entries are skipped.
+///
+/// # Arguments
+/// * `reader` - The reader to read the list from.
+/// * `nul_separated` - Whether the paths are separated by NUL bytes instead of newlines.
+///   Use this for lists that may contain file names with line breaks.
+///
+/// # Returns
+/// The paths in the order they are listed.
+///
+/// # Errors
+/// * If the list cannot be read.
+/// * If a path is not valid UTF-8 on platforms without byte paths (non Unix).
+///
+/// # Example
+/// ```
+/// use std::path::PathBuf;
+/// use backup_deduplicator::stages::build::cmd::file_list::read_file_list;
+///
+/// let paths = read_file_list("a.txt\r\ndir/b c\n\n".as_bytes(), false).unwrap();
+/// assert_eq!(paths, vec![PathBuf::from("a.txt"), PathBuf::from("dir/b c")]);
+///
+/// let paths = read_file_list("line\nbreak\0other\0".as_bytes(), true).unwrap();
+/// assert_eq!(paths, vec![PathBuf::from("line\nbreak"), PathBuf::from("other")]);
+/// ```
+pub fn read_file_list<R: BufRead>(mut reader: R, nul_separated: bool) -> Result<Vec<PathBuf>> {
+    let separator = match nul_separated {
+        true => b'\0',
+        false => b'\n',
+    };
+
+    let mut paths = Vec::new();
+    let mut buffer = Vec::new();
+
+    loop {
+        buffer.clear();
+        let read = reader.read_until(separator, &mut buffer)
+            .map_err(|err| anyhow!("Failed to read file list: {}", err))?;
+        if read == 0 {
+            break;
+        }
+
+        if buffer.last() == Some(&separator) {
+            buffer.pop();
+        }
+        if !nul_separated && buffer.last() == Some(&b'\r') {
+            buffer.pop();
+        }
+        if buffer.is_empty() {
+            continue;
+        }
+
+        paths.push(bytes_to_path(&buffer)?);
+    }
+
+    Ok(paths)
+}
+
+/// Convert the raw bytes of a listed path to a path.
+///
+/// # Arguments
+/// * `bytes` - The bytes of the path.
+///
+/// # Returns
+/// The path.
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> Result<PathBuf> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+/// Convert the raw bytes of a listed path to a path.
+///
+/// # Arguments
+/// * `bytes` - The bytes of the path.
+///
+/// # Returns
+/// The path.
+///
+/// # Errors
+/// If the bytes are not valid UTF-8.
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> Result<PathBuf> {
+    match std::str::from_utf8(bytes) {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(err) => Err(anyhow!("Listed path is not valid UTF-8: {}", err)),
+    }
+}
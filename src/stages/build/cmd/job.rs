@@ -99,15 +99,28 @@ pub struct JobResultContent {
     pub content: BuildFile,
 }
 
+/// A job that panicked while being processed.
+///
+/// # Fields
+/// * `path` - The path of the file/directory the job was processing.
+/// * `message` - The panic message.
+#[derive(Debug, Serialize, Clone)]
+pub struct JobPanicInformation {
+    pub path: FilePath,
+    pub message: String,
+}
+
 /// A job result.
 /// 
 /// # Fields
 /// * `Final` - The final result of command. Returned if the job has no parent.
 /// * `Intermediate` - An intermediate result of a command. Returned if the job has a parent.
+/// * `Panicked` - The worker panicked while processing the job. The job and its parents never complete.
 #[derive(Debug, Serialize, Clone)]
 pub enum JobResult {
     Final(JobResultContent),
     Intermediate(JobResultContent),
+    Panicked(JobPanicInformation),
 }
 
 impl ResultTrait for JobResult {
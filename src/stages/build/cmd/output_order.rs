@@ -0,0 +1,134 @@
+use std::fmt::Display;
+use std::path::{Components, Path, PathBuf};
+use std::str::FromStr;
+use crate::stages::build::output::HashTreeFileEntry;
+
+/// The order in which a build writes the entries to the hash tree file.
+///
+/// # Variants
+/// * `Arrival` - Every entry is written as soon as it is hashed. Parallel workers make the order differ
+///   between runs, a directory is written after its children.
+/// * `Sorted` - The entries are buffered and written sorted by their path.
+/// * `Discovery` - The entries are buffered and written in the order of the directory traversal, see [discovery_key]:
+///   a directory before its children, the children of a directory by name, every subtree before the next sibling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputOrder {
+    #[default]
+    Arrival,
+    Sorted,
+    Discovery,
+}
+
+impl OutputOrder {
+    /// Whether the entries are buffered until the end of the build instead of being written as they arrive.
+    ///
+    /// # Returns
+    /// True for every order except `Arrival`.
+    pub fn is_buffered(&self) -> bool {
+        *self != OutputOrder::Arrival
+    }
+}
+
+impl FromStr for OutputOrder {
+    /// Error type for parsing an `OutputOrder` from a string.
+    type Err = &'static str;
+
+    /// Parses a string into an `OutputOrder`.
+    ///
+    /// # Arguments
+    /// * `s` - The string to parse.
+    ///
+    /// # Returns
+    /// The `OutputOrder` that corresponds to the string or an error.
+    ///
+    /// # Errors
+    /// Returns the available orders if the string does not correspond to an `OutputOrder`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::str::FromStr;
+    /// use backup_deduplicator::stages::build::cmd::output_order::OutputOrder;
+    ///
+    /// assert_eq!(OutputOrder::from_str("arrival"), Ok(OutputOrder::Arrival));
+    /// assert_eq!(OutputOrder::from_str("Discovery"), Ok(OutputOrder::Discovery));
+    /// assert!(OutputOrder::from_str("random").is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "arrival" => Ok(OutputOrder::Arrival),
+            "sorted" => Ok(OutputOrder::Sorted),
+            "discovery" => Ok(OutputOrder::Discovery),
+            _ => Err("arrival, sorted, discovery"),
+        }
+    }
+}
+
+impl Display for OutputOrder {
+    /// Converts an `OutputOrder` into a string.
+    ///
+    /// # Arguments
+    /// * `f` - The formatter to write to.
+    ///
+    /// # Returns
+    /// A result indicating whether the operation was successful.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputOrder::Arrival => write!(f, "arrival"),
+            OutputOrder::Sorted => write!(f, "sorted"),
+            OutputOrder::Discovery => write!(f, "discovery"),
+        }
+    }
+}
+
+/// Get the position of a path in the directory traversal of a build. The roots are traversed in the given order,
+/// the directory listings are sorted by name, so comparing the paths component by component yields the traversal
+/// order: a directory comes before its children and a subtree before the next sibling.
+///
+/// # Arguments
+/// * `path` - The path of the entry.
+/// * `roots` - The build roots in the order they were given.
+///
+/// # Returns
+/// The sort key of the path: the index of its root (the number of roots if it is below none) and its components.
+///
+/// # Example
+/// ```
+/// use std::path::{Path, PathBuf};
+/// use backup_deduplicator::stages::build::cmd::output_order::discovery_key;
+///
+/// let roots = vec![PathBuf::from("/data"), PathBuf::from("/backup")];
+/// let mut paths = vec!["/data/a/x", "/backup", "/data/a0", "/data/a", "/data"];
+/// paths.sort_by(|a, b| discovery_key(Path::new(a), &roots).cmp(&discovery_key(Path::new(b), &roots)));
+/// assert_eq!(paths, vec!["/data", "/data/a", "/data/a/x", "/data/a0", "/backup"]);
+/// ```
+pub fn discovery_key<'a>(path: &'a Path, roots: &[PathBuf]) -> (usize, Components<'a>) {
+    let root = roots.iter().position(|root| path.starts_with(root)).unwrap_or(roots.len());
+    (root, path.components())
+}
+
+/// Sort the buffered entries of a build into the output order.
+///
+/// # Arguments
+/// * `entries` - The entries in the order they arrived.
+/// * `order` - The order to write the entries in.
+/// * `roots` - The build roots in the order they were given, see [discovery_key].
+pub fn sort_entries(entries: &mut [HashTreeFileEntry], order: OutputOrder, roots: &[PathBuf]) {
+    match order {
+        OutputOrder::Arrival => {},
+        OutputOrder::Sorted => entries.sort_by_cached_key(|entry| entry.path.to_string()),
+        OutputOrder::Discovery => entries.sort_by(|a, b| {
+            discovery_key(first_component(a), roots).cmp(&discovery_key(first_component(b), roots))
+        }),
+    }
+}
+
+/// Get the file system path of an entry, without the paths within archives.
+///
+/// # Arguments
+/// * `entry` - The entry.
+///
+/// # Returns
+/// The path of the first component of the entry's path.
+fn first_component(entry: &HashTreeFileEntry) -> &Path {
+    entry.path.path.first().map(|component| component.path.as_path()).unwrap_or(Path::new(""))
+}
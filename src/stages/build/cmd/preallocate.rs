@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::PathBuf;
+use anyhow::Result;
+use crate::hash::{GeneralHash, GeneralHashType};
+use crate::stages::build::cmd::walk::{Walk, WalkEntry};
+
+/// The estimated length in bytes of an entry line without its path and hashes,
+/// i.e. the JSON keys, the file type, the modification time and the size.
+const ENTRY_OVERHEAD: u64 = 150;
+
+/// Estimate the length in bytes of a single hash tree entry.
+///
+/// # Arguments
+/// * `path_length` - The length of the path in bytes.
+/// * `hash_length` - The length of a hash as written to the hash tree, including its type prefix.
+/// * `children` - The number of children of a directory, 0 for other entries.
+///
+/// # Returns
+/// The estimated length of the entry line.
+///
+/// # Example
+/// ```
+/// use backup_deduplicator::stages::build::cmd::preallocate::estimate_entry_size;
+///
+/// let file = estimate_entry_size(20, 71, 0);
+/// let directory = estimate_entry_size(20, 71, 3);
+/// assert!(file > 20 + 71);
+/// assert_eq!(directory - file, 3 * (71 + 3));
+/// ```
+pub fn estimate_entry_size(path_length: u64, hash_length: u64, children: u64) -> u64 {
+    ENTRY_OVERHEAD + path_length + hash_length + children * (hash_length + 3)
+}
+
+/// Walk the directories to build and estimate the size of the hash tree file written for them,
+/// see [estimate_entry_size]. Traverses the file system the same way the build does (see [Walk]),
+/// without opening any file. The estimate is a heuristic: the real size differs with the recorded
+/// optional information (e.g. file ids) and the hashes of files with overridden hash types.
+///
+/// # Arguments
+/// * `roots` - The files and directories to build.
+/// * `walk` - The traversal rules of the build.
+/// * `hash_type` - The hash algorithm used for the build.
+///
+/// # Returns
+/// The estimated size of the entries in bytes.
+///
+/// # Errors
+/// Never, unreadable entries are estimated without children. The result only follows the signature of [Walk::run].
+pub fn estimate_output_size(roots: &[PathBuf], walk: &Walk, hash_type: GeneralHashType) -> Result<u64> {
+    let hash_length = GeneralHash::from_type(hash_type).to_string().len() as u64;
+    let mut size = 0;
+
+    walk.run(roots, |path, entry| {
+        let children = match entry {
+            WalkEntry::Directory(children) => children as u64,
+            _ => 0,
+        };
+        size += estimate_entry_size(path.as_os_str().len() as u64, hash_length, children);
+        Ok(())
+    })?;
+
+    Ok(size)
+}
+
+/// Reserve disk space behind the end of a file without changing its size, so that appending
+/// to it causes less fragmentation. Truncating the file to its size releases the unused space.
+/// Only supported on Linux, a no-op elsewhere.
+///
+/// # Arguments
+/// * `file` - The file to reserve space for.
+/// * `offset` - The offset to reserve the space from, usually the current size of the file.
+/// * `length` - The number of bytes to reserve.
+///
+/// # Errors
+/// If the file system does not support reserving space or is full.
+#[cfg(target_os = "linux")]
+pub fn preallocate(file: &fs::File, offset: u64, length: u64) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    if length == 0 {
+        return Ok(());
+    }
+
+    // SAFETY: the file descriptor is valid while `file` is open
+    let result = unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, offset as libc::off_t, length as libc::off_t) };
+    match result {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Reserve disk space behind the end of a file without changing its size.
+/// Not supported on this platform, does nothing.
+///
+/// # Arguments
+/// * `file` - The file to reserve space for.
+/// * `offset` - The offset to reserve the space from.
+/// * `length` - The number of bytes to reserve.
+///
+/// # Errors
+/// Never on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn preallocate(_file: &fs::File, _offset: u64, _length: u64) -> std::io::Result<()> {
+    Ok(())
+}
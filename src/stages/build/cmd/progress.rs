@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use log::info;
+use crate::path::FilePath;
+
+/// Maximum number of roots with their own progress counters. Longer lists of roots,
+/// like the output of `find` given to `--files-from`, are only counted in total.
+const MAX_TRACKED_ROOTS: usize = 64;
+
+/// Progress counters of a single directory given to the build.
+///
+/// # Fields
+/// * `path` - The path of the root, as given to the build.
+#[derive(Debug)]
+struct RootProgress {
+    path: PathBuf,
+    discovered_files: AtomicU64,
+    discovered_bytes: AtomicU64,
+    processed_files: AtomicU64,
+    processed_bytes: AtomicU64,
+    finished: AtomicBool,
+}
+
+/// Progress counters of a running build, shared between the workers and the main thread.
+///
+/// Discovered files are only counted if `track_discovered` is set, since this requires
+/// reading the metadata of every directory entry while listing directories.
+/// Discovery and hashing run interleaved, therefore the discovered totals grow while
+/// the build is running and the estimated time is only a lower bound until the
+/// whole tree has been listed.
+///
+/// With several directories to build (`--files-from`), every directory is also counted
+/// on its own, see [BuildProgress::set_roots].
+///
+/// # Fields
+/// * `track_discovered` - Whether discovered files are counted.
+#[derive(Debug)]
+pub struct BuildProgress {
+    pub track_discovered: bool,
+    start: Instant,
+    discovered_files: AtomicU64,
+    discovered_bytes: AtomicU64,
+    processed_files: AtomicU64,
+    processed_bytes: AtomicU64,
+    errors: AtomicU64,
+    roots: Vec<RootProgress>,
+    root_by_path: HashMap<PathBuf, usize>,
+}
+
+impl BuildProgress {
+    /// Create new progress counters, starting the clock.
+    ///
+    /// # Arguments
+    /// * `track_discovered` - Whether discovered files are counted.
+    ///
+    /// # Returns
+    /// The progress counters.
+    pub fn new(track_discovered: bool) -> Self {
+        BuildProgress {
+            track_discovered,
+            start: Instant::now(),
+            discovered_files: AtomicU64::new(0),
+            discovered_bytes: AtomicU64::new(0),
+            processed_files: AtomicU64::new(0),
+            processed_bytes: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            roots: Vec::new(),
+            root_by_path: HashMap::new(),
+        }
+    }
+
+    /// Count the files of every root directory on its own as well, so that the progress of
+    /// roots on different disks can be told apart. Only done for at least two and at most
+    /// [MAX_TRACKED_ROOTS] directories, files given as roots are only counted in total.
+    ///
+    /// # Arguments
+    /// * `roots` - The roots of the build.
+    ///
+    /// # Example
+    /// ```
+    /// use std::path::PathBuf;
+    /// use backup_deduplicator::path::FilePath;
+    /// use backup_deduplicator::stages::build::cmd::progress::BuildProgress;
+    ///
+    /// let roots = vec![PathBuf::from("src"), PathBuf::from("tests")];
+    /// let mut progress = BuildProgress::new(false);
+    /// progress.set_roots(&roots);
+    ///
+    /// progress.add_processed(&FilePath::from_realpath(PathBuf::from("src/main.rs")), 3);
+    /// progress.add_processed(&FilePath::from_realpath(PathBuf::from("src/lib.rs")), 4);
+    /// progress.finish_root(&FilePath::from_realpath(PathBuf::from("tests")));
+    ///
+    /// assert_eq!(progress.processed(), (2, 7));
+    /// assert_eq!(progress.roots(), vec![(PathBuf::from("src"), 2, 7, false), (PathBuf::from("tests"), 0, 0, true)]);
+    /// ```
+    pub fn set_roots(&mut self, roots: &[PathBuf]) {
+        let directories: Vec<&PathBuf> = roots.iter().filter(|root| root.is_dir()).collect();
+        if directories.len() < 2 || directories.len() > MAX_TRACKED_ROOTS {
+            return;
+        }
+
+        for root in directories {
+            let key = match FilePath::from_realpath(root.clone()).path.into_iter().next() {
+                Some(component) => component.path,
+                None => continue,
+            };
+            self.root_by_path.insert(key, self.roots.len());
+            self.roots.push(RootProgress {
+                path: root.clone(),
+                discovered_files: AtomicU64::new(0),
+                discovered_bytes: AtomicU64::new(0),
+                processed_files: AtomicU64::new(0),
+                processed_bytes: AtomicU64::new(0),
+                finished: AtomicBool::new(false),
+            });
+        }
+    }
+
+    /// Find the counters of the root a path is in.
+    ///
+    /// # Arguments
+    /// * `path` - The path of a file or directory of the build.
+    ///
+    /// # Returns
+    /// The counters of the innermost root containing the path. None if roots are not counted on their own.
+    fn root_of(&self, path: &FilePath) -> Option<&RootProgress> {
+        if self.roots.is_empty() {
+            return None;
+        }
+
+        let first = path.path.first()?;
+        first.path.ancestors()
+            .find_map(|ancestor| self.root_by_path.get(ancestor))
+            .map(|index| &self.roots[*index])
+    }
+
+    /// Count a file found while listing a directory.
+    ///
+    /// # Arguments
+    /// * `directory` - The path of the listed directory.
+    /// * `size` - The size of the file in bytes.
+    pub fn add_discovered(&self, directory: &FilePath, size: u64) {
+        self.discovered_files.fetch_add(1, Ordering::Relaxed);
+        self.discovered_bytes.fetch_add(size, Ordering::Relaxed);
+        if let Some(root) = self.root_of(directory) {
+            root.discovered_files.fetch_add(1, Ordering::Relaxed);
+            root.discovered_bytes.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a file that was hashed or taken from the cache.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the file.
+    /// * `size` - The size of the file in bytes.
+    pub fn add_processed(&self, path: &FilePath, size: u64) {
+        self.processed_files.fetch_add(1, Ordering::Relaxed);
+        self.processed_bytes.fetch_add(size, Ordering::Relaxed);
+        if let Some(root) = self.root_of(path) {
+            root.processed_files.fetch_add(1, Ordering::Relaxed);
+            root.processed_bytes.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
+    /// Mark a root as finished, once its entry was written.
+    ///
+    /// # Arguments
+    /// * `root` - The path of the root.
+    pub fn finish_root(&self, root: &FilePath) {
+        let index = root.path.first().and_then(|component| self.root_by_path.get(&component.path));
+        if let Some(index) = index {
+            self.roots[*index].finished.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Get the processed files of every root counted on its own, see [BuildProgress::set_roots].
+    ///
+    /// # Returns
+    /// For every root its path, the number of processed files, their total size in bytes
+    /// and whether the root is finished.
+    pub fn roots(&self) -> Vec<(PathBuf, u64, u64, bool)> {
+        self.roots.iter().map(|root| (
+            root.path.clone(),
+            root.processed_files.load(Ordering::Relaxed),
+            root.processed_bytes.load(Ordering::Relaxed),
+            root.finished.load(Ordering::Relaxed),
+        )).collect()
+    }
+
+    /// Count a file or directory that could not be read and was recorded as `Other` entry.
+    pub fn add_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the discovered files.
+    ///
+    /// # Returns
+    /// The number of discovered files and their total size in bytes.
+    pub fn discovered(&self) -> (u64, u64) {
+        (self.discovered_files.load(Ordering::Relaxed), self.discovered_bytes.load(Ordering::Relaxed))
+    }
+
+    /// Get the processed files.
+    ///
+    /// # Returns
+    /// The number of processed files and their total size in bytes.
+    pub fn processed(&self) -> (u64, u64) {
+        (self.processed_files.load(Ordering::Relaxed), self.processed_bytes.load(Ordering::Relaxed))
+    }
+
+    /// Get the number of files and directories that could not be read.
+    ///
+    /// # Returns
+    /// The number of errors.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Get the time elapsed since the counters were created.
+    ///
+    /// # Returns
+    /// The elapsed time.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Estimate the remaining time based on the processed and discovered bytes so far.
+    ///
+    /// # Returns
+    /// The estimated remaining time. None if discovered files are not tracked or nothing was processed yet.
+    pub fn eta(&self) -> Option<Duration> {
+        if !self.track_discovered {
+            return None;
+        }
+
+        let (_, processed_bytes) = self.processed();
+        let (_, discovered_bytes) = self.discovered();
+
+        if processed_bytes == 0 {
+            return None;
+        }
+
+        let remaining = discovered_bytes.saturating_sub(processed_bytes) as f64;
+        Some(self.elapsed().mul_f64(remaining / processed_bytes as f64))
+    }
+
+    /// Log the current progress at info level, followed by a line per root counted on its own.
+    pub fn log(&self) {
+        let (processed_files, processed_bytes) = self.processed();
+
+        if !self.track_discovered {
+            info!("Progress: {} files ({} MB) processed", processed_files, processed_bytes / 1024 / 1024);
+            self.log_roots();
+            return;
+        }
+
+        let (discovered_files, discovered_bytes) = self.discovered();
+        let percentage = match discovered_bytes {
+            0 => 0.0,
+            _ => (processed_bytes as f64 / discovered_bytes as f64 * 100.0).min(100.0),
+        };
+
+        match self.eta() {
+            Some(eta) => info!("Progress: {}/{} discovered files, {}/{} MB hashed so far ({:.1}%), ETA {}s", processed_files, discovered_files, processed_bytes / 1024 / 1024, discovered_bytes / 1024 / 1024, percentage, eta.as_secs()),
+            None => info!("Progress: {}/{} discovered files, {}/{} MB hashed so far ({:.1}%)", processed_files, discovered_files, processed_bytes / 1024 / 1024, discovered_bytes / 1024 / 1024, percentage),
+        }
+        self.log_roots();
+    }
+
+    /// Log the progress of every root counted on its own at info level.
+    fn log_roots(&self) {
+        for root in &self.roots {
+            let processed_files = root.processed_files.load(Ordering::Relaxed);
+            let processed_bytes = root.processed_bytes.load(Ordering::Relaxed);
+            let state = match root.finished.load(Ordering::Relaxed) {
+                true => "finished",
+                false => "running",
+            };
+
+            match self.track_discovered {
+                true => info!("Root {:?}: {}/{} discovered files, {}/{} MB hashed so far, {}", root.path, processed_files, root.discovered_files.load(Ordering::Relaxed),
+                    processed_bytes / 1024 / 1024, root.discovered_bytes.load(Ordering::Relaxed) / 1024 / 1024, state),
+                false => info!("Root {:?}: {} files ({} MB) processed, {}", root.path, processed_files, processed_bytes / 1024 / 1024, state),
+            }
+        }
+    }
+}
+
+/// Reports the progress of a running build, either as progress bar or as log lines.
+///
+/// # Variants
+/// * `Log` - Log the progress at info level in a fixed interval.
+/// * `Bar` - Render a progress bar on stderr (requires the `progress` feature).
+pub enum ProgressReporter {
+    Log {
+        interval: Duration,
+        last: Instant,
+    },
+    #[cfg(feature = "progress")]
+    Bar(indicatif::ProgressBar),
+}
+
+impl ProgressReporter {
+    /// Create a progress reporter.
+    ///
+    /// # Arguments
+    /// * `progress_bar` - Whether to render a progress bar. Falls back to log lines if stderr
+    ///   is not a terminal or the `progress` feature is disabled.
+    /// * `progress` - The progress counters to report. A bar with a known length is only
+    ///   shown if discovered files are tracked, otherwise a spinner.
+    /// * `interval` - The interval in which log lines are written.
+    ///
+    /// # Returns
+    /// The progress reporter.
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    pub fn new(progress_bar: bool, progress: &BuildProgress, interval: Duration) -> Self {
+        #[cfg(feature = "progress")]
+        {
+            use std::io::IsTerminal;
+
+            if progress_bar && std::io::stderr().is_terminal() {
+                let (bar, template) = match progress.track_discovered {
+                    true => (indicatif::ProgressBar::new(0), "{spinner} [{elapsed_precise}] [{wide_bar}] {bytes}/{total_bytes} ETA {eta} {msg}"),
+                    false => (indicatif::ProgressBar::new_spinner(), "{spinner} [{elapsed_precise}] {bytes} ({bytes_per_sec}) {msg}"),
+                };
+                if let Ok(style) = indicatif::ProgressStyle::with_template(template) {
+                    bar.set_style(style);
+                }
+                return ProgressReporter::Bar(bar);
+            }
+        }
+        #[cfg(not(feature = "progress"))]
+        if progress_bar {
+            log::warn!("Built without the progress feature, logging the progress instead of a progress bar");
+        }
+
+        ProgressReporter::Log {
+            interval,
+            last: Instant::now(),
+        }
+    }
+
+    /// Report the progress after a result was written.
+    ///
+    /// # Arguments
+    /// * `progress` - The progress counters.
+    /// * `current` - The path of the last processed file, shown next to the progress bar.
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    pub fn update(&mut self, progress: &BuildProgress, current: &FilePath) {
+        match self {
+            ProgressReporter::Log { interval, last } => {
+                if last.elapsed() >= *interval {
+                    progress.log();
+                    *last = Instant::now();
+                }
+            },
+            #[cfg(feature = "progress")]
+            ProgressReporter::Bar(bar) => {
+                let (processed_files, processed_bytes) = progress.processed();
+                if progress.track_discovered {
+                    bar.set_length(progress.discovered().1.max(processed_bytes));
+                }
+                bar.set_position(processed_bytes);
+                bar.set_message(format!("{} files, {}", processed_files, current));
+            },
+        }
+    }
+
+    /// Report the final progress and remove the progress bar.
+    ///
+    /// # Arguments
+    /// * `progress` - The progress counters.
+    pub fn finish(&mut self, progress: &BuildProgress) {
+        #[cfg(feature = "progress")]
+        if let ProgressReporter::Bar(bar) = self {
+            bar.finish_and_clear();
+        }
+
+        progress.log();
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use log::info;
+use crate::hash::{GeneralHash, GeneralHashType};
+use crate::stages::build::cmd::walk::{Walk, WalkEntry};
+
+/// Find the file sizes that occur more than once.
+///
+/// # Arguments
+/// * `sizes` - The sizes of all files.
+///
+/// # Returns
+/// The sizes shared by at least two files.
+///
+/// # Example
+/// ```
+/// use std::collections::HashSet;
+/// use backup_deduplicator::stages::build::cmd::size_filter::shared_sizes;
+///
+/// let shared = shared_sizes([3, 5, 3, 7, 0, 0, 3]);
+/// assert_eq!(shared, HashSet::from([0, 3]));
+/// ```
+pub fn shared_sizes<I: IntoIterator<Item = u64>>(sizes: I) -> HashSet<u64> {
+    let mut counts: HashMap<u64, usize> = HashMap::new();
+    for size in sizes {
+        *counts.entry(size).or_insert(0) += 1;
+    }
+
+    counts.into_iter().filter(|(_, count)| *count >= 2).map(|(size, _)| size).collect()
+}
+
+/// Walk the directories to build and collect the sizes shared by at least two files.
+/// Traverses the file system the same way the build does (see [Walk]), without opening any file.
+///
+/// # Arguments
+/// * `roots` - The files and directories to build.
+/// * `walk` - The traversal rules of the build.
+///
+/// # Returns
+/// The sizes shared by at least two files, see [shared_sizes].
+///
+/// # Errors
+/// Never, unreadable entries are skipped. The result only follows the signature of [Walk::run].
+pub fn collect_shared_sizes(roots: &[PathBuf], walk: &Walk) -> Result<HashSet<u64>> {
+    let mut sizes = Vec::new();
+
+    walk.run(roots, |_, entry| {
+        if let WalkEntry::Leaf(metadata) = entry {
+            if metadata.is_file() {
+                sizes.push(metadata.len());
+            }
+        }
+        Ok(())
+    })?;
+
+    let files = sizes.len();
+    let shared = shared_sizes(sizes);
+    info!("Found {} files, {} distinct sizes are shared by more than one file", files, shared.len());
+
+    Ok(shared)
+}
+
+/// Compute the placeholder hash of a file whose size is unique in the build.
+/// Such a file can not have duplicates, so instead of its content, the path, size and
+/// modification time are hashed. The placeholder differs for every file, but does not
+/// describe the content.
+///
+/// # Arguments
+/// * `hash_type` - The hash algorithm to use.
+/// * `path` - The path of the file.
+/// * `size` - The size of the file.
+/// * `modified` - The last modified time of the file.
+///
+/// # Returns
+/// The placeholder hash.
+pub fn placeholder_hash(hash_type: GeneralHashType, path: &Path, size: u64, modified: u64) -> GeneralHash {
+    let mut hasher = hash_type.hasher();
+    hasher.update(path.as_os_str().as_encoded_bytes());
+    hasher.update(&[0]);
+    hasher.update(&size.to_le_bytes());
+    hasher.update(&modified.to_le_bytes());
+    hasher.finalize()
+}
@@ -0,0 +1,214 @@
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+
+/// The file system tool used to take a snapshot of a build root.
+///
+/// # Variants
+/// * `Btrfs` - A read-only snapshot of the btrfs subvolume at the root, created next to it.
+///   The root must be a subvolume.
+/// * `Zfs` - A snapshot of the zfs dataset containing the root, read through the `.zfs/snapshot`
+///   directory of the dataset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotKind {
+    Btrfs,
+    Zfs,
+}
+
+impl FromStr for SnapshotKind {
+    /// Error type for parsing a `SnapshotKind` from a string.
+    type Err = &'static str;
+
+    /// Parses a string into a `SnapshotKind`.
+    ///
+    /// # Arguments
+    /// * `s` - The string to parse.
+    ///
+    /// # Returns
+    /// The `SnapshotKind` that corresponds to the string or an error.
+    ///
+    /// # Errors
+    /// Returns the available kinds if the string does not correspond to a `SnapshotKind`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::str::FromStr;
+    /// use backup_deduplicator::stages::build::cmd::snapshot::SnapshotKind;
+    ///
+    /// assert_eq!(SnapshotKind::from_str("btrfs"), Ok(SnapshotKind::Btrfs));
+    /// assert_eq!(SnapshotKind::from_str("ZFS"), Ok(SnapshotKind::Zfs));
+    /// assert!(SnapshotKind::from_str("lvm").is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "btrfs" => Ok(SnapshotKind::Btrfs),
+            "zfs" => Ok(SnapshotKind::Zfs),
+            _ => Err("btrfs, zfs"),
+        }
+    }
+}
+
+impl Display for SnapshotKind {
+    /// Converts a `SnapshotKind` into a string.
+    ///
+    /// # Arguments
+    /// * `f` - The formatter to write to.
+    ///
+    /// # Returns
+    /// A result indicating whether the operation was successful.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotKind::Btrfs => write!(f, "btrfs"),
+            SnapshotKind::Zfs => write!(f, "zfs"),
+        }
+    }
+}
+
+/// A snapshot of a build root. The build reads the files from the snapshot, so that files changing
+/// while the build runs do not make the hash tree inconsistent. The snapshot is released when dropped.
+///
+/// # Fields
+/// * `kind` - The tool the snapshot was taken with.
+/// * `root` - The build root as given to the build.
+/// * `path` - The path of the root within the snapshot.
+/// * `handle` - The name of the snapshot for the tool, used to release it.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub kind: SnapshotKind,
+    pub root: PathBuf,
+    pub path: PathBuf,
+    handle: String,
+}
+
+impl Snapshot {
+    /// Take a snapshot of a build root by running `btrfs` or `zfs`.
+    ///
+    /// # Arguments
+    /// * `root` - The build root.
+    /// * `kind` - The tool to take the snapshot with.
+    /// * `index` - The index of the root, to name the snapshots of several roots differently.
+    ///
+    /// # Returns
+    /// The snapshot.
+    ///
+    /// # Errors
+    /// * If the root does not exist.
+    /// * If the tool is not installed or fails, e.g. since the root is not on a matching file system,
+    ///   is not a btrfs subvolume or the permissions are missing.
+    pub fn create(root: &Path, kind: SnapshotKind, index: usize) -> Result<Snapshot> {
+        let absolute = fs::canonicalize(root).map_err(|err| anyhow!("Failed to resolve the root {:?}: {}", root, err))?;
+        let name = format!("bdd-snapshot-{}-{}", std::process::id(), index);
+
+        let (path, handle) = match kind {
+            SnapshotKind::Btrfs => {
+                let parent = absolute.parent().ok_or_else(|| anyhow!("The root {:?} has no parent directory to create the snapshot in", root))?;
+                let path = parent.join(format!(".{}", name));
+                run_tool("btrfs", &[OsStr::new("subvolume"), OsStr::new("snapshot"), OsStr::new("-r"), absolute.as_os_str(), path.as_os_str()])?;
+                let handle = path.to_string_lossy().into_owned();
+                (path, handle)
+            },
+            SnapshotKind::Zfs => {
+                let dataset = run_tool("zfs", &[OsStr::new("list"), OsStr::new("-H"), OsStr::new("-o"), OsStr::new("name,mountpoint"), absolute.as_os_str()])?;
+                let (dataset, mountpoint) = dataset.trim().split_once('\t')
+                    .ok_or_else(|| anyhow!("Unexpected output of zfs list: {}", dataset.trim()))?;
+                let relative = absolute.strip_prefix(mountpoint)
+                    .map_err(|_| anyhow!("The root {:?} is not below the mountpoint {} of its dataset", root, mountpoint))?;
+                let handle = format!("{}@{}", dataset, name);
+                run_tool("zfs", &[OsStr::new("snapshot"), OsStr::new(&handle)])?;
+                (Path::new(mountpoint).join(".zfs").join("snapshot").join(&name).join(relative), handle)
+            },
+        };
+
+        info!("Took {} snapshot {} of {:?}", kind, handle, root);
+
+        Ok(Snapshot {
+            kind,
+            root: root.to_path_buf(),
+            path,
+            handle,
+        })
+    }
+
+    /// Release the snapshot by running `btrfs` or `zfs`.
+    ///
+    /// # Errors
+    /// If the tool fails.
+    fn release(&self) -> Result<()> {
+        match self.kind {
+            SnapshotKind::Btrfs => run_tool("btrfs", &[OsStr::new("subvolume"), OsStr::new("delete"), OsStr::new(&self.handle)])?,
+            SnapshotKind::Zfs => run_tool("zfs", &[OsStr::new("destroy"), OsStr::new(&self.handle)])?,
+        };
+
+        Ok(())
+    }
+}
+
+impl Drop for Snapshot {
+    /// Release the snapshot. A failure is logged, the snapshot then has to be removed by hand.
+    fn drop(&mut self) {
+        match self.release() {
+            Ok(_) => info!("Released {} snapshot {}", self.kind, self.handle),
+            Err(err) => warn!("Failed to release {} snapshot {}, remove it by hand: {}", self.kind, self.handle, err),
+        }
+    }
+}
+
+/// Run a file system tool and get its output.
+///
+/// # Arguments
+/// * `program` - The tool to run.
+/// * `args` - The arguments of the tool.
+///
+/// # Returns
+/// The standard output of the tool.
+///
+/// # Errors
+/// If the tool can not be started or exits unsuccessfully.
+fn run_tool(program: &str, args: &[&OsStr]) -> Result<String> {
+    let output = Command::new(program).args(args).output()
+        .map_err(|err| anyhow!("Failed to run {}: {}", program, err))?;
+
+    if !output.status.success() {
+        return Err(anyhow!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Get the path a file is read from during the build. Paths below a build root with a snapshot
+/// are read from the snapshot, other paths are read directly. The hash tree keeps the original paths.
+///
+/// # Arguments
+/// * `path` - The path of the file as stored in the hash tree.
+/// * `snapshots` - The build roots and the path of each root within its snapshot.
+///
+/// # Returns
+/// The path to read the file from.
+///
+/// # Example
+/// ```
+/// use std::path::{Path, PathBuf};
+/// use backup_deduplicator::stages::build::cmd::snapshot::snapshot_path;
+///
+/// let snapshots = vec![(PathBuf::from("data"), PathBuf::from("/mnt/.bdd-snapshot-1-0"))];
+/// assert_eq!(snapshot_path(Path::new("data/docs/a.txt"), &snapshots), PathBuf::from("/mnt/.bdd-snapshot-1-0/docs/a.txt"));
+/// assert_eq!(snapshot_path(Path::new("data"), &snapshots), PathBuf::from("/mnt/.bdd-snapshot-1-0"));
+/// assert_eq!(snapshot_path(Path::new("database/a.txt"), &snapshots), PathBuf::from("database/a.txt"));
+/// ```
+pub fn snapshot_path(path: &Path, snapshots: &[(PathBuf, PathBuf)]) -> PathBuf {
+    for (root, snapshot) in snapshots {
+        if let Ok(relative) = path.strip_prefix(root) {
+            return match relative.as_os_str().is_empty() {
+                true => snapshot.clone(),
+                false => snapshot.join(relative),
+            };
+        }
+    }
+
+    path.to_path_buf()
+}
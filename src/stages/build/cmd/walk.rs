@@ -0,0 +1,136 @@
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use log::{info, warn};
+use crate::stages::build::cmd::device_filter::DeviceFilter;
+use crate::stages::build::cmd::exclude::is_excluded;
+
+/// An entry found by a [Walk].
+///
+/// # Variants
+/// * `Directory` - A listed directory and the number of its children that are walked.
+/// * `UnreadableDirectory` - A directory that could not be listed.
+/// * `SkippedDirectory` - A directory on a device skipped by the device filter, it is not listed.
+/// * `Leaf` - A file, a symlink (if symlinks are not followed) or another entry, with its metadata.
+/// * `Unreadable` - An entry whose metadata could not be read.
+#[derive(Debug)]
+pub enum WalkEntry<'a> {
+    Directory(usize),
+    UnreadableDirectory(io::Error),
+    SkippedDirectory,
+    Leaf(&'a Metadata),
+    Unreadable(io::Error),
+}
+
+/// The traversal of the build directories without hashing, shared by the dry run and the walks
+/// before a build (collecting the file sizes, estimating the output size). It applies the rules of
+/// the build workers: the directory listings are sorted by name, symlinks are followed if enabled,
+/// the files written by the build are left out and directories on devices skipped by the device
+/// filter are not listed, except for the roots.
+///
+/// # Fields
+/// * `follow_symlinks` - Whether to follow symlinks when traversing the file system.
+/// * `device_filter` - Directories on the devices skipped by this filter are not traversed.
+/// * `excluded` - The files written by the build, they are not walked, see [is_excluded].
+pub struct Walk<'a> {
+    pub follow_symlinks: bool,
+    pub device_filter: &'a DeviceFilter,
+    pub excluded: &'a [PathBuf],
+}
+
+impl Walk<'_> {
+    /// Walk the roots depth-first in the order of the build traversal: a directory before its
+    /// children, every subtree before its next sibling.
+    ///
+    /// # Arguments
+    /// * `roots` - The files and directories to walk.
+    /// * `visit` - Called with the path of every entry found.
+    ///
+    /// # Errors
+    /// The first error returned by `visit`, the walk stops there.
+    ///
+    /// # Example
+    /// ```
+    /// use std::fs;
+    /// use backup_deduplicator::stages::build::cmd::device_filter::DeviceFilter;
+    /// use backup_deduplicator::stages::build::cmd::walk::{Walk, WalkEntry};
+    ///
+    /// let directory = std::env::temp_dir().join(format!("bdd-walk-example-{}", std::process::id()));
+    /// fs::create_dir_all(directory.join("sub")).unwrap();
+    /// fs::write(directory.join("sub").join("file"), "content").unwrap();
+    /// fs::write(directory.join("a"), "").unwrap();
+    ///
+    /// let walk = Walk { follow_symlinks: false, device_filter: &DeviceFilter::default(), excluded: &[] };
+    /// let mut found = Vec::new();
+    /// walk.run(&[directory.clone()], |path, entry| {
+    ///     let name = path.strip_prefix(&directory).unwrap().to_string_lossy().to_string();
+    ///     found.push(match entry {
+    ///         WalkEntry::Directory(children) => format!("{}/ ({})", name, children),
+    ///         _ => name,
+    ///     });
+    ///     Ok(())
+    /// }).unwrap();
+    ///
+    /// assert_eq!(found, vec!["/ (2)", "a", "sub/ (1)", "sub/file"]);
+    /// # fs::remove_dir_all(&directory).unwrap();
+    /// ```
+    pub fn run<F: FnMut(&Path, WalkEntry) -> Result<()>>(&self, roots: &[PathBuf], mut visit: F) -> Result<()> {
+        let mut stack: Vec<(PathBuf, bool)> = roots.iter().rev().map(|root| (root.clone(), true)).collect();
+
+        while let Some((path, is_root)) = stack.pop() {
+            let metadata = match self.follow_symlinks {
+                true => fs::metadata(&path),
+                false => fs::symlink_metadata(&path),
+            };
+
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    warn!("Failed to read metadata of {:?}: {}", path, err);
+                    visit(&path, WalkEntry::Unreadable(err))?;
+                    continue;
+                }
+            };
+
+            if !metadata.is_dir() {
+                visit(&path, WalkEntry::Leaf(&metadata))?;
+                continue;
+            }
+
+            // like the build, the roots are always listed
+            if !is_root && self.device_filter.skips_path(&path) {
+                info!("Not descending into {:?}, it is on another filesystem or an excluded device", path);
+                visit(&path, WalkEntry::SkippedDirectory)?;
+                continue;
+            }
+
+            let read_dir = match fs::read_dir(&path) {
+                Ok(read_dir) => read_dir,
+                Err(err) => {
+                    warn!("Error while reading directory {:?}: {}", path, err);
+                    visit(&path, WalkEntry::UnreadableDirectory(err))?;
+                    continue;
+                }
+            };
+
+            let mut children: Vec<PathBuf> = read_dir.filter_map(|entry| match entry {
+                Ok(entry) if is_excluded(self.excluded, &entry.path(), &entry.file_name()) => {
+                    info!("Not walking {:?}, it is written by the build", entry.path());
+                    None
+                },
+                Ok(entry) => Some(entry.path()),
+                Err(err) => {
+                    warn!("Error while reading directory entry {:?}: {}", path, err);
+                    None
+                }
+            }).collect();
+            children.sort_by(|a, b| b.cmp(a));
+
+            visit(&path, WalkEntry::Directory(children.len()))?;
+            stack.extend(children.into_iter().map(|child| (child, false)));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use std::io;
+use std::sync::mpsc::Sender;
+use serde::Serialize;
+use crate::path::FilePath;
+
+/// A notable problem the build or clean stage recovered from. Every warning is logged;
+/// applications embedding the stages can additionally receive them through a [WarningSink],
+/// e.g. to show them to the user.
+///
+/// # Variants
+/// * `DuplicateEntry` - The hash tree contains several entries for the same path, the last one is used.
+/// * `HashTypeMismatch` - An entry of the hash tree was hashed with another algorithm than given in the header
+///   and is ignored.
+/// * `PermissionDenied` - A file or directory could not be read due to missing permissions.
+/// * `SymlinkLoop` - A symlink could not be resolved since it (indirectly) points to itself.
+/// * `Unreadable` - A file or directory could not be read for another reason.
+/// * `Unchecked` - The clean stage could not check if the file of an entry still exists, the entry is kept.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum BuildWarning {
+    DuplicateEntry { path: FilePath },
+    HashTypeMismatch { path: FilePath },
+    PermissionDenied { path: FilePath, reason: String },
+    SymlinkLoop { path: FilePath, reason: String },
+    Unreadable { path: FilePath, reason: String },
+    Unchecked { path: FilePath, reason: String },
+}
+
+impl BuildWarning {
+    /// Create the warning for a file or directory that could not be read.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the file or directory.
+    /// * `reason` - The description of the error.
+    /// * `cause` - The underlying IO error if known, used to tell missing permissions and symlink loops apart.
+    ///
+    /// # Returns
+    /// The warning matching the cause, [BuildWarning::Unreadable] if there is none.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io;
+    /// use std::path::PathBuf;
+    /// use backup_deduplicator::path::FilePath;
+    /// use backup_deduplicator::stages::build::cmd::warning::BuildWarning;
+    ///
+    /// let path = FilePath::from_realpath(PathBuf::from("secret.txt"));
+    /// let err = io::Error::from(io::ErrorKind::PermissionDenied);
+    ///
+    /// let warning = BuildWarning::unreadable(path.clone(), "Failed to open the file".to_string(), Some(&err));
+    /// assert!(matches!(warning, BuildWarning::PermissionDenied { .. }));
+    ///
+    /// let warning = BuildWarning::unreadable(path, "Failed to hash the directory".to_string(), None);
+    /// assert!(matches!(warning, BuildWarning::Unreadable { .. }));
+    /// ```
+    pub fn unreadable(path: FilePath, reason: String, cause: Option<&io::Error>) -> BuildWarning {
+        match cause {
+            Some(err) if err.kind() == io::ErrorKind::PermissionDenied => BuildWarning::PermissionDenied { path, reason },
+            Some(err) if is_symlink_loop(err) => BuildWarning::SymlinkLoop { path, reason },
+            _ => BuildWarning::Unreadable { path, reason },
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_symlink_loop(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ELOOP)
+}
+
+#[cfg(not(unix))]
+fn is_symlink_loop(_err: &io::Error) -> bool {
+    false
+}
+
+/// An optional receiver of [BuildWarning]s.
+pub type WarningSink = Option<Sender<BuildWarning>>;
+
+/// Push a warning to a sink, if set. A disconnected receiver is ignored, the warning is logged anyway.
+///
+/// # Arguments
+/// * `sink` - The sink to push the warning to.
+/// * `warning` - The warning to push.
+pub fn send_warning(sink: &WarningSink, warning: BuildWarning) {
+    if let Some(sender) = sink {
+        let _ = sender.send(warning);
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// The interval in which a wait checks whether it was cancelled.
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits between the updates of `build --watch` until a file below the build roots changed.
+/// Changes are reported by the file system (inotify, FSEvents, ReadDirectoryChangesW). If the roots
+/// can not be watched, e.g. since the limit of inotify watches is reached, every wait ends after
+/// the interval and the next update looks for changes itself.
+///
+/// # Fields
+/// * `watcher` - The file system watcher, none if the roots are polled.
+/// * `events` - The events reported by the watcher.
+/// * `ignored` - The files written by the build, changing them does not start an update.
+pub struct ChangeWatcher {
+    watcher: Option<RecommendedWatcher>,
+    events: Receiver<notify::Result<Event>>,
+    ignored: Vec<PathBuf>,
+}
+
+impl ChangeWatcher {
+    /// Start watching the build roots.
+    ///
+    /// # Arguments
+    /// * `roots` - The files and directories of the build, directories are watched recursively.
+    /// * `ignored` - The files written by the build, changing them does not start an update.
+    ///
+    /// # Returns
+    /// The watcher, it falls back to polling if a root can not be watched.
+    pub fn new(roots: &[PathBuf], ignored: Vec<PathBuf>) -> Self {
+        let (sender, events) = channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        }).and_then(|mut watcher| {
+            for root in roots {
+                let mode = match root.is_dir() {
+                    true => RecursiveMode::Recursive,
+                    false => RecursiveMode::NonRecursive,
+                };
+                watcher.watch(root, mode)?;
+            }
+            Ok(watcher)
+        });
+
+        let watcher = match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!("Failed to watch the build directories for changes, updating the hash tree periodically instead: {}", err);
+                None
+            }
+        };
+
+        ChangeWatcher { watcher, events, ignored }
+    }
+
+    /// Wait for the next update. Waits at least the interval, so that the changes of a longer
+    /// operation (e.g. a copy) are picked up by one update, and then until a file changed.
+    /// Changes reported while the last update ran count as well.
+    ///
+    /// # Arguments
+    /// * `interval` - The minimum time between two updates.
+    /// * `cancel` - Ends the wait early if set.
+    ///
+    /// # Returns
+    /// False if the wait was cancelled.
+    pub fn wait(&self, interval: Duration, cancel: &AtomicBool) -> bool {
+        let start = Instant::now();
+        let mut changed = self.watcher.is_none();
+
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return false;
+            }
+            if changed && start.elapsed() >= interval {
+                return true;
+            }
+
+            match self.events.recv_timeout(CANCEL_CHECK_INTERVAL) {
+                Ok(Ok(event)) => if !changed && self.is_relevant(&event) {
+                    debug!("Change detected: {:?} {:?}", event.kind, event.paths);
+                    changed = true;
+                },
+                Ok(Err(err)) => {
+                    // e.g. an overflow of the event queue, the update finds the changes
+                    warn!("Error while watching for changes: {}", err);
+                    changed = true;
+                },
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => {
+                    info!("Watching for changes stopped, updating the hash tree periodically instead");
+                    changed = true;
+                },
+            }
+        }
+    }
+
+    /// Check if an event changes the hash tree. Reading a file does not, neither do changes
+    /// of the files written by the build (and their temporary files).
+    ///
+    /// # Arguments
+    /// * `event` - The reported event.
+    ///
+    /// # Returns
+    /// True if the event starts an update.
+    fn is_relevant(&self, event: &Event) -> bool {
+        if matches!(event.kind, EventKind::Access(_)) {
+            return false;
+        }
+
+        event.paths.is_empty() || event.paths.iter().any(|path| !self.is_ignored(path))
+    }
+
+    /// Check if a path is a file written by the build.
+    ///
+    /// # Arguments
+    /// * `path` - The changed path.
+    ///
+    /// # Returns
+    /// True if changes of the path are ignored.
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.ignored.iter().any(|ignored| ignored == path)
+    }
+}
@@ -1,19 +1,33 @@
 use crate::stages::build::intermediary_build_data::{BuildFile, BuildOtherInformation, BuildStubInformation};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::sync::Arc;
+use std::io;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
 use std::time::SystemTime;
 use anyhow::anyhow;
 use log::{error, info, trace, warn};
-use crate::hash::GeneralHashType;
+use crate::fileid::HandleIdentifier;
+use crate::hash::{DirHashMode, FileIdentity, GeneralHash, GeneralHashType};
 use crate::path::FilePath;
-use crate::stages::build::cmd::job::{BuildJob, JobResult, JobResultContent};
+use crate::stages::build::cmd::cache::BuildCache;
+use crate::stages::build::cmd::device_filter::DeviceFilter;
+use crate::stages::build::cmd::job::{BuildJob, JobPanicInformation, JobResult, JobResultContent};
+use crate::stages::build::cmd::progress::BuildProgress;
+use crate::stages::build::cmd::snapshot::snapshot_path;
+use crate::stages::build::cmd::UnreadableFile;
+use crate::stages::build::cmd::warning::{send_warning, BuildWarning, WarningSink};
 use crate::stages::build::cmd::worker::directory::worker_run_directory;
-use crate::stages::build::cmd::worker::file::worker_run_file;
+use crate::stages::build::cmd::worker::file::{worker_run_file, FileMetadata};
 use crate::stages::build::cmd::worker::other::worker_run_other;
 use crate::stages::build::cmd::worker::symlink::worker_run_symlink;
 use crate::stages::build::output::HashTreeFileEntry;
+use crate::utils::Semaphore;
+use crate::xattr::hash_xattrs;
 
 mod directory;
 mod file;
@@ -25,15 +39,64 @@ mod symlink;
 /// # Fields
 /// * `follow_symlinks` - Whether to follow symlinks when traversing the file system.
 /// * `hash_type` - The hash algorithm to use for hashing files.
-/// * `save_file_by_path` - A hash map of [FilePath] -> [HashTreeFileEntry].
+/// * `save_file_by_path` - The entries of the previous build by [FilePath], see [BuildCache].
+/// * `hash_type_by_extension` - A hash map of lowercase file extension -> [GeneralHashType], overriding `hash_type` for files.
+/// * `dir_hash_mode` - How directory hashes are computed from their children.
+/// * `file_identity` - Whether the file name is mixed into the content hash of files.
+/// * `progress` - The progress counters of the build.
+/// * `device_filter` - Directories on the devices skipped by this filter are not traversed.
+/// * `capture_xattrs` - Whether to record a hash of the extended attributes of files and directories (Linux only).
+/// * `capture_file_ids` - Whether to record the inode and device of files, to recognize hardlinks.
+/// * `capture_btime` - Whether to record the creation (birth) time of files and directories.
+/// * `shared_sizes` - If set, only files with one of these sizes are hashed, the others get a placeholder hash,
+///   see [placeholder_hash](crate::stages::build::cmd::size_filter::placeholder_hash).
+/// * `detect_content_type` - Whether to detect the MIME type of files from their leading bytes.
+/// * `mtime_tolerance` - Modification times within this many seconds of the previous build count as unchanged, see [modified_matches].
+/// * `since` - If set, files last modified before this time (in seconds since the Unix epoch) are not hashed
+///   but recorded as `Other`, unless the continued hash tree has an unchanged entry for them.
+/// * `mmap_threshold` - If set, files of at least this size in bytes are memory mapped for hashing.
+/// * `external_roots` - If set, the canonical build roots. Symlinks to regular files outside of them are
+///   hashed as copies of their target, see [worker_external_symlink_target].
+/// * `snapshots` - If set, the build roots and their paths within a snapshot. Files below these roots are
+///   read from the snapshot, see [snapshot_path].
+/// * `excluded` - The canonical paths of the files written by the build, they are not hashed, see [is_excluded](crate::stages::build::cmd::exclude::is_excluded).
+/// * `warnings` - If set, the warnings of the workers are sent to this channel in addition to logging them.
+/// * `unreadable` - The files and directories that could not be read so far, with the reason.
+/// * `cancel` - If set, the build was cancelled and the remaining jobs are skipped.
+/// * `open_files` - If set, a permit is acquired before opening a file or directory, bounding the open file descriptors.
+/// * `capture_physical_size` - Whether to record the size allocated on disk for files (Unix only).
 pub struct WorkerArgument {
     pub follow_symlinks: bool,
     pub hash_type: GeneralHashType,
-    pub save_file_by_path: Arc<HashMap<FilePath, HashTreeFileEntry>>,
+    pub save_file_by_path: Arc<dyn BuildCache>,
+    pub hash_type_by_extension: Arc<HashMap<String, GeneralHashType>>,
+    pub dir_hash_mode: DirHashMode,
+    pub file_identity: FileIdentity,
+    pub progress: Arc<BuildProgress>,
+    pub device_filter: Arc<DeviceFilter>,
+    pub capture_physical_size: bool,
+    pub open_files: Option<Arc<Semaphore>>,
+    pub capture_xattrs: bool,
+    pub capture_file_ids: bool,
+    pub capture_btime: bool,
+    pub shared_sizes: Option<Arc<HashSet<u64>>>,
+    pub detect_content_type: bool,
+    pub mtime_tolerance: u64,
+    pub since: Option<u64>,
+    pub mmap_threshold: Option<u64>,
+    pub external_roots: Option<Arc<Vec<PathBuf>>>,
+    pub snapshots: Option<Arc<Vec<(PathBuf, PathBuf)>>>,
+    pub excluded: Arc<Vec<PathBuf>>,
+    pub warnings: WarningSink,
+    pub unreadable: Arc<Mutex<Vec<UnreadableFile>>>,
+    pub cancel: Arc<AtomicBool>,
 }
 
 /// Main function for the worker thread.
 /// 
+/// A panic while processing the job is caught and published as [JobResult::Panicked],
+/// so that the build can fail with an error instead of waiting for the job forever.
+/// 
 /// # Arguments
 /// * `id` - The id of the worker.
 /// * `job` - The job to process.
@@ -41,16 +104,57 @@ pub struct WorkerArgument {
 /// * `job_publish` - The channel to publish new jobs to.
 /// * `arg` - The argument for the worker thread.
 pub fn worker_run(id: usize, job: BuildJob, result_publish: &Sender<JobResult>, job_publish: &Sender<BuildJob>, arg: &mut WorkerArgument) {
+    if arg.cancel.load(Ordering::Relaxed) {
+        trace!("[{}] build cancelled, skipping {}", id, job.target_path);
+        return;
+    }
+    
+    let path = job.target_path.clone();
+    
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        worker_run_job(id, job, result_publish, job_publish, arg);
+    }));
+    
+    if let Err(payload) = result {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match payload.downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => String::from("unknown panic"),
+            },
+        };
+        
+        error!("[{}] panicked while processing {}: {}", id, path, message);
+        worker_publish_result(id, result_publish, JobResult::Panicked(JobPanicInformation {
+            path,
+            message,
+        }));
+    }
+}
+
+/// Process a single job, dispatching on the file type.
+/// 
+/// # Arguments
+/// * `id` - The id of the worker.
+/// * `job` - The job to process.
+/// * `result_publish` - The channel to publish the result to.
+/// * `job_publish` - The channel to publish new jobs to.
+/// * `arg` - The argument for the worker thread.
+fn worker_run_job(id: usize, job: BuildJob, result_publish: &Sender<JobResult>, job_publish: &Sender<BuildJob>, arg: &mut WorkerArgument) {
     let path = job.target_path.resolve_file();
     let path = match path {
         Ok(file) => file,
         Err(e) => {
             error!("[{}] failed to resolve file: {}", id, e);
             info!("[{}] Skipping file...", id);
-            worker_publish_result_or_trigger_parent(id, false, worker_create_error(job.target_path.clone(), 0, 0), job, result_publish, job_publish, arg);
+            worker_publish_result_or_trigger_parent(id, false, worker_create_error(arg, job.target_path.clone(), 0, 0, format!("Failed to resolve the path: {}", e), None), job, result_publish, job_publish, arg);
             return;
         }
     };
+    let path = match &arg.snapshots {
+        Some(snapshots) => snapshot_path(&path, snapshots),
+        None => path,
+    };
 
     let metadata = match arg.follow_symlinks {
         true => fs::metadata(&path),
@@ -62,10 +166,18 @@ pub fn worker_run(id: usize, job: BuildJob, result_publish: &Sender<JobResult>,
         Err(e) => {
             warn!("[{}] failed to read metadata: {}", id, e);
             info!("[{}] Skipping file...", id);
-            worker_publish_result_or_trigger_parent(id, false, worker_create_error(job.target_path.clone(), 0, 0), job, result_publish, job_publish, arg);
+            worker_publish_result_or_trigger_parent(id, false, worker_create_error(arg, job.target_path.clone(), 0, 0, format!("Failed to read the metadata: {}", e), Some(&e)), job, result_publish, job_publish, arg);
             return;
         }
     };
+    
+    let metadata = match worker_external_symlink_target(arg, &path, &metadata) {
+        Some(target_metadata) => {
+            trace!("[{}] hashing external symlink target of {:?} as copy", id, path);
+            target_metadata
+        },
+        None => metadata,
+    };
 
     let modified_result = metadata.modified()
         .map(|time| time.duration_since(SystemTime::UNIX_EPOCH)
@@ -90,15 +202,48 @@ pub fn worker_run(id: usize, job: BuildJob, result_publish: &Sender<JobResult>,
 
     if metadata.is_symlink() {
         worker_run_symlink(path, modified, size, id, job, result_publish, job_publish, arg);
+    } else if metadata.is_dir() && job.parent.is_some() && arg.device_filter.skips_path(&path) {
+        // the roots are always listed, a skipped mount point is kept as an entry without content
+        info!("[{}] Not descending into {:?}, it is on another filesystem or an excluded device", id, path);
+        worker_run_other(path, modified, size, id, job, result_publish, job_publish, arg);
     } else if metadata.is_dir() {
         worker_run_directory(path, modified, size, id, job, result_publish, job_publish, arg);
     } else if metadata.is_file() {
-        worker_run_file(path, modified, size, id, job, result_publish, job_publish, arg);
+        let physical_size = match arg.capture_physical_size {
+            true => worker_physical_size(&metadata),
+            false => None,
+        };
+        worker_run_file(FileMetadata { path, modified, size, physical_size }, id, job, result_publish, job_publish, arg);
     } else {
         worker_run_other(path, modified, size, id, job, result_publish, job_publish, arg);
     }
 }
 
+/// Check if a symlink points to a regular file outside of the build roots that should be
+/// hashed as if it was copied into the tree (`external_roots` of the [WorkerArgument] set).
+/// Symlinks to targets within the roots, to directories or to missing files stay symlinks.
+/// 
+/// # Arguments
+/// * `arg` - The argument for the worker thread.
+/// * `path` - The path of the entry.
+/// * `metadata` - The metadata of the entry itself, not following symlinks.
+/// 
+/// # Returns
+/// The metadata of the target if the entry should be hashed as a copy of it, else None.
+fn worker_external_symlink_target(arg: &WorkerArgument, path: &Path, metadata: &fs::Metadata) -> Option<fs::Metadata> {
+    let roots = arg.external_roots.as_ref()?;
+    if !metadata.is_symlink() {
+        return None;
+    }
+    
+    let target = fs::canonicalize(path).ok()?;
+    if roots.iter().any(|root| target.starts_with(root)) {
+        return None;
+    }
+    
+    fs::metadata(&target).ok().filter(|target_metadata| target_metadata.is_file())
+}
+
 /// Publish a result to the result channel.
 /// Processes the error if the result could not be published.
 /// 
@@ -114,16 +259,30 @@ fn worker_publish_result(id: usize, result_publish: &Sender<JobResult>, result:
 }
 
 /// Create a [File::Other] with the given information.
-/// Used when an error occurs.
+/// Used when an error occurs, counts the error in the build progress, records it as unreadable file
+/// and sends it as [BuildWarning] if a warning sink is set.
 /// 
 /// # Arguments
+/// * `args` - The argument for the worker thread.
 /// * `path` - The path of the file.
 /// * `modified` - The modified date of the file.
 /// * `size` - The size of the file.
+/// * `reason` - The description of the error.
+/// * `cause` - The underlying IO error, if any.
 /// 
 /// # Returns
 /// The created [File::Other].
-fn worker_create_error(path: FilePath, modified: u64, size: u64) -> BuildFile {
+fn worker_create_error(args: &WorkerArgument, path: FilePath, modified: u64, size: u64, reason: String, cause: Option<&io::Error>) -> BuildFile {
+    args.progress.add_error();
+    send_warning(&args.warnings, BuildWarning::unreadable(path.clone(), reason.clone(), cause));
+    match args.unreadable.lock() {
+        Ok(mut unreadable) => unreadable.push(UnreadableFile {
+            path: path.clone(),
+            occurred_at: crate::utils::get_time(),
+            reason,
+        }),
+        Err(err) => error!("Failed to record unreadable file {}: {}", path, err),
+    }
     BuildFile::Other(BuildOtherInformation {
         path,
         modified,
@@ -163,11 +322,13 @@ fn worker_publish_result_or_trigger_parent(id: usize, cached: bool, result: Buil
     let parent_job;
 
     let hash;
+    let subtree_bytes;
 
     match job.parent {
         Some(parent) => {
             parent_job = parent;
             hash = result.get_content_hash().to_owned();
+            subtree_bytes = result.get_subtree_bytes();
             worker_publish_result(id, result_publish, JobResult::Intermediate(JobResultContent {already_cached: cached, content: result}));
         },
         None => {
@@ -181,6 +342,7 @@ fn worker_publish_result_or_trigger_parent(id: usize, cached: bool, result: Buil
             finished.push(BuildFile::Stub(BuildStubInformation {
                 path: job.target_path,
                 content_hash: hash,
+                subtree_bytes,
             }));
         },
         Err(err) => {
@@ -200,6 +362,134 @@ fn worker_publish_result_or_trigger_parent(id: usize, cached: bool, result: Buil
     }
 }
 
+/// Determine the size allocated on disk for a file.
+/// 
+/// # Arguments
+/// * `metadata` - The metadata of the file.
+/// 
+/// # Returns
+/// The number of allocated 512 byte blocks times 512. None on platforms without block counts (non Unix).
+#[cfg(unix)]
+pub fn worker_physical_size(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.blocks() * 512)
+}
+
+/// Determine the size allocated on disk for a file.
+/// 
+/// # Arguments
+/// * `metadata` - The metadata of the file.
+/// 
+/// # Returns
+/// None, the allocated size is only available on Unix.
+#[cfg(not(unix))]
+pub fn worker_physical_size(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// Hash the extended attributes of a file or directory, if enabled.
+/// 
+/// # Arguments
+/// * `args` - The argument for the worker thread.
+/// * `path` - The path of the file or directory.
+/// 
+/// # Returns
+/// The hash of the extended attributes. None if not enabled, not supported by the
+/// platform or file system, or the attributes can not be read.
+fn worker_xattr_hash(args: &WorkerArgument, path: &Path) -> Option<GeneralHash> {
+    if !args.capture_xattrs {
+        return None;
+    }
+    
+    match hash_xattrs(path, args.hash_type, args.follow_symlinks) {
+        Ok(hash) => hash,
+        Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {
+            trace!("File system of {:?} does not support extended attributes", path);
+            None
+        },
+        Err(err) => {
+            warn!("Failed to read extended attributes of {:?}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Determine the inode and device of a file, if enabled.
+/// 
+/// # Arguments
+/// * `args` - The argument for the worker thread.
+/// * `path` - The path of the file.
+/// 
+/// # Returns
+/// The id of the file. None if not enabled or the id can not be read.
+fn worker_file_id(args: &WorkerArgument, path: &Path) -> Option<HandleIdentifier> {
+    if !args.capture_file_ids {
+        return None;
+    }
+    
+    match HandleIdentifier::from_path(path) {
+        Ok(id) => Some(id),
+        Err(err) => {
+            warn!("Failed to read the file id of {:?}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Determine the creation (birth) time of a file or directory, if enabled.
+/// 
+/// # Arguments
+/// * `args` - The argument for the worker thread.
+/// * `path` - The path of the file or directory.
+/// 
+/// # Returns
+/// The creation time in unix time. None if not enabled, not recorded by the platform or
+/// file system, or the metadata can not be read.
+fn worker_created(args: &WorkerArgument, path: &Path) -> Option<u64> {
+    if !args.capture_btime {
+        return None;
+    }
+    
+    let metadata = match args.follow_symlinks {
+        true => fs::metadata(path),
+        false => fs::symlink_metadata(path),
+    };
+    
+    match metadata.and_then(|metadata| metadata.created()) {
+        Ok(time) => match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(duration) => Some(duration.as_secs()),
+            Err(_) => {
+                warn!("Unable to convert creation date of {:?} to UNIX_EPOCH", path);
+                None
+            }
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {
+            trace!("Creation time of {:?} is not available", path);
+            None
+        },
+        Err(err) => {
+            warn!("Failed to read the creation time of {:?}: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Determine the hash algorithm used to hash the content of a file.
+/// 
+/// # Arguments
+/// * `args` - The argument for the worker thread.
+/// * `path` - The path of the file to hash.
+/// 
+/// # Returns
+/// The hash type configured for the file extension, or the default hash type.
+fn worker_file_hash_type(args: &WorkerArgument, path: &Path) -> GeneralHashType {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(|extension| args.hash_type_by_extension.get(&extension.to_lowercase()))
+        .copied()
+        .unwrap_or(args.hash_type)
+}
+
 /// Fetch the saved data for a file.
 /// 
 /// # Arguments
@@ -208,6 +498,32 @@ fn worker_publish_result_or_trigger_parent(id: usize, cached: bool, result: Buil
 /// 
 /// # Returns
 /// The saved data for the file if it exists.
-fn worker_fetch_savedata<'a, 'b>(args: &'a WorkerArgument, path: &'b FilePath) -> Option<&'a HashTreeFileEntry> {
+fn worker_fetch_savedata(args: &WorkerArgument, path: &FilePath) -> Option<Arc<HashTreeFileEntry>> {
     args.save_file_by_path.get(path)
 }
+
+/// Check whether the modification time of a file matches the one recorded by a previous build.
+/// Times that differ by at most `tolerance` seconds are treated as equal, to ignore the clock
+/// skew between the systems a backup was copied between.
+/// 
+/// # Arguments
+/// * `recorded` - The modification time recorded by the previous build.
+/// * `modified` - The current modification time.
+/// * `tolerance` - The allowed difference in seconds.
+/// 
+/// # Returns
+/// True if the modification time is considered unchanged.
+/// 
+/// # Example
+/// ```
+/// use backup_deduplicator::stages::build::cmd::worker::modified_matches;
+/// 
+/// assert!(modified_matches(1000, 1000, 0));
+/// assert!(!modified_matches(1000, 1003, 0));
+/// assert!(modified_matches(1000, 1003, 5));
+/// assert!(modified_matches(1003, 1000, 5));
+/// assert!(!modified_matches(1000, 1006, 5));
+/// ```
+pub fn modified_matches(recorded: u64, modified: u64, tolerance: u64) -> bool {
+    recorded.abs_diff(modified) <= tolerance
+}
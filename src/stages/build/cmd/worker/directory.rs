@@ -4,12 +4,14 @@ use std::ops::DerefMut;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::mpsc::Sender;
-use log::{error, trace};
+use log::{error, info, trace};
 use crate::stages::build::intermediary_build_data::{BuildDirectoryInformation, BuildFile};
-use crate::hash::GeneralHash;
+use crate::hash::{DirHashMode, GeneralHash};
 use crate::stages::build::cmd::job::{BuildJob, BuildJobState, JobResult};
-use crate::stages::build::cmd::worker::{worker_create_error, worker_fetch_savedata, worker_publish_result_or_trigger_parent, WorkerArgument};
+use crate::stages::build::cmd::exclude::is_excluded;
+use crate::stages::build::cmd::worker::{worker_create_error, worker_fetch_savedata, worker_created, worker_publish_result_or_trigger_parent, worker_xattr_hash, WorkerArgument, modified_matches};
 use crate::stages::build::output::HashTreeFileEntryType;
+use crate::utils::Semaphore;
 
 /// Analyze a directory.
 /// 
@@ -27,12 +29,15 @@ pub fn worker_run_directory(path: PathBuf, modified: u64, size: u64, id: usize,
 
     match job.state {
         BuildJobState::NotProcessed => {
+            let open_files = arg.open_files.clone();
+            let permit = open_files.as_deref().map(Semaphore::acquire);
+            
             let read_dir = fs::read_dir(&path);
             let read_dir = match read_dir {
                 Ok(read_dir) => read_dir,
                 Err(err) => {
                     error!("Error while reading directory {:?}: {}", path, err);
-                    worker_publish_result_or_trigger_parent(id, false, worker_create_error(job.target_path.clone(), modified, size), job, result_publish, job_publish, arg);
+                    worker_publish_result_or_trigger_parent(id, false, worker_create_error(arg, job.target_path.clone(), modified, size, format!("Failed to read the directory: {}", err), Some(&err)), job, result_publish, job_publish, arg);
                     return;
                 }
             };
@@ -49,10 +54,28 @@ pub fn worker_run_directory(path: PathBuf, modified: u64, size: u64, id: usize,
                     }
                 }).collect();
             read_dir.sort_by_key(|entry| entry.file_name());
+            drop(permit);
 
             let mut children = Vec::new();
 
             for entry in read_dir {
+                if is_excluded(&arg.excluded, &entry.path(), &entry.file_name()) {
+                    info!("[{}] Not hashing {:?}, it is written by the build", id, entry.path());
+                    continue;
+                }
+                
+                if arg.progress.track_discovered {
+                    let metadata = match arg.follow_symlinks {
+                        true => fs::metadata(entry.path()),
+                        false => entry.metadata(),
+                    };
+                    if let Ok(metadata) = metadata {
+                        if metadata.is_file() {
+                            arg.progress.add_discovered(&job.target_path, metadata.len());
+                        }
+                    }
+                }
+                
                 let child_path = job.target_path.child(entry.file_name());
                 children.push(child_path);
             }
@@ -67,7 +90,11 @@ pub fn worker_run_directory(path: PathBuf, modified: u64, size: u64, id: usize,
                 jobs.push(job);
             }
 
-            drop(parent_job);
+            match jobs.is_empty() {
+                // an empty directory has no child that triggers its analysis
+                true => jobs.extend(Arc::into_inner(parent_job).map(BuildJob::new_job_id)),
+                false => drop(parent_job),
+            }
 
             for job in jobs {
                 match job_publish.send(job) {
@@ -83,29 +110,41 @@ pub fn worker_run_directory(path: PathBuf, modified: u64, size: u64, id: usize,
             let mut children = Vec::new();
 
             let mut cached_entry = None;
+            let mut cached_attributes_unchanged = true;
             let mut error;
+            let mut subtree_bytes = 0;
             match job.finished_children.lock() {
                 Ok(mut finished) => {
-                    finished.sort_by(|a, b| a.get_content_hash().partial_cmp(b.get_content_hash()).expect("Two hashes must compare to each other"));
+                    finished.sort_by(|a, b| a.get_content_hash().partial_cmp(b.get_content_hash()).expect("Two hashes must compare to each other")
+                        .then_with(|| a.get_path().path.last().map(|component| &component.path).cmp(&b.get_path().path.last().map(|component| &component.path))));
 
                     error = false;
+                    subtree_bytes = finished.iter().map(|child| child.get_subtree_bytes()).sum();
                     
                     // query cache
                     match worker_fetch_savedata(arg, &job.target_path) {
                         Some(found) => {
-                            if found.file_type == HashTreeFileEntryType::Directory && found.modified == modified && found.size == finished.len() as u64 {
+                            if found.file_type == HashTreeFileEntryType::Directory && modified_matches(found.modified, modified, arg.mtime_tolerance) && found.size == finished.len() as u64 && found.subtree_bytes == Some(subtree_bytes) {
                                 if found.children.len() == finished.len() && found.children.iter().zip(finished.iter().map(|e| e.get_content_hash())).all(|(a, b)| a == b) {
                                     trace!("Directory {:?} is already in save file", path);
 
                                     let mut children = Vec::new();
                                     children.append(finished.deref_mut());
 
+                                    // attribute and creation time changes do not touch the modification time, re-publish the entry if they changed
+                                    let xattr_hash = worker_xattr_hash(arg, &path);
+                                    let created = worker_created(arg, &path);
+                                    cached_attributes_unchanged = found.xattr_hash == xattr_hash && found.created == created;
+
                                     let file = BuildFile::Directory(BuildDirectoryInformation {
                                         path: job.target_path.clone(),
                                         modified,
                                         content_hash: found.hash.clone(),
                                         number_of_children: children.len() as u64,
+                                        subtree_bytes,
                                         children,
+                                        xattr_hash,
+                                        created,
                                     });
 
                                     cached_entry = Some(file);
@@ -116,7 +155,11 @@ pub fn worker_run_directory(path: PathBuf, modified: u64, size: u64, id: usize,
                     }
 
                     if cached_entry.is_none() {
-                        match hash.hash_directory(finished.iter()) {
+                        let result = match arg.dir_hash_mode {
+                            DirHashMode::ContentOnly => hash.hash_directory(finished.iter()),
+                            DirHashMode::ContentAndNames => hash.hash_directory_with_names(finished.iter()),
+                        };
+                        match result {
                             Ok(_) => {},
                             Err(err) => {
                                 error = true;
@@ -132,12 +175,12 @@ pub fn worker_run_directory(path: PathBuf, modified: u64, size: u64, id: usize,
                 }
             }
             if error {
-                worker_publish_result_or_trigger_parent(id, false, worker_create_error(job.target_path.clone(), modified, size), job, result_publish, job_publish, arg);
+                worker_publish_result_or_trigger_parent(id, false, worker_create_error(arg, job.target_path.clone(), modified, size, "Failed to hash the directory".to_string(), None), job, result_publish, job_publish, arg);
                 return;
             }
 
             if let Some(file) = cached_entry {
-                worker_publish_result_or_trigger_parent(id, true, file, job, result_publish, job_publish, arg);
+                worker_publish_result_or_trigger_parent(id, cached_attributes_unchanged, file, job, result_publish, job_publish, arg);
                 return;
             }
 
@@ -146,10 +189,13 @@ pub fn worker_run_directory(path: PathBuf, modified: u64, size: u64, id: usize,
                 modified,
                 content_hash: hash,
                 number_of_children: children.len() as u64,
+                subtree_bytes,
                 children,
+                xattr_hash: worker_xattr_hash(arg, &path),
+                created: worker_created(arg, &path),
             });
 
             worker_publish_result_or_trigger_parent(id, false, file, job, result_publish, job_publish, arg);
         }
     }
-}
\ No newline at end of file
+}
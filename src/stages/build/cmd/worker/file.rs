@@ -1,37 +1,72 @@
 use crate::stages::build::cmd::worker::GeneralHashType;
+use crate::content_type::{detect_content_type, DETECTION_BYTES};
 use crate::hash::GeneralHash;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{BufRead, Read};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
-use log::{error, trace};
-use crate::stages::build::intermediary_build_data::{BuildFile, BuildFileInformation};
+use log::{error, trace, warn};
+use crate::stages::build::intermediary_build_data::{BuildFile, BuildFileInformation, BuildOtherInformation};
 use crate::stages::build::cmd::job::{BuildJob, JobResult};
-use crate::stages::build::cmd::worker::{worker_create_error, worker_fetch_savedata, worker_publish_result_or_trigger_parent, WorkerArgument};
+use crate::stages::build::cmd::size_filter::placeholder_hash;
+use crate::stages::build::cmd::worker::{worker_create_error, worker_fetch_savedata, worker_file_hash_type, worker_created, worker_file_id, worker_publish_result_or_trigger_parent, worker_xattr_hash, WorkerArgument, modified_matches};
 use crate::stages::build::output::HashTreeFileEntryType;
+use crate::utils::Semaphore;
 
-/// Analyze a file.
+/// The metadata of a file to analyze, read before dispatching on the file type.
 /// 
-/// # Arguments
+/// # Fields
 /// * `path` - The path to the file.
 /// * `modified` - The last modified time of the file.
 /// * `size` - The size of the file (given by fs::metadata).
+/// * `physical_size` - The size allocated on disk, if captured.
+pub struct FileMetadata {
+    pub path: PathBuf,
+    pub modified: u64,
+    pub size: u64,
+    pub physical_size: Option<u64>,
+}
+
+/// Analyze a file.
+/// 
+/// # Arguments
+/// * `file` - The metadata of the file.
 /// * `id` - The id of the worker.
 /// * `job` - The job to process.
 /// * `result_publish` - The channel to publish the result to.
 /// * `job_publish` - The channel to publish new jobs to.
 /// * `arg` - The argument for the worker thread.
-pub fn worker_run_file(path: PathBuf, modified: u64, size: u64, id: usize, job: BuildJob, result_publish: &Sender<JobResult>, job_publish: &Sender<BuildJob>, arg: &mut WorkerArgument) {
+pub fn worker_run_file(file: FileMetadata, id: usize, job: BuildJob, result_publish: &Sender<JobResult>, job_publish: &Sender<BuildJob>, arg: &mut WorkerArgument) {
+    let FileMetadata { path, modified, size, physical_size } = file;
     trace!("[{}] analyzing file {} > {:?}", id, &job.target_path, path);
+    
+    let hash_type = worker_file_hash_type(arg, &path);
 
     match worker_fetch_savedata(arg, &job.target_path) {
         Some(found) => {
-            if found.file_type == HashTreeFileEntryType::File && found.modified == modified && found.size == size {
+            if found.file_type == HashTreeFileEntryType::File && modified_matches(found.modified, modified, arg.mtime_tolerance) && found.size == size && found.hash.hash_type() == hash_type {
                 trace!("File {:?} is already in save file", path);
-                worker_publish_result_or_trigger_parent(id, true, BuildFile::File(BuildFileInformation {
+                // attribute, link, allocation and creation time changes do not touch the modification time,
+                // re-publish the entry if they changed or were not recorded by the previous build
+                let xattr_hash = worker_xattr_hash(arg, &path);
+                let file_id = worker_file_id(arg, &path);
+                let created = worker_created(arg, &path);
+                // an entry of a build without content type detection is completed instead of rehashed
+                let content_type = match (&found.content_type, arg.detect_content_type) {
+                    (None, true) => worker_content_type(arg, &path),
+                    (content_type, _) => content_type.clone(),
+                };
+                let cached = found.xattr_hash == xattr_hash && found.file_id == file_id && found.created == created && found.physical_size == physical_size && found.content_type == content_type;
+                worker_publish_result_or_trigger_parent(id, cached, BuildFile::File(BuildFileInformation {
                     path: job.target_path.clone(),
                     modified,
                     content_hash: found.hash.clone(),
                     content_size: size,
+                    physical_size,
+                    xattr_hash,
+                    file_id,
+                    created,
+                    content_type,
                 }), job, result_publish, job_publish, arg);
                 return;
             }
@@ -39,15 +74,63 @@ pub fn worker_run_file(path: PathBuf, modified: u64, size: u64, id: usize, job:
         None => {}
     }
     
+    if arg.since.is_some_and(|since| modified < since) {
+        trace!("File {:?} was last modified before the given time, not hashing it", path);
+        let file = BuildFile::Other(BuildOtherInformation {
+            path: job.target_path.clone(),
+            content_size: size,
+            modified,
+        });
+        worker_publish_result_or_trigger_parent(id, false, file, job, result_publish, job_publish, arg);
+        return;
+    }
+    
+    if let Some(shared_sizes) = &arg.shared_sizes {
+        if hash_type != GeneralHashType::NULL && !shared_sizes.contains(&size) {
+            trace!("File {:?} has a unique size, not hashing it", path);
+            let file = BuildFile::File(BuildFileInformation {
+                path: job.target_path.clone(),
+                modified,
+                content_hash: placeholder_hash(hash_type, &path, size, modified),
+                content_size: size,
+                physical_size,
+                xattr_hash: worker_xattr_hash(arg, &path),
+                file_id: worker_file_id(arg, &path),
+                created: worker_created(arg, &path),
+                content_type: None,
+            });
+            worker_publish_result_or_trigger_parent(id, false, file, job, result_publish, job_publish, arg);
+            return;
+        }
+    }
+    
+    let open_files = arg.open_files.clone();
+    let _permit = open_files.as_deref().map(Semaphore::acquire);
+    
     match fs::File::open(&path) {
         Ok(file) => {
             let mut reader = std::io::BufReader::new(file);
-            let mut hash = GeneralHash::from_type(arg.hash_type);
+            let mut hash = GeneralHash::from_type(hash_type);
+            
+            // peek into the buffer, the hash still reads the bytes
+            let content_type = match arg.detect_content_type {
+                true => reader.fill_buf().ok()
+                    .and_then(|bytes| detect_content_type(&bytes[..bytes.len().min(DETECTION_BYTES)]))
+                    .map(str::to_string),
+                false => None,
+            };
             let content_size;
 
-            if arg.hash_type == GeneralHashType::NULL {
+            let mapped = match arg.mmap_threshold {
+                Some(threshold) if hash_type != GeneralHashType::NULL && size >= threshold => worker_hash_mapped(&path, reader.get_ref(), size, modified, &mut hash),
+                _ => None,
+            };
+
+            if hash_type == GeneralHashType::NULL {
                 // dont hash file
                 content_size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+            } else if let Some(size) = mapped {
+                content_size = size;
             } else {
                 match hash.hash_file(&mut reader) {
                     Ok(size) => {
@@ -55,25 +138,113 @@ pub fn worker_run_file(path: PathBuf, modified: u64, size: u64, id: usize, job:
                     }
                     Err(err) => {
                         error!("Error while hashing file {:?}: {}", path, err);
-                        worker_publish_result_or_trigger_parent(id, false, worker_create_error(job.target_path.clone(), modified, size), job, result_publish, job_publish, arg);
+                        worker_publish_result_or_trigger_parent(id, false, worker_create_error(arg, job.target_path.clone(), modified, size, format!("Failed to hash the file: {}", err), err.downcast_ref()), job, result_publish, job_publish, arg);
                         return;
                     }
                 }
             }
 
+            // with the name in the identity, files of equal content but different names are no duplicates
+            if !arg.file_identity.is_content() {
+                if let Some(name) = job.target_path.path.last().and_then(|component| component.path.file_name()) {
+                    hash.hash_with_name(name);
+                }
+            }
+
             let file = BuildFile::File(BuildFileInformation {
                 path: job.target_path.clone(),
                 modified,
                 content_hash: hash,
                 content_size,
+                physical_size,
+                xattr_hash: worker_xattr_hash(arg, &path),
+                file_id: worker_file_id(arg, &path),
+                created: worker_created(arg, &path),
+                content_type,
             });
             worker_publish_result_or_trigger_parent(id, false, file, job, result_publish, job_publish, arg);
             return;
         }
         Err(err) => {
             error!("Error while opening file {:?}: {}", path, err);
-            worker_publish_result_or_trigger_parent(id, false, worker_create_error(job.target_path.clone(), modified, size), job, result_publish, job_publish, arg);
+            worker_publish_result_or_trigger_parent(id, false, worker_create_error(arg, job.target_path.clone(), modified, size, format!("Failed to open the file: {}", err), Some(&err)), job, result_publish, job_publish, arg);
             return;
         }
     }
-}
\ No newline at end of file
+}
+
+/// Detect the type of a file from its first bytes, without hashing it.
+/// 
+/// # Arguments
+/// * `arg` - The argument for the worker thread.
+/// * `path` - The path of the file.
+/// 
+/// # Returns
+/// The detected MIME type. None if the type is not recognized or the file can not be read.
+fn worker_content_type(arg: &WorkerArgument, path: &Path) -> Option<String> {
+    let open_files = arg.open_files.clone();
+    let _permit = open_files.as_deref().map(Semaphore::acquire);
+    
+    let mut bytes = Vec::with_capacity(DETECTION_BYTES);
+    match fs::File::open(path).and_then(|file| file.take(DETECTION_BYTES as u64).read_to_end(&mut bytes)) {
+        Ok(_) => detect_content_type(&bytes).map(str::to_string),
+        Err(err) => {
+            warn!("Failed to read {:?} to detect its type: {}", path, err);
+            None
+        }
+    }
+}
+
+/// Files modified less than this many seconds ago are not memory mapped, since they are
+/// likely still being written.
+#[cfg(feature = "mmap")]
+const MMAP_MIN_AGE: u64 = 60;
+
+/// Hash a file by memory mapping it instead of reading it through a buffer.
+/// 
+/// If the file is truncated while it is mapped, reading the mapping raises SIGBUS and
+/// terminates the process. Therefore, files that were modified recently or whose size
+/// changed since their metadata was read are not mapped.
+/// 
+/// # Arguments
+/// * `path` - The path of the file.
+/// * `file` - The opened file.
+/// * `size` - The size of the file (given by fs::metadata).
+/// * `modified` - The last modified time of the file.
+/// * `hash` - The hash to compute, of the hash type to use.
+/// 
+/// # Returns
+/// The size of the hashed content. None if the file was not mapped and must be read instead.
+#[cfg(feature = "mmap")]
+fn worker_hash_mapped(path: &Path, file: &fs::File, size: u64, modified: u64, hash: &mut GeneralHash) -> Option<u64> {
+    if crate::utils::get_time().saturating_sub(modified) < MMAP_MIN_AGE {
+        trace!("File {:?} was modified recently, reading it instead of mapping it", path);
+        return None;
+    }
+    
+    // SAFETY: the mapping is only read. A concurrent truncation raises SIGBUS instead of
+    // undefined behaviour, recently modified files are skipped to make this unlikely.
+    let mapping = match unsafe { memmap2::Mmap::map(file) } {
+        Ok(mapping) => mapping,
+        Err(err) => {
+            log::debug!("Failed to map file {:?}, reading it instead: {}", path, err);
+            return None;
+        }
+    };
+    
+    if mapping.len() as u64 != size {
+        log::debug!("File {:?} changed its size, reading it instead of mapping it", path);
+        return None;
+    }
+    
+    Some(hash.hash_bytes(&mapping))
+}
+
+/// Hash a file by memory mapping it instead of reading it through a buffer.
+/// 
+/// # Returns
+/// None, memory mapping requires the `mmap` feature.
+#[cfg(not(feature = "mmap"))]
+fn worker_hash_mapped(_path: &Path, _file: &fs::File, _size: u64, _modified: u64, _hash: &mut GeneralHash) -> Option<u64> {
+    None
+}
@@ -3,7 +3,7 @@ use std::sync::mpsc::Sender;
 use log::trace;
 use crate::stages::build::intermediary_build_data::{BuildFile, BuildOtherInformation};
 use crate::stages::build::cmd::job::{BuildJob, JobResult};
-use crate::stages::build::cmd::worker::{worker_fetch_savedata, worker_publish_result_or_trigger_parent, WorkerArgument};
+use crate::stages::build::cmd::worker::{worker_fetch_savedata, worker_publish_result_or_trigger_parent, WorkerArgument, modified_matches};
 use crate::stages::build::output::HashTreeFileEntryType;
 
 /// Analyze a file that is not a symlink/folder/file.
@@ -22,7 +22,7 @@ pub fn worker_run_other(path: PathBuf, modified: u64, size: u64, id: usize, job:
 
     match worker_fetch_savedata(arg, &job.target_path) {
         Some(found) => {
-            if found.file_type == HashTreeFileEntryType::Other && found.modified == modified && found.size == size {
+            if found.file_type == HashTreeFileEntryType::Other && modified_matches(found.modified, modified, arg.mtime_tolerance) && found.size == size {
                 trace!("Other {:?} is already in save file", path);
                 worker_publish_result_or_trigger_parent(id, true, BuildFile::Other(BuildOtherInformation {
                     path: job.target_path.clone(),
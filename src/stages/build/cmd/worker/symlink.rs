@@ -6,7 +6,7 @@ use log::{error, trace};
 use crate::stages::build::intermediary_build_data::{BuildFile, BuildSymlinkInformation};
 use crate::hash::GeneralHash;
 use crate::stages::build::cmd::job::JobResult;
-use crate::stages::build::cmd::worker::{worker_create_error, worker_fetch_savedata, worker_publish_result_or_trigger_parent, WorkerArgument};
+use crate::stages::build::cmd::worker::{worker_create_error, worker_fetch_savedata, worker_publish_result_or_trigger_parent, WorkerArgument, modified_matches};
 use crate::stages::build::output::HashTreeFileEntryType;
 
 /// Analyze a symlink.
@@ -25,14 +25,14 @@ pub fn worker_run_symlink(path: PathBuf, modified: u64, size: u64, id: usize, jo
     
     match worker_fetch_savedata(arg, &job.target_path) {
         Some(found) => {
-            if found.file_type == HashTreeFileEntryType::Symlink && found.modified == modified && found.size == size {
+            if found.file_type == HashTreeFileEntryType::Symlink && modified_matches(found.modified, modified, arg.mtime_tolerance) && found.size == size {
                 trace!("Symlink {:?} is already in save file", path);
                 let target_link = fs::read_link(&path);
                 let target_link = match target_link {
                     Ok(target_link) => target_link,
                     Err(err) => {
                         error!("Error while reading symlink {:?}: {}", path, err);
-                        worker_publish_result_or_trigger_parent(id, false, worker_create_error(job.target_path.clone(), modified, size), job, result_publish, job_publish, arg);
+                        worker_publish_result_or_trigger_parent(id, false, worker_create_error(arg, job.target_path.clone(), modified, size, format!("Failed to read the symlink: {}", err), Some(&err)), job, result_publish, job_publish, arg);
                         return;
                     }
                 };
@@ -54,7 +54,7 @@ pub fn worker_run_symlink(path: PathBuf, modified: u64, size: u64, id: usize, jo
         Ok(target_link) => target_link,
         Err(err) => {
             error!("Error while reading symlink {:?}: {}", path, err);
-            worker_publish_result_or_trigger_parent(id, false, worker_create_error(job.target_path.clone(), modified, size), job, result_publish, job_publish, arg);
+            worker_publish_result_or_trigger_parent(id, false, worker_create_error(arg, job.target_path.clone(), modified, size, format!("Failed to read the symlink: {}", err), Some(&err)), job, result_publish, job_publish, arg);
             return;
         }
     };
@@ -65,7 +65,7 @@ pub fn worker_run_symlink(path: PathBuf, modified: u64, size: u64, id: usize, jo
         Ok(_) => {},
         Err(err) => {
             error!("Error while hashing symlink target {:?}: {}", target_link, err);
-            worker_publish_result_or_trigger_parent(id, false, worker_create_error(job.target_path.clone(), modified, size), job, result_publish, job_publish, arg);
+            worker_publish_result_or_trigger_parent(id, false, worker_create_error(arg, job.target_path.clone(), modified, size, format!("Failed to hash the symlink target: {}", err), err.downcast_ref()), job, result_publish, job_publish, arg);
             return;
         }
     }
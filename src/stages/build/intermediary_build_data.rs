@@ -1,5 +1,6 @@
 use std::path::{PathBuf};
 use serde::{Deserialize, Serialize};
+use crate::fileid::HandleIdentifier;
 use crate::hash::GeneralHash;
 use crate::path::FilePath;
 
@@ -10,12 +11,22 @@ use crate::path::FilePath;
 /// * `modified` - The last modification time of the file.
 /// * `content_hash` - The hash of the file content.
 /// * `content_size` - The size of the file content.
+/// * `physical_size` - The size allocated on disk, if captured. Smaller than `content_size` for sparse files.
+/// * `xattr_hash` - The hash of the extended attributes, if captured.
+/// * `file_id` - The inode and device of the file, if captured. Hardlinks share the same id.
+/// * `created` - The creation (birth) time of the file in unix time, if captured and supported.
+/// * `content_type` - The detected MIME type of the file, if detected.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BuildFileInformation {
     pub path: FilePath,
     pub modified: u64,
     pub content_hash: GeneralHash,
     pub content_size: u64,
+    pub physical_size: Option<u64>,
+    pub xattr_hash: Option<GeneralHash>,
+    pub file_id: Option<HandleIdentifier>,
+    pub created: Option<u64>,
+    pub content_type: Option<String>,
 }
 
 /// Information about an analyzed directory.
@@ -25,14 +36,20 @@ pub struct BuildFileInformation {
 /// * `modified` - The last modification time of the directory.
 /// * `content_hash` - The hash of the directory content.
 /// * `number_of_children` - The number of children in the directory.
+/// * `subtree_bytes` - The total size of all files below the directory.
 /// * `children` - The children of the directory.
+/// * `xattr_hash` - The hash of the extended attributes, if captured.
+/// * `created` - The creation (birth) time of the directory in unix time, if captured and supported.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BuildDirectoryInformation {
     pub path: FilePath,
     pub modified: u64,
     pub content_hash: GeneralHash,
     pub number_of_children: u64,
+    pub subtree_bytes: u64,
     pub children: Vec<BuildFile>,
+    pub xattr_hash: Option<GeneralHash>,
+    pub created: Option<u64>,
 }
 
 /// Information about an analyzed symlink.
@@ -71,10 +88,12 @@ pub struct BuildOtherInformation {
 /// # Fields
 /// * `path` - The path of the file.
 /// * `content_hash` - The hash of the file content.
+/// * `subtree_bytes` - The size of the file, see [BuildFile::get_subtree_bytes].
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BuildStubInformation {
     pub path: FilePath,
     pub content_hash: GeneralHash,
+    pub subtree_bytes: u64,
 }
 
 /// A file that has been analyzed.
@@ -111,6 +130,60 @@ impl BuildFile {
         }
     }
     
+    /// Get the number of bytes a file contributes to the size of its parent directory.
+    /// 
+    /// # Returns
+    /// The file size for files, the total size of all files below a directory for directories,
+    /// 0 for symlinks and other files.
+    /// 
+    /// # Example
+    /// ```
+    /// use std::path::PathBuf;
+    /// use backup_deduplicator::hash::GeneralHash;
+    /// use backup_deduplicator::path::FilePath;
+    /// use backup_deduplicator::stages::build::intermediary_build_data::{BuildDirectoryInformation, BuildFile, BuildFileInformation, BuildStubInformation};
+    /// 
+    /// let file = BuildFile::File(BuildFileInformation {
+    ///     path: FilePath::from_realpath(PathBuf::from("dir/file.txt")),
+    ///     modified: 0,
+    ///     content_hash: GeneralHash::NULL,
+    ///     content_size: 100,
+    ///     physical_size: None,
+    ///     xattr_hash: None,
+    ///     file_id: None,
+    ///     created: None,
+    ///     content_type: None,
+    /// });
+    /// let subdir = BuildFile::Stub(BuildStubInformation {
+    ///     path: FilePath::from_realpath(PathBuf::from("dir/subdir")),
+    ///     content_hash: GeneralHash::NULL,
+    ///     subtree_bytes: 50,
+    /// });
+    /// 
+    /// let children = vec![file, subdir];
+    /// let dir = BuildFile::Directory(BuildDirectoryInformation {
+    ///     path: FilePath::from_realpath(PathBuf::from("dir")),
+    ///     modified: 0,
+    ///     content_hash: GeneralHash::NULL,
+    ///     number_of_children: children.len() as u64,
+    ///     subtree_bytes: children.iter().map(|child| child.get_subtree_bytes()).sum(),
+    ///     children,
+    ///     xattr_hash: None,
+    ///     created: None,
+    /// });
+    /// 
+    /// assert_eq!(dir.get_subtree_bytes(), 150);
+    /// ```
+    pub fn get_subtree_bytes(&self) -> u64 {
+        match self {
+            BuildFile::File(info) => info.content_size,
+            BuildFile::Directory(info) => info.subtree_bytes,
+            BuildFile::Symlink(_) => 0,
+            BuildFile::Other(_) => 0,
+            BuildFile::Stub(info) => info.subtree_bytes,
+        }
+    }
+    
     /// Gets the path of this file
     /// 
     /// # Returns
@@ -18,6 +18,12 @@ impl From<BuildFileInformation> for HashTreeFileEntry {
             hash: value.content_hash,
             path: value.path,
             children: Vec::with_capacity(0),
+            subtree_bytes: None,
+            physical_size: value.physical_size,
+            xattr_hash: value.xattr_hash,
+            file_id: value.file_id,
+            created: value.created,
+            content_type: value.content_type,
         }
     }
 }
@@ -38,6 +44,12 @@ impl From<BuildSymlinkInformation> for HashTreeFileEntry {
             hash: value.content_hash,
             path: value.path,
             children: Vec::with_capacity(0),
+            subtree_bytes: None,
+            physical_size: None,
+            xattr_hash: None,
+            file_id: None,
+            created: None,
+            content_type: None,
         }
     }
 }
@@ -58,6 +70,12 @@ impl From<BuildDirectoryInformation> for HashTreeFileEntry {
             hash: value.content_hash,
             path: value.path,
             children: Vec::with_capacity(value.children.len()),
+            subtree_bytes: Some(value.subtree_bytes),
+            physical_size: None,
+            xattr_hash: value.xattr_hash,
+            file_id: None,
+            created: value.created,
+            content_type: None,
         };
         for child in value.children {
             result.children.push(child.get_content_hash().clone());
@@ -82,6 +100,12 @@ impl From<BuildOtherInformation> for HashTreeFileEntry {
             hash: GeneralHash::NULL,
             path: value.path,
             children: Vec::with_capacity(0),
+            subtree_bytes: None,
+            physical_size: None,
+            xattr_hash: None,
+            file_id: None,
+            created: None,
+            content_type: None,
         }
     }
 }
@@ -102,6 +126,12 @@ impl From<BuildStubInformation> for HashTreeFileEntry {
             hash: value.content_hash,
             path: value.path,
             children: Vec::with_capacity(0),
+            subtree_bytes: None,
+            physical_size: None,
+            xattr_hash: None,
+            file_id: None,
+            created: None,
+            content_type: None,
         }
     }
 }
@@ -122,6 +152,12 @@ impl<'a> From<&'a BuildFileInformation> for HashTreeFileEntryRef<'a> {
             path: &value.path,
             size: &value.content_size,
             children: Vec::with_capacity(0),
+            subtree_bytes: None,
+            physical_size: value.physical_size.as_ref(),
+            xattr_hash: value.xattr_hash.as_ref(),
+            file_id: value.file_id.as_ref(),
+            created: value.created.as_ref(),
+            content_type: value.content_type.as_ref(),
         }
     }
 }
@@ -142,6 +178,12 @@ impl<'a> From<&'a BuildSymlinkInformation> for HashTreeFileEntryRef<'a> {
             path: &value.path,
             size: &value.content_size,
             children: Vec::with_capacity(0),
+            subtree_bytes: None,
+            physical_size: None,
+            xattr_hash: None,
+            file_id: None,
+            created: None,
+            content_type: None,
         }
     }
 }
@@ -162,6 +204,12 @@ impl<'a> From<&'a BuildDirectoryInformation> for HashTreeFileEntryRef<'a> {
             path: &value.path,
             size: &value.number_of_children,
             children: Vec::with_capacity(value.children.len()),
+            subtree_bytes: Some(&value.subtree_bytes),
+            physical_size: None,
+            xattr_hash: value.xattr_hash.as_ref(),
+            file_id: None,
+            created: value.created.as_ref(),
+            content_type: None,
         };
         for child in &value.children {
             result.children.push(child.get_content_hash());
@@ -186,6 +234,12 @@ impl<'a> From<&'a BuildOtherInformation> for HashTreeFileEntryRef<'a> {
             path: &value.path,
             size: &value.content_size,
             children: Vec::with_capacity(0),
+            subtree_bytes: None,
+            physical_size: None,
+            xattr_hash: None,
+            file_id: None,
+            created: None,
+            content_type: None,
         }
     }
 }
@@ -206,6 +260,12 @@ impl<'a> From<&'a BuildStubInformation> for HashTreeFileEntryRef<'a> {
             path: &value.path,
             size: &0,
             children: Vec::with_capacity(0),
+            subtree_bytes: None,
+            physical_size: None,
+            xattr_hash: None,
+            file_id: None,
+            created: None,
+            content_type: None,
         }
     }
 }
@@ -264,6 +324,12 @@ impl<'a> From<&'a HashTreeFileEntry> for HashTreeFileEntryRef<'a> {
             path: &value.path,
             size: &value.size,
             children: Vec::with_capacity(0),
+            subtree_bytes: value.subtree_bytes.as_ref(),
+            physical_size: value.physical_size.as_ref(),
+            xattr_hash: value.xattr_hash.as_ref(),
+            file_id: value.file_id.as_ref(),
+            created: value.created.as_ref(),
+            content_type: value.content_type.as_ref(),
         }
     }
 }
@@ -4,7 +4,7 @@ use std::io::{BufRead, Write};
 use std::ops::DerefMut;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use log::{info, trace, warn};
 use serde::{Deserialize, Serialize};
 
@@ -12,8 +12,10 @@ pub use HashTreeFileEntryTypeV1 as HashTreeFileEntryType;
 pub use HashTreeFileEntryV1 as HashTreeFileEntry;
 pub type HashTreeFileEntryRef<'a> = HashTreeFileEntryV1Ref<'a>;
 
-use crate::hash::{GeneralHash, GeneralHashType};
+use crate::fileid::HandleIdentifier;
+use crate::hash::{DirHashMode, FileIdentity, GeneralHash, GeneralHashType};
 use crate::path::FilePath;
+use crate::stages::build::cmd::warning::{send_warning, BuildWarning, WarningSink};
 use crate::utils;
 
 /// HashTreeFile file version. In further versions, the file format may change.
@@ -32,11 +34,36 @@ pub enum HashTreeFileVersion {
 /// * `version` - The version of the file.
 /// * `hash_type` - The hash type used to hash the files.
 /// * `creation_date` - The creation date of the file in unix time
+/// * `mixed_hashes` - Whether entries may use a different hash type than `hash_type`.
+///   Only set if the tree was built with per-extension hash type overrides.
+/// * `dir_hash_mode` - How directory hashes were computed from their children.
+/// * `file_identity` - What file hashes were computed from, i.e. whether files with equal content but
+///   different names have equal hashes.
+/// * `anonymized` - Whether the paths were replaced by opaque tokens (see `clean --anonymize-paths`).
+///   Such a tree can only be analyzed, the paths do not exist on disk.
+/// * `dedup_optimized` - Whether only files with a size shared by another file were hashed (see `build --dedup-optimized`).
+///   The other files have placeholder hashes, the tree can only be used to find duplicates within itself.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct HashTreeFileHeader {
     pub version: HashTreeFileVersion,
     pub hash_type: GeneralHashType,
     pub creation_date: u64,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub mixed_hashes: bool,
+    #[serde(default, skip_serializing_if = "DirHashMode::is_content_only")]
+    pub dir_hash_mode: DirHashMode,
+    #[serde(default, skip_serializing_if = "FileIdentity::is_content")]
+    pub file_identity: FileIdentity,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub anonymized: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub dedup_optimized: bool,
+}
+
+/// Used to omit boolean header flags that are not set, keeping the header
+/// compatible with older versions.
+fn is_false(value: &bool) -> bool {
+    !*value
 }
 
 /// HashTreeFile entry type. Describes the type of file.
@@ -57,6 +84,18 @@ pub enum HashTreeFileEntryTypeV1 {
 /// * `hash` - The hash of the file content.
 /// * `path` - The path of the file.
 /// * `children` - The children of the file. Only for directories.
+/// * `subtree_bytes` - The total size of all files below a directory. Only for directories,
+///   None for entries written by older versions.
+/// * `physical_size` - The size allocated on disk. Only for files and only if captured during the build
+///   (`--capture-physical-size`, Unix only). Smaller than `size` for sparse files.
+/// * `xattr_hash` - The hash of the extended attributes. Only for files and directories and only if captured
+///   during the build (`--capture-xattrs`, Linux only).
+/// * `file_id` - The inode and device of the file. Only for files and only if captured during the build
+///   (`--capture-file-ids`). Hardlinks of the same file share the same id.
+/// * `created` - The creation (birth) time in unix time. Only for files and directories, only if captured during
+///   the build (`--capture-btime`) and only if the platform and file system record it.
+/// * `content_type` - The MIME type detected from the leading bytes. Only for files, only if detected during
+///   the build (`--detect-content-type`) and only for known formats.
 ///
 /// # See also
 /// * [HashTreeFileEntryV1Ref] which is a reference version of this struct.
@@ -68,6 +107,128 @@ pub struct HashTreeFileEntryV1 {
     pub hash: GeneralHash,
     pub path: FilePath,
     pub children: Vec<GeneralHash>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtree_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub physical_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xattr_hash: Option<GeneralHash>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<HandleIdentifier>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+impl HashTreeFileEntryV1 {
+    /// Check the structural invariants of an entry, e.g. of an externally produced or
+    /// possibly corrupt hash tree file.
+    ///
+    /// * The path is not empty.
+    /// * Only directories have children and subtree bytes; the size of a directory is its number of children.
+    /// * Only files have a physical size, a file id and a content type.
+    /// * The hash (and the hashes of the children) use the hash type of the header, unless the header
+    ///   allows mixed hash types. Entries of type `Other` may use the NULL hash.
+    ///
+    /// # Arguments
+    /// * `header` - The header of the hash tree file the entry belongs to.
+    ///
+    /// # Returns
+    /// Nothing if the entry is valid.
+    ///
+    /// # Errors
+    /// A description of the first violated invariant.
+    ///
+    /// # Example
+    /// ```
+    /// use std::path::PathBuf;
+    /// use backup_deduplicator::hash::{GeneralHash, GeneralHashType};
+    /// use backup_deduplicator::path::FilePath;
+    /// use backup_deduplicator::stages::build::output::{HashTreeFileEntry, HashTreeFileEntryType, HashTreeFileHeader};
+    ///
+    /// let header: HashTreeFileHeader = serde_json::from_str(r#"{"version":"V1","hash_type":"SHA256","creation_date":0}"#).unwrap();
+    /// let file = HashTreeFileEntry {
+    ///     file_type: HashTreeFileEntryType::File,
+    ///     modified: 0,
+    ///     size: 3,
+    ///     hash: GeneralHash::from_type(GeneralHashType::SHA256),
+    ///     path: FilePath::from_realpath(PathBuf::from("dir/file")),
+    ///     children: Vec::new(),
+    ///     subtree_bytes: None,
+    ///     physical_size: Some(4096),
+    ///     xattr_hash: None,
+    ///     file_id: None,
+    ///     created: None,
+    ///     content_type: None,
+    /// };
+    /// let directory = HashTreeFileEntry {
+    ///     file_type: HashTreeFileEntryType::Directory,
+    ///     size: 1,
+    ///     path: FilePath::from_realpath(PathBuf::from("dir")),
+    ///     children: vec![file.hash.clone()],
+    ///     subtree_bytes: Some(3),
+    ///     physical_size: None,
+    ///     ..file.clone()
+    /// };
+    /// assert!(file.validate(&header).is_ok());
+    /// assert!(directory.validate(&header).is_ok());
+    ///
+    /// // empty path
+    /// assert!(HashTreeFileEntry { path: FilePath::from_pathcomponents(Vec::new()), ..file.clone() }.validate(&header).is_err());
+    /// // a file with children or subtree bytes
+    /// assert!(HashTreeFileEntry { children: vec![file.hash.clone()], ..file.clone() }.validate(&header).is_err());
+    /// assert!(HashTreeFileEntry { subtree_bytes: Some(3), ..file.clone() }.validate(&header).is_err());
+    /// // a directory whose size is not its number of children
+    /// assert!(HashTreeFileEntry { size: 2, ..directory.clone() }.validate(&header).is_err());
+    /// // a directory with a physical size
+    /// assert!(HashTreeFileEntry { physical_size: Some(4096), ..directory.clone() }.validate(&header).is_err());
+    /// // a hash type different from the header
+    /// assert!(HashTreeFileEntry { hash: GeneralHash::from_type(GeneralHashType::NULL), ..file.clone() }.validate(&header).is_err());
+    /// assert!(HashTreeFileEntry { children: vec![GeneralHash::from_type(GeneralHashType::XXH64)], ..directory.clone() }.validate(&header).is_err());
+    /// // entries that could not be read use the NULL hash
+    /// let other = HashTreeFileEntry { file_type: HashTreeFileEntryType::Other, hash: GeneralHash::from_type(GeneralHashType::NULL), physical_size: None, ..file.clone() };
+    /// assert!(other.validate(&header).is_ok());
+    /// ```
+    pub fn validate(&self, header: &HashTreeFileHeader) -> std::result::Result<(), String> {
+        if self.path.path.is_empty() {
+            return Err("the path is empty".to_string());
+        }
+
+        let is_directory = self.file_type == HashTreeFileEntryType::Directory;
+
+        if !is_directory && !self.children.is_empty() {
+            return Err(format!("a {:?} entry has {} children", self.file_type, self.children.len()));
+        }
+        if !is_directory && self.subtree_bytes.is_some() {
+            return Err(format!("a {:?} entry has subtree bytes", self.file_type));
+        }
+        if is_directory && self.size != self.children.len() as u64 {
+            return Err(format!("the directory size {} differs from its number of children {}", self.size, self.children.len()));
+        }
+        if self.file_type != HashTreeFileEntryType::File && self.physical_size.is_some() {
+            return Err(format!("a {:?} entry has a physical size", self.file_type));
+        }
+        if self.file_type != HashTreeFileEntryType::File && self.file_id.is_some() {
+            return Err(format!("a {:?} entry has a file id", self.file_type));
+        }
+        if self.file_type != HashTreeFileEntryType::File && self.content_type.is_some() {
+            return Err(format!("a {:?} entry has a content type", self.file_type));
+        }
+
+        if !header.mixed_hashes {
+            let null_allowed = self.file_type == HashTreeFileEntryType::Other && self.hash.hash_type() == GeneralHashType::NULL;
+            if self.hash.hash_type() != header.hash_type && !null_allowed {
+                return Err(format!("the hash type {} differs from the hash type {} of the header", self.hash.hash_type(), header.hash_type));
+            }
+            // children that could not be read have the NULL hash
+            if let Some(child) = self.children.iter().find(|child| child.hash_type() != header.hash_type && child.hash_type() != GeneralHashType::NULL) {
+                return Err(format!("the hash type {} of a child differs from the hash type {} of the header", child.hash_type(), header.hash_type));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// HashTreeFile entry reference. Describes an analyzed file.
@@ -80,6 +241,12 @@ pub struct HashTreeFileEntryV1 {
 /// * `hash` - The hash of the file content.
 /// * `path` - The path of the file.
 /// * `children` - The children of the file. Only for directories.
+/// * `subtree_bytes` - The total size of all files below a directory. Only for directories.
+/// * `physical_size` - The size allocated on disk. Only for files and only if captured.
+/// * `xattr_hash` - The hash of the extended attributes. Only if captured.
+/// * `file_id` - The inode and device of the file. Only for files and only if captured.
+/// * `created` - The creation (birth) time in unix time. Only if captured and supported.
+/// * `content_type` - The detected MIME type. Only for files and only if detected.
 /// 
 /// # See also
 /// * [HashTreeFileEntryV1] which is the owned version of this struct.
@@ -91,6 +258,18 @@ pub struct HashTreeFileEntryV1Ref<'a> {
     pub hash: &'a GeneralHash,
     pub path: &'a FilePath,
     pub children: Vec<&'a GeneralHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtree_bytes: Option<&'a u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub physical_size: Option<&'a u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xattr_hash: Option<&'a GeneralHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<&'a HandleIdentifier>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<&'a u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<&'a String>,
 }
 
 /// Interface to access and manage a hash tree file.
@@ -100,6 +279,10 @@ pub struct HashTreeFileEntryV1Ref<'a> {
 /// * `file_by_hash` - A map of files by their hash.
 /// * `file_by_path` - A map of files by their path.
 /// * `all_entries` - A list of all entries.
+///
+/// If the file by path - hash map is enabled, only the last entry per path is kept.
+/// Replaced entries are counted, see [HashTreeFile::get_duplicate_entries].
+/// In strict mode every loaded entry is validated, see [HashTreeFile::set_strict].
 pub struct HashTreeFile<'a, W, R> where W: Write, R: BufRead {
     pub header: HashTreeFileHeader,
     pub file_by_hash: HashMap<GeneralHash, Vec<Arc<HashTreeFileEntry>>>,
@@ -113,6 +296,81 @@ pub struct HashTreeFile<'a, W, R> where W: Write, R: BufRead {
     writer: RefCell<&'a mut W>,
     written_bytes: RefCell<usize>,
     reader: RefCell<&'a mut R>,
+    read_bytes: usize,
+    last_entry_offset: usize,
+    incomplete_tail: bool,
+    strict: bool,
+    warnings: WarningSink,
+    
+    duplicate_entries: usize,
+    identical_duplicate_entries: usize,
+}
+
+impl HashTreeFile<'_, std::io::Sink, std::io::Empty> {
+    /// Read only the header of a hash tree file, i.e. its first line. The reader is left at the first entry,
+    /// so the entries can be loaded from it afterward. Cheaper than setting up a [HashTreeFile] to inspect
+    /// the hash type or creation date.
+    ///
+    /// # Arguments
+    /// * `reader` - The reader positioned at the start of the hash tree file.
+    ///
+    /// # Returns
+    /// The parsed header.
+    ///
+    /// # Errors
+    /// * If reading fails.
+    /// * If the file is empty or the first line is no valid header.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io::Cursor;
+    /// use backup_deduplicator::hash::GeneralHashType;
+    /// use backup_deduplicator::stages::build::output::HashTreeFile;
+    /// use backup_deduplicator::utils::NullWriter;
+    ///
+    /// let header_line = r#"{"version":"V1","hash_type":"SHA256","creation_date":1714566600}"#;
+    /// let content = format!("{}\n{}\n", header_line,
+    ///     r#"{"file_type":"File","modified":1714566600,"size":2,"hash":"SHA256:0263829989b6fd954f72baaf2fc64bc2e2f01d692d4de72986ea808f6e99813f","path":{"path":[{"path":"data/a.txt","target":"File"}]},"children":[]}"#,
+    /// );
+    /// let mut reader = Cursor::new(content);
+    ///
+    /// let (header, length) = HashTreeFile::read_header_with_length(&mut reader).unwrap();
+    /// assert_eq!(header.hash_type, GeneralHashType::SHA256);
+    /// assert_eq!(header.creation_date, 1714566600);
+    ///
+    /// let mut writer = NullWriter::new();
+    /// let mut file = HashTreeFile::new(&mut writer, &mut reader, header.hash_type, false, false, false);
+    /// file.set_loaded_header(header, length);
+    /// let entry = file.load_entry_no_filter().unwrap().unwrap();
+    /// assert_eq!(entry.path.to_string(), "data/a.txt");
+    /// assert_eq!(file.get_last_entry_offset(), header_line.len() + 1);
+    ///
+    /// assert!(HashTreeFile::read_header(&mut Cursor::new("")).is_err());
+    /// ```
+    pub fn read_header<R: BufRead>(reader: &mut R) -> Result<HashTreeFileHeader> {
+        HashTreeFile::read_header_with_length(reader).map(|(header, _)| header)
+    }
+    
+    /// Read only the header of a hash tree file, see [HashTreeFile::read_header].
+    ///
+    /// # Arguments
+    /// * `reader` - The reader positioned at the start of the hash tree file.
+    ///
+    /// # Returns
+    /// The parsed header and the length of its line in bytes, to pass to [HashTreeFile::set_loaded_header].
+    ///
+    /// # Errors
+    /// * If reading fails.
+    /// * If the file is empty or the first line is no valid header.
+    pub fn read_header_with_length<R: BufRead>(reader: &mut R) -> Result<(HashTreeFileHeader, usize)> {
+        let mut header_str = String::new();
+        let length = reader.read_line(&mut header_str)?;
+        if length == 0 {
+            return Err(anyhow!("The hash tree file is empty, it has no header"));
+        }
+
+        Ok((serde_json::from_str(header_str.as_str())?, length))
+    }
 }
 
 impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
@@ -137,6 +395,11 @@ impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
                 version: HashTreeFileVersion::V1,
                 hash_type,
                 creation_date: time,
+                mixed_hashes: false,
+                dir_hash_mode: DirHashMode::ContentOnly,
+                file_identity: FileIdentity::Content,
+                anonymized: false,
+                dedup_optimized: false,
             },
             file_by_hash: HashMap::new(),
             file_by_path: HashMap::new(),
@@ -147,6 +410,13 @@ impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
             writer: RefCell::new(writer),
             reader: RefCell::new(reader),
             written_bytes: RefCell::new(0),
+            read_bytes: 0,
+            last_entry_offset: 0,
+            incomplete_tail: false,
+            strict: false,
+            warnings: None,
+            duplicate_entries: 0,
+            identical_duplicate_entries: 0,
         }
     }
     
@@ -162,19 +432,28 @@ impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
         Ok(())
     }
     
-    /// Load a file header from the file
+    /// Load a file header from the file, see [HashTreeFile::read_header].
     /// 
     /// # Error
-    /// If reading from the file errors
+    /// * If reading from the file errors
+    /// * If the file is empty or the first line is no valid header
     pub fn load_header(&mut self) -> Result<()> {
-        let mut header_str = String::new();
-        self.reader.borrow_mut().deref_mut().read_line(&mut header_str)?;
-        
-        let header: HashTreeFileHeader = serde_json::from_str(header_str.as_str())?;
-        self.header = header;
+        let (header, length) = HashTreeFile::read_header_with_length(self.reader.borrow_mut().deref_mut())?;
+        self.set_loaded_header(header, length);
         
         Ok(())
     }
+    
+    /// Use a header that was read from the reader before, e.g. by [HashTreeFile::read_header_with_length],
+    /// instead of loading it. The byte counts of the loaded entries start after the header.
+    /// 
+    /// # Arguments
+    /// * `header` - The header of the file.
+    /// * `length` - The length of the header line in bytes.
+    pub fn set_loaded_header(&mut self, header: HashTreeFileHeader, length: usize) {
+        self.header = header;
+        self.read_bytes += length;
+    }
 
     /// Load a file entry from the file
     /// 
@@ -186,6 +465,11 @@ impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
     
     /// Load a file entry from the file
     /// 
+    /// Empty lines and comment lines starting with `#` (e.g. checkpoint markers) are skipped.
+    /// A last line without a trailing newline is treated as an entry or marker that was
+    /// only partially written before the writing process was interrupted: it is ignored
+    /// and the end of the file is reported, see [HashTreeFile::has_incomplete_tail].
+    /// 
     /// # Arguments
     /// * `filter` - A filter function to filter the entries. If the function returns false the entry is ignored.
     /// 
@@ -193,7 +477,8 @@ impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
     /// The loaded entry or None if the end of the file is reached.
     /// 
     /// # Error
-    /// If reading from the file errors
+    /// * If reading from the file errors
+    /// * If an entry is invalid in strict mode
     pub fn load_entry<F: Fn(&HashTreeFileEntry) -> bool>(&mut self, filter: F) -> Result<Option<Arc<HashTreeFileEntry>>> {
         loop {
             let mut entry_str = String::new();
@@ -203,14 +488,31 @@ impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
                 return Ok(None);
             }
             
-            if count == 1 {
+            // also a torn comment line, anything appended to it would become part of the comment
+            if !entry_str.ends_with('\n') {
+                warn!("Ignoring incomplete last line of {} bytes", count);
+                self.incomplete_tail = true;
+                return Ok(None);
+            }
+            
+            if count == 1 || entry_str.starts_with('#') {
+                self.read_bytes += count;
                 continue;
             }
 
             let entry: HashTreeFileEntry = serde_json::from_str(entry_str.as_str())?;
+            self.last_entry_offset = self.read_bytes;
+            self.read_bytes += count;
+            
+            if self.strict {
+                if let Err(err) = entry.validate(&self.header) {
+                    return Err(anyhow!("Invalid entry {} at byte {}: {}", entry.path, self.last_entry_offset, err));
+                }
+            }
 
-            if entry.hash.hash_type() != self.header.hash_type && !(entry.file_type == HashTreeFileEntryType::Other && entry.hash.hash_type() == GeneralHashType::NULL) {
+            if !self.header.mixed_hashes && entry.hash.hash_type() != self.header.hash_type && !(entry.file_type == HashTreeFileEntryType::Other && entry.hash.hash_type() == GeneralHashType::NULL) {
                 warn!("Hash type mismatch ignoring entry: {:?}", entry.path);
+                send_warning(&self.warnings, BuildWarning::HashTypeMismatch { path: entry.path.clone() });
                 continue;
             }
             
@@ -232,6 +534,11 @@ impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
                         // this happens if analysis was canceled and continued
                         // and an already analysed file changed
                         info!("Duplicate entry for path: {:?}", &old.path);
+                        send_warning(&self.warnings, BuildWarning::DuplicateEntry { path: old.path.clone() });
+                        self.duplicate_entries += 1;
+                        if old == shared_entry {
+                            self.identical_duplicate_entries += 1;
+                        }
                         if self.enable_all_entry_list {
                             self.all_entries.retain(|x| x != &old);
                         }
@@ -283,6 +590,19 @@ impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
         Ok(())
     }
 
+    /// Write a checkpoint marker comment line to the file and flush the writer.
+    /// The marker is skipped when loading entries. Call `sync_data` on the underlying
+    /// file afterward to make everything written so far durable.
+    /// 
+    /// # Error
+    /// If writing to the file errors
+    pub fn write_checkpoint(&self) -> Result<()> {
+        let string = format!("# checkpoint {}\n", utils::get_time());
+        *self.written_bytes.borrow_mut() += self.writer.borrow_mut().deref_mut().write(string.as_bytes())?;
+        self.writer.borrow_mut().deref_mut().flush()?;
+        Ok(())
+    }
+
     /// Write an entry reference to the file
     /// 
     /// # Arguments
@@ -327,6 +647,110 @@ impl<'a, W: Write, R: BufRead> HashTreeFile<'a, W, R> {
         *self.written_bytes.borrow()
     }
     
+    /// Get the number of bytes read up to the end of the last complete line.
+    /// 
+    /// # Returns
+    /// The read bytes count.
+    pub fn get_read_bytes(&self) -> usize {
+        self.read_bytes
+    }
+    
+    /// Get the offset of the line of the last parsed entry in the file.
+    /// 
+    /// # Returns
+    /// The offset in bytes from the start of the file.
+    pub fn get_last_entry_offset(&self) -> usize {
+        self.last_entry_offset
+    }
+    
+    /// Whether loading stopped at an incomplete last entry, see [HashTreeFile::load_entry].
+    /// The file should be truncated to [HashTreeFile::get_read_bytes] before appending to it.
+    /// 
+    /// # Returns
+    /// True if an incomplete last entry was ignored.
+    pub fn has_incomplete_tail(&self) -> bool {
+        self.incomplete_tail
+    }
+    
+    /// Enable or disable strict mode. In strict mode [HashTreeFile::load_entry] validates every
+    /// entry (see [HashTreeFileEntry::validate]) and fails on the first malformed entry,
+    /// instead of loading it or skipping entries of another hash type.
+    /// 
+    /// # Arguments
+    /// * `strict` - Whether to validate the loaded entries.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    
+    /// Set the sink that receives the warnings of [HashTreeFile::load_entry] in addition to logging them,
+    /// i.e. duplicate entries and entries of another hash type.
+    /// 
+    /// # Arguments
+    /// * `warnings` - The sink for the warnings, None to only log them.
+    pub fn set_warnings(&mut self, warnings: WarningSink) {
+        self.warnings = warnings;
+    }
+    
+    /// Get the number of loaded entries that replaced an older entry with the same path.
+    /// Only counted if the file by path - hash map is enabled.
+    /// 
+    /// # Returns
+    /// The number of duplicate entries and how many of them were byte-identical to the replaced entry.
+    pub fn get_duplicate_entries(&self) -> (usize, usize) {
+        (self.duplicate_entries, self.identical_duplicate_entries)
+    }
+
+    /// Consume the hash tree file and return the loaded entries by their path, e.g. for
+    /// applications embedding this library. Requires the file by path - hash map to be enabled
+    /// and the entries to be loaded, see [HashTreeFile::load_all_entries].
+    ///
+    /// The entries are moved out of the map without copying them, unless they are still shared
+    /// with the file by hash - hash map or the entry list: enable only the file by path - hash map
+    /// to avoid holding every entry twice. Expect the map to need about as much memory as the
+    /// hash tree file is large, plus the map overhead per entry.
+    ///
+    /// # Returns
+    /// The last loaded entry of every path.
+    ///
+    /// # Example
+    /// ```
+    /// use std::io::BufReader;
+    /// use backup_deduplicator::hash::GeneralHashType;
+    /// use backup_deduplicator::stages::build::output::HashTreeFile;
+    /// use backup_deduplicator::utils::NullWriter;
+    ///
+    /// let content = r#"{"version":"V1","hash_type":"NULL","creation_date":0}
+    /// {"file_type":"File","modified":1,"size":3,"hash":"NULL","path":{"path":[{"path":"a","target":"File"}]},"children":[]}
+    /// {"file_type":"File","modified":2,"size":5,"hash":"NULL","path":{"path":[{"path":"a","target":"File"}]},"children":[]}
+    /// {"file_type":"File","modified":1,"size":7,"hash":"NULL","path":{"path":[{"path":"b","target":"File"}]},"children":[]}
+    /// "#;
+    ///
+    /// let mut reader = BufReader::new(content.as_bytes());
+    /// let mut writer = NullWriter::new();
+    /// let mut file = HashTreeFile::new(&mut writer, &mut reader, GeneralHashType::NULL, false, true, false);
+    /// file.load_header().unwrap();
+    /// file.load_all_entries_no_filter().unwrap();
+    ///
+    /// let entries = file.into_path_map();
+    /// assert_eq!(entries.len(), 2);
+    /// assert!(entries.values().any(|entry| entry.size == 5 && entry.modified == 2));
+    /// assert!(entries.values().any(|entry| entry.size == 7));
+    /// ```
+    pub fn into_path_map(self) -> HashMap<FilePath, HashTreeFileEntry> {
+        if !self.enable_file_by_path {
+            warn!("The file by path - hash map is disabled, no entries are returned");
+        }
+
+        // release the other references to the entries first, so that they can be moved out
+        let HashTreeFile { file_by_path, file_by_hash, all_entries, .. } = self;
+        drop(file_by_hash);
+        drop(all_entries);
+
+        file_by_path.into_iter()
+            .map(|(path, entry)| (path, Arc::try_unwrap(entry).unwrap_or_else(|entry| entry.as_ref().clone())))
+            .collect()
+    }
+
     /// Flush the writer.
     /// 
     /// # Error
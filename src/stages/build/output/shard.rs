@@ -0,0 +1,163 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+use crate::hash::{GeneralHash, GeneralHashType};
+use crate::stages::build::output::HashTreeFile;
+use crate::utils::NullWriter;
+
+/// Get the path of a shard of a hash tree file. The shard number is inserted before the extension.
+///
+/// # Arguments
+/// * `output` - The path of the unsplit hash tree file.
+/// * `shard` - The number of the shard.
+///
+/// # Returns
+/// The path of the shard file.
+///
+/// # Example
+/// ```
+/// use std::path::{Path, PathBuf};
+/// use backup_deduplicator::stages::build::output::shard::shard_path;
+///
+/// assert_eq!(shard_path(Path::new("/backup/hash_tree.bdd"), 3), PathBuf::from("/backup/hash_tree.3.bdd"));
+/// assert_eq!(shard_path(Path::new("hash_tree"), 0), PathBuf::from("hash_tree.0"));
+/// ```
+pub fn shard_path(output: &Path, shard: u32) -> PathBuf {
+    let mut name = OsString::from(output.file_stem().unwrap_or_default());
+    name.push(format!(".{}", shard));
+    if let Some(extension) = output.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    output.with_file_name(name)
+}
+
+/// Get the shard of an entry by the prefix of its hash. All entries with the same hash are in the same shard,
+/// therefore every shard contains all duplicates of its files.
+///
+/// # Arguments
+/// * `hash` - The hash of the entry.
+/// * `shards` - The number of shards.
+///
+/// # Returns
+/// The shard number, the first two bytes of the hash modulo the number of shards.
+///
+/// # Example
+/// ```
+/// use std::str::FromStr;
+/// use backup_deduplicator::hash::GeneralHash;
+/// use backup_deduplicator::stages::build::output::shard::shard_of;
+///
+/// let hash = GeneralHash::from_str("SHA256:0102030405060708091011121314151617181920212223242526272829303132").unwrap();
+/// assert_eq!(shard_of(&hash, 16), 0x0102 % 16);
+/// assert_eq!(shard_of(&GeneralHash::NULL, 16), 0);
+/// ```
+pub fn shard_of(hash: &GeneralHash, shards: u32) -> u32 {
+    let prefix = hash.as_bytes().iter().take(2).fold(0u32, |prefix, byte| (prefix << 8) | *byte as u32);
+    prefix % shards.max(1)
+}
+
+/// Split a hash tree file into shard files by the hash of the entries, see [shard_of] and [shard_path].
+/// Every shard starts with the header of the hash tree file and is a valid hash tree file itself.
+/// Existing shard files are overwritten, the hash tree file is kept. Shard files numbered `shards` and
+/// above, left by an earlier split into more shards, are removed, so that they are not mistaken for
+/// shards of this split.
+///
+/// # Arguments
+/// * `input` - The hash tree file to split.
+/// * `shards` - The number of shards.
+///
+/// # Returns
+/// The paths of the written shard files.
+///
+/// # Errors
+/// * If the hash tree file can not be read or is malformed.
+/// * If a shard file can not be written or a stale shard file can not be removed.
+pub fn write_shards(input: &Path, shards: u32) -> Result<Vec<PathBuf>> {
+    if shards == 0 {
+        return Err(anyhow!("The number of shards must be at least 1"));
+    }
+
+    let input_file = fs::File::open(input).map_err(|err| anyhow!("Failed to open the hash tree file {:?}: {}", input, err))?;
+    let mut input_buf_reader = BufReader::new(&input_file);
+    let mut null_out_writer = NullWriter::new();
+
+    let mut save_file = HashTreeFile::new(&mut null_out_writer, &mut input_buf_reader, GeneralHashType::NULL, false, false, false);
+    save_file.load_header()?;
+    let header = serde_json::to_string(&save_file.header)?;
+
+    let paths = (0..shards).map(|shard| shard_path(input, shard)).collect::<Vec<PathBuf>>();
+    let mut writers = Vec::with_capacity(paths.len());
+    for path in &paths {
+        let file = fs::File::create(path).map_err(|err| anyhow!("Failed to create the shard file {:?}: {}", path, err))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "{}", header)?;
+        writers.push(writer);
+    }
+
+    while let Some(entry) = save_file.load_entry_no_filter()? {
+        let writer = &mut writers[shard_of(&entry.hash, shards) as usize];
+        writeln!(writer, "{}", serde_json::to_string(entry.as_ref())?)?;
+    }
+
+    for writer in writers.iter_mut() {
+        writer.flush()?;
+    }
+
+    let mut stale = shards;
+    loop {
+        let path = shard_path(input, stale);
+        match fs::remove_file(&path) {
+            Ok(()) => stale += 1,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => break,
+            Err(err) => return Err(anyhow!("Failed to remove the stale shard file {:?}: {}", path, err)),
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Open the shard files of a hash tree file as one hash tree, see [write_shards].
+/// The header of the first shard is read, the headers of the other shards are skipped.
+///
+/// # Arguments
+/// * `input` - The path of the unsplit hash tree file, the shard paths are derived from it (see [shard_path]).
+///   The file itself does not need to exist.
+/// * `shards` - The number of shards.
+///
+/// # Returns
+/// A reader of the header and the entries of all shards.
+///
+/// # Errors
+/// * If a shard file does not exist or can not be read.
+/// * If the shards have different headers, i.e. belong to different hash trees.
+pub fn open_shards(input: &Path, shards: u32) -> Result<BufReader<Box<dyn Read>>> {
+    let mut header: Option<String> = None;
+    let mut reader: Box<dyn Read> = Box::new(std::io::empty());
+
+    for shard in 0..shards {
+        let path = shard_path(input, shard);
+        let file = fs::File::open(&path).map_err(|err| anyhow!("Failed to open the shard file {:?}: {}", path, err))?;
+        let mut shard_reader = BufReader::new(file);
+
+        let mut shard_header = String::new();
+        shard_reader.read_line(&mut shard_header)?;
+
+        match &header {
+            None => {
+                reader = Box::new(Cursor::new(shard_header.clone()));
+                header = Some(shard_header);
+            },
+            Some(header) if header != &shard_header => {
+                return Err(anyhow!("The shard file {:?} belongs to another hash tree than the first shard", path));
+            },
+            Some(_) => {},
+        }
+
+        reader = Box::new(reader.chain(shard_reader));
+    }
+
+    Ok(BufReader::new(reader))
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use crate::stages::build::output::HashTreeFileEntry;
+
+/// A destination for the entries of a build, e.g. a database or a message queue. A build always writes its
+/// result file, since continuing and verifying a build read it back, and passes every entry it writes to the
+/// further sinks of its settings as well.
+///
+/// The sinks are called on the thread running the build, never on a worker thread, in the order the entries are
+/// written to the result file (see [OutputOrder](crate::stages::build::cmd::output_order::OutputOrder)).
+/// Entries reused unchanged from a continued result file are not written again and therefore not passed to the sinks.
+/// The sinks are part of the build settings, which can be moved to the thread running the build, so they must be [Send].
+///
+/// # Example
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use anyhow::Result;
+/// use backup_deduplicator::stages::build::output::HashTreeFileEntry;
+/// use backup_deduplicator::stages::build::output::sink::EntrySink;
+///
+/// /// Collects the paths of the entries, e.g. to insert them into a database when the build finished.
+/// struct PathCollector {
+///     paths: Arc<Mutex<Vec<String>>>,
+///     finished: bool,
+/// }
+///
+/// impl EntrySink for PathCollector {
+///     fn write(&mut self, entry: &HashTreeFileEntry) -> Result<()> {
+///         self.paths.lock().unwrap().push(entry.path.to_string());
+///         Ok(())
+///     }
+///
+///     fn finish(&mut self) -> Result<()> {
+///         self.finished = true;
+///         Ok(())
+///     }
+/// }
+///
+/// let paths = Arc::new(Mutex::new(Vec::new()));
+/// let sink: Box<dyn EntrySink> = Box::new(PathCollector { paths: Arc::clone(&paths), finished: false });
+/// // passed to the build as `BuildSettings { sinks: vec![sink], .. }`
+/// # drop(sink);
+/// ```
+pub trait EntrySink: Send {
+    /// Write an entry.
+    ///
+    /// # Arguments
+    /// * `entry` - The entry to write.
+    ///
+    /// # Errors
+    /// If the entry can not be written. The build stops with this error.
+    fn write(&mut self, entry: &HashTreeFileEntry) -> Result<()>;
+
+    /// Called once after the last entry of a build, also if the build was cancelled. Takes `&mut self`
+    /// instead of `self`, so that the sinks can be passed to the build as trait objects.
+    ///
+    /// # Errors
+    /// If the written entries can not be completed, e.g. flushed. The build fails with this error.
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Pass an entry to every sink.
+///
+/// # Arguments
+/// * `sinks` - The sinks to write to.
+/// * `entry` - The entry to write.
+///
+/// # Errors
+/// If a sink fails to write the entry.
+pub fn write_to_sinks(sinks: &mut [Box<dyn EntrySink>], entry: &HashTreeFileEntry) -> Result<()> {
+    for sink in sinks.iter_mut() {
+        sink.write(entry)?;
+    }
+
+    Ok(())
+}
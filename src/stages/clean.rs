@@ -1 +1,4 @@
+pub mod anonymize;
 pub mod cmd;
+pub mod progress;
+pub mod rewrite;
@@ -0,0 +1,128 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use anyhow::{anyhow, Result};
+use log::info;
+use crate::hash::GeneralHashType;
+use crate::path::{FilePath, PathComponent};
+use crate::utils::decode_hex;
+
+/// Number of bytes of the salted hash kept for a path token.
+const TOKEN_BYTES: usize = 16;
+
+/// Number of random bytes of a generated salt.
+const SALT_BYTES: usize = 32;
+
+/// Load the salt from a salt file, or generate a new salt and write it to the file if it does not exist.
+/// The salt is stored hex encoded.
+///
+/// # Arguments
+/// * `salt_file` - The file to read the salt from or to write a new salt to.
+///
+/// # Returns
+/// The salt.
+///
+/// # Errors
+/// * If the salt file cannot be read or written.
+/// * If the salt file does not contain a hex string.
+/// * If the operating system's random source is not available.
+pub fn load_or_create_salt(salt_file: &Path) -> Result<Vec<u8>> {
+    if salt_file.exists() {
+        let content = fs::read_to_string(salt_file)
+            .map_err(|err| anyhow!("Failed to read salt file {:?}: {}", salt_file, err))?;
+        let salt = decode_hex(content.trim())
+            .map_err(|err| anyhow!("Salt file {:?} does not contain a hex string: {}", salt_file, err))?;
+        if salt.is_empty() {
+            return Err(anyhow!("Salt file {:?} is empty", salt_file));
+        }
+        return Ok(salt);
+    }
+
+    let mut salt = vec![0; SALT_BYTES];
+    getrandom::getrandom(&mut salt)
+        .map_err(|err| anyhow!("Failed to generate a salt: {}", err))?;
+
+    fs::write(salt_file, encode_hex(&salt))
+        .map_err(|err| anyhow!("Failed to write salt file {:?}: {}", salt_file, err))?;
+    info!("Generated a new salt and saved it to {:?}", salt_file);
+
+    Ok(salt)
+}
+
+/// Check that a hash type is suitable to derive path tokens from.
+///
+/// # Arguments
+/// * `hash_type` - The hash type of the hash tree.
+///
+/// # Errors
+/// If the hash type is not a cryptographic hash. Tokens of short or
+/// non-cryptographic hashes could be reversed or collide.
+pub fn check_hash_type(hash_type: GeneralHashType) -> Result<()> {
+    match hash_type {
+        #[cfg(feature = "hash-sha2")]
+        GeneralHashType::SHA512 | GeneralHashType::SHA256 => Ok(()),
+        #[cfg(feature = "hash-sha1")]
+        GeneralHashType::SHA1 => Ok(()),
+        _ => Err(anyhow!("Anonymizing paths requires a hash tree built with a SHA hash, but it uses {}", hash_type)),
+    }
+}
+
+/// Replace every name of a file path with an opaque token.
+///
+/// The token of a name is derived from the salt and all names up to and including it.
+/// Equal paths therefore get equal tokens and a child keeps the tokens of its parent
+/// directory as prefix, preserving the directory structure within the tree. Root,
+/// `.` and `..` components are kept.
+///
+/// # Arguments
+/// * `path` - The path to anonymize.
+/// * `salt` - The secret salt.
+/// * `hash_type` - The hash algorithm to derive the tokens with.
+///
+/// # Returns
+/// The anonymized path.
+pub fn anonymize_path(path: &FilePath, salt: &[u8], hash_type: GeneralHashType) -> FilePath {
+    let mut prefix = Vec::new();
+
+    let components = path.path.iter().map(|component| {
+        let mut anonymized = PathBuf::new();
+
+        for name in component.path.components() {
+            match name {
+                Component::Normal(name) => {
+                    prefix.extend_from_slice(name.as_encoded_bytes());
+                    prefix.push(0);
+
+                    let mut hasher = hash_type.hasher();
+                    hasher.update(salt);
+                    hasher.update(&prefix);
+                    let token = hasher.finalize();
+                    let token = token.as_bytes();
+
+                    anonymized.push(encode_hex(&token[..TOKEN_BYTES.min(token.len())]));
+                },
+                other => anonymized.push(other.as_os_str()),
+            }
+        }
+
+        // separate the names of nested path components (archives)
+        prefix.push(1);
+
+        PathComponent {
+            path: anonymized,
+            target: component.target.clone(),
+        }
+    }).collect();
+
+    FilePath::from_pathcomponents(components)
+}
+
+/// Encode bytes as lowercase hex string.
+///
+/// # Arguments
+/// * `bytes` - The bytes to encode.
+///
+/// # Returns
+/// The hex string.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -1,9 +1,18 @@
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::fs;
 use std::path::PathBuf;
-use anyhow::{anyhow, Result};
-use log::{info, trace, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use log::{info, warn};
 use crate::hash::GeneralHashType;
-use crate::stages::build::output::{HashTreeFile, HashTreeFileEntryType};
+use crate::stages::clean::progress::CleanProgress;
+use crate::stages::clean::anonymize::{anonymize_path, check_hash_type, load_or_create_salt};
+use crate::stages::clean::rewrite::rewrite_prefix;
+use crate::stages::build::cmd::warning::{send_warning, BuildWarning, WarningSink};
+use crate::stages::build::output::{HashTreeFile, HashTreeFileEntry, HashTreeFileEntryType};
+use crate::utils::ReplacementFile;
 
 /// Settings for the clean stage.
 /// 
@@ -12,13 +21,32 @@ use crate::stages::build::output::{HashTreeFile, HashTreeFileEntryType};
 /// * `output` - The output hashtree file to write the cleaned hashtree to.
 /// * `root` - The root path of the original working directory. This is used to resolve relative paths.
 /// * `follow_symlinks` - Whether to follow symlinks when checking if files exist.
+/// * `anonymize_salt` - If set, the paths of the cleaned hashtree are replaced by opaque tokens,
+///   salted with the salt in this file (created if it does not exist). See [anonymize_path].
+/// * `strict` - Whether to fail on malformed entries instead of keeping them, see [HashTreeFileEntry::validate].
+/// * `rewrite_prefixes` - Leading directories of paths to replace and their replacements, see [rewrite_prefix].
+///   Entries are checked for existence at their rewritten path.
+/// * `warnings` - If set, duplicate entries and entries whose existence could not be checked are sent to this
+///   channel in addition to logging them, see [BuildWarning].
+/// * `progress` - Whether to log the number of checked and removed entries every few seconds, see [CleanProgress].
+/// * `dedupe_identical_lines` - Whether to only remove entries that repeat an entry byte for byte, keeping the last
+///   one. Older entries of the same path and entries of deleted files are kept, no file is checked.
 pub struct CleanSettings {
     pub input: PathBuf,
     pub output: PathBuf,
     pub root: Option<String>,
     pub follow_symlinks: bool,
+    pub anonymize_salt: Option<PathBuf>,
+    pub rewrite_prefixes: Vec<(PathBuf, PathBuf)>,
+    pub strict: bool,
+    pub warnings: WarningSink,
+    pub progress: bool,
+    pub dedupe_identical_lines: bool,
 }
 
+/// The interval in which the progress is logged.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
 /// Run the clean command.
 /// 
 /// # Arguments
@@ -26,89 +54,185 @@ pub struct CleanSettings {
 pub fn run(
     clean_settings: CleanSettings,
 ) -> Result<()> {
-    let mut input_file_options = fs::File::options();
-    input_file_options.read(true);
-    input_file_options.write(false);
-
-    let mut output_file_options = fs::File::options();
-    output_file_options.create(true);
-    output_file_options.write(true);
-
-    let input_file = match input_file_options.open(clean_settings.input) {
-        Ok(file) => file,
-        Err(err) => {
-            return Err(anyhow!("Failed to open input file: {}", err));
-        }
-    };
-
-    let output_file = match output_file_options.open(clean_settings.output) {
-        Ok(file) => file,
-        Err(err) => {
-            return Err(anyhow!("Failed to open output file: {}", err));
-        }
-    };
+    let input_file = fs::File::open(&clean_settings.input).context("Failed to open input file")?;
+    // the output is often the input, it is only replaced once the cleaned entries are written completely
+    let output = ReplacementFile::create(&clean_settings.output).context("Failed to open output file")?;
 
     let mut input_buf_reader = std::io::BufReader::new(&input_file);
-    let mut output_buf_writer = std::io::BufWriter::new(&output_file);
+    let mut output_buf_writer = std::io::BufWriter::new(output.file());
 
-    let mut save_file = HashTreeFile::new(&mut output_buf_writer, &mut input_buf_reader, GeneralHashType::NULL, false, true, true);
+    // without the path map every loaded entry is kept, also older entries of the same path
+    let mut save_file = HashTreeFile::new(&mut output_buf_writer, &mut input_buf_reader, GeneralHashType::NULL, false, !clean_settings.dedupe_identical_lines, true);
+    save_file.set_strict(clean_settings.strict);
+    save_file.set_warnings(clean_settings.warnings.clone());
     save_file.load_header()?;
+    
+    let salt = match &clean_settings.anonymize_salt {
+        Some(salt_file) => {
+            check_hash_type(save_file.header.hash_type)?;
+            Some(load_or_create_salt(salt_file)?)
+        },
+        None => None,
+    };
+    
+    // the paths of an anonymized tree do not exist on disk
+    let check_exists = !save_file.header.anonymized && !clean_settings.dedupe_identical_lines;
+    if save_file.header.anonymized && !clean_settings.dedupe_identical_lines {
+        info!("The hashtree contains anonymized paths, only older entries of the same path are removed");
+    }
 
+    let progress = CleanProgress::new(clean_settings.progress, PROGRESS_INTERVAL);
+    let rewritten_entries = Cell::new(0usize);
+    
     // remove duplicates, remove deleted files
     save_file.load_all_entries(|entry| {
-        match entry.path.resolve_file() {
-            Ok(path) => {
-                if !path.exists() {
-                    return false;
-                }
-                
-                let metadata = match clean_settings.follow_symlinks { 
-                    true => fs::metadata(path),
-                    false => fs::symlink_metadata(path)
-                };
-                let metadata = match metadata {
-                    Ok(data) => Some(data),
-                    Err(err) => {
-                        warn!("Unable to read metadata of {:?}: {}", entry.path, err);
-                        None
-                    }
-                };
-                
-                if let Some(metadata) = metadata {
-                    return if metadata.is_symlink() {
-                        entry.file_type == HashTreeFileEntryType::Symlink
-                    } else if metadata.is_dir() {
-                        entry.file_type == HashTreeFileEntryType::Directory
-                    } else if metadata.is_file() {
-                        entry.file_type == HashTreeFileEntryType::File
-                    } else {
-                        entry.file_type == HashTreeFileEntryType::Other
-                    }
-                }
-                
-                true
-            },
-            Err(err) => {
-                warn!("File {:?} resolving failed: {}", entry.path, err);
-                true
-            }
-        }
+        let keep = !check_exists || match rewrite_prefix(&entry.path, &clean_settings.rewrite_prefixes) {
+            Some(path) => entry_exists(&HashTreeFileEntry { path, ..entry.clone() }, clean_settings.follow_symlinks, &clean_settings.warnings),
+            None => entry_exists(entry, clean_settings.follow_symlinks, &clean_settings.warnings),
+        };
+        
+        progress.add_checked(!keep);
+        
+        keep
     })?;
     
+    let identical_lines = match clean_settings.dedupe_identical_lines {
+        true => remove_identical_entries(&mut save_file.all_entries)?,
+        false => 0,
+    };
+    
     // todo filter files deleted from inside archives
 
     // save results
 
-    info!("Saving results to output file. Dont interrupt this process. It may corrupt the file.");
+    info!("Saving results to output file");
+    if salt.is_some() {
+        save_file.header.anonymized = true;
+    }
     save_file.save_header()?;
     for entry in save_file.all_entries.iter() {
-        save_file.write_entry(entry)?;
+        let rewritten = rewrite_prefix(&entry.path, &clean_settings.rewrite_prefixes);
+        if rewritten.is_some() {
+            rewritten_entries.set(rewritten_entries.get() + 1);
+        }
+        
+        match (&salt, rewritten) {
+            (Some(salt), rewritten) => {
+                let mut entry = entry.as_ref().clone();
+                entry.path = anonymize_path(rewritten.as_ref().unwrap_or(&entry.path), salt, save_file.header.hash_type);
+                save_file.write_entry(&entry)?;
+            },
+            (None, Some(path)) => {
+                let mut entry = entry.as_ref().clone();
+                entry.path = path;
+                save_file.write_entry(&entry)?;
+            },
+            (None, None) => save_file.write_entry(entry)?,
+        }
     }
     
     save_file.flush()?;
+    let (duplicate_entries, identical_duplicate_entries) = save_file.get_duplicate_entries();
+    drop(save_file);
+    drop(output_buf_writer);
+    output.persist()?;
 
-    trace!("Truncating output file.");
-    fs::File::set_len(&output_file, save_file.get_written_bytes() as u64)?;
+    match clean_settings.dedupe_identical_lines {
+        true => info!("Removed {} byte-identical repeated entries", identical_lines),
+        false => {
+            info!("Removed {} entries of deleted files or files that changed their type", progress.counts().1);
+            info!("Removed {} older entries of the same path, {} of them byte-identical duplicates", duplicate_entries, identical_duplicate_entries);
+        },
+    }
+    if !clean_settings.rewrite_prefixes.is_empty() {
+        info!("Rewrote the path prefix of {} entries", rewritten_entries.get());
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Remove the entries that repeat a later entry byte for byte, e.g. the unchanged entries appended
+/// again by continued builds. The last repetition is kept, so that it still replaces older entries
+/// of its path when the hash tree is loaded.
+///
+/// # Arguments
+/// * `entries` - The entries in file order.
+///
+/// # Returns
+/// The number of removed entries.
+///
+/// # Errors
+/// If an entry can not be serialized.
+fn remove_identical_entries(entries: &mut Vec<Arc<HashTreeFileEntry>>) -> Result<usize> {
+    let mut lines = HashSet::with_capacity(entries.len());
+    let mut keep = vec![false; entries.len()];
+    for (index, entry) in entries.iter().enumerate().rev() {
+        keep[index] = lines.insert(serde_json::to_string(entry.as_ref())?);
+    }
+    
+    let count = entries.len();
+    let mut keep = keep.into_iter();
+    entries.retain(|_| keep.next().unwrap_or(true));
+    Ok(count - entries.len())
+}
+
+/// Check if the file of an entry still exists with the same file type.
+/// 
+/// # Arguments
+/// * `entry` - The entry to check.
+/// * `follow_symlinks` - Whether to follow symlinks when reading the metadata.
+/// * `warnings` - The sink for the warning if the file can not be checked.
+/// 
+/// # Returns
+/// False if the file does not exist anymore or changed its type, true otherwise.
+/// If the file can not be checked, true is returned.
+fn entry_exists(entry: &HashTreeFileEntry, follow_symlinks: bool, warnings: &WarningSink) -> bool {
+    match entry.path.resolve_file() {
+        Ok(path) => {
+            // without following symlinks, a symlink to a missing target still exists
+            let exists = match follow_symlinks {
+                true => path.exists(),
+                false => fs::symlink_metadata(&path).is_ok(),
+            };
+            if !exists {
+                return false;
+            }
+            
+            let metadata = match follow_symlinks { 
+                true => fs::metadata(&path),
+                false => fs::symlink_metadata(&path)
+            };
+            let metadata = match metadata {
+                Ok(data) => Some(data),
+                Err(err) => {
+                    warn!("Unable to read metadata of {:?}: {}", entry.path, err);
+                    send_warning(warnings, BuildWarning::Unchecked { path: entry.path.clone(), reason: format!("Unable to read the metadata: {}", err) });
+                    None
+                }
+            };
+            
+            if let Some(metadata) = metadata {
+                return if metadata.is_symlink() {
+                    // symlinks to external files are recorded as files with --follow-symlinks-external-as-copy
+                    entry.file_type == HashTreeFileEntryType::Symlink
+                        || (entry.file_type == HashTreeFileEntryType::File && path.is_file())
+                } else if metadata.is_dir() {
+                    // directories that could not be listed and skipped mount points are recorded as others
+                    entry.file_type == HashTreeFileEntryType::Directory || entry.file_type == HashTreeFileEntryType::Other
+                } else if metadata.is_file() {
+                    // files that could not be read or were skipped with --since are recorded as others
+                    entry.file_type == HashTreeFileEntryType::File || entry.file_type == HashTreeFileEntryType::Other
+                } else {
+                    entry.file_type == HashTreeFileEntryType::Other
+                }
+            }
+            
+            true
+        },
+        Err(err) => {
+            warn!("File {:?} resolving failed: {}", entry.path, err);
+            send_warning(warnings, BuildWarning::Unchecked { path: entry.path.clone(), reason: format!("Failed to resolve the path: {}", err) });
+            true
+        }
+    }
+}
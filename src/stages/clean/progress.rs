@@ -0,0 +1,71 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+use log::info;
+
+/// Progress counters of a running clean, logged in a fixed interval while the entries are checked.
+/// Checking an entry reads the metadata of its file, which can be slow on network file systems.
+///
+/// # Fields
+/// * `enabled` - Whether the progress is logged.
+#[derive(Debug)]
+pub struct CleanProgress {
+    pub enabled: bool,
+    interval: Duration,
+    start: Instant,
+    last: Cell<Instant>,
+    checked: Cell<u64>,
+    removed: Cell<u64>,
+}
+
+impl CleanProgress {
+    /// Create new progress counters, starting the clock.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether the progress is logged.
+    /// * `interval` - The interval in which log lines are written.
+    ///
+    /// # Returns
+    /// The progress counters.
+    pub fn new(enabled: bool, interval: Duration) -> Self {
+        let now = Instant::now();
+        CleanProgress {
+            enabled,
+            interval,
+            start: now,
+            last: Cell::new(now),
+            checked: Cell::new(0),
+            removed: Cell::new(0),
+        }
+    }
+
+    /// Count a checked entry and log the progress if the interval elapsed.
+    ///
+    /// # Arguments
+    /// * `removed` - Whether the entry is removed.
+    pub fn add_checked(&self, removed: bool) {
+        self.checked.set(self.checked.get() + 1);
+        if removed {
+            self.removed.set(self.removed.get() + 1);
+        }
+
+        if self.enabled && self.last.get().elapsed() >= self.interval {
+            self.last.set(Instant::now());
+            self.log();
+        }
+    }
+
+    /// Get the counted entries.
+    ///
+    /// # Returns
+    /// The number of checked and removed entries.
+    pub fn counts(&self) -> (u64, u64) {
+        (self.checked.get(), self.removed.get())
+    }
+
+    /// Log the current progress at info level.
+    pub fn log(&self) {
+        let (checked, removed) = self.counts();
+        let seconds = self.start.elapsed().as_secs_f64().max(f64::EPSILON);
+        info!("Progress: {} entries checked ({:.0}/s), {} removed ({:.0}/s)", checked, checked as f64 / seconds, removed, removed as f64 / seconds);
+    }
+}
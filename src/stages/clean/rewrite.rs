@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+use crate::path::FilePath;
+
+/// Replace the leading directories of a file path, e.g. after the backup moved to another mount point.
+///
+/// Only the outermost path component (the path on disk) is rewritten, paths inside archives are kept.
+/// Prefixes match whole path names: `/mnt/old` matches `/mnt/old/file`, but not `/mnt/older/file`.
+/// If several prefixes match, the first one is used.
+///
+/// # Arguments
+/// * `path` - The path to rewrite.
+/// * `rewrites` - The prefixes to replace and their replacements.
+///
+/// # Returns
+/// The rewritten path. None if no prefix matches.
+///
+/// # Example
+/// ```
+/// use std::path::PathBuf;
+/// use backup_deduplicator::path::FilePath;
+/// use backup_deduplicator::stages::clean::rewrite::rewrite_prefix;
+///
+/// let rewrites = vec![
+///     (PathBuf::from("/mnt/old/photos"), PathBuf::from("/mnt/photos")),
+///     (PathBuf::from("/mnt/old"), PathBuf::from("/mnt/new")),
+/// ];
+///
+/// let path = FilePath::from_realpath(PathBuf::from("/mnt/old/docs/letter.txt"));
+/// let rewritten = rewrite_prefix(&path, &rewrites).unwrap();
+/// assert_eq!(rewritten, FilePath::from_realpath(PathBuf::from("/mnt/new/docs/letter.txt")));
+///
+/// let path = FilePath::from_realpath(PathBuf::from("/mnt/old/photos/2024/cat.jpg"));
+/// let rewritten = rewrite_prefix(&path, &rewrites).unwrap();
+/// assert_eq!(rewritten, FilePath::from_realpath(PathBuf::from("/mnt/photos/2024/cat.jpg")));
+///
+/// let path = FilePath::from_realpath(PathBuf::from("/mnt/older/file"));
+/// assert_eq!(rewrite_prefix(&path, &rewrites), None);
+/// ```
+pub fn rewrite_prefix(path: &FilePath, rewrites: &[(PathBuf, PathBuf)]) -> Option<FilePath> {
+    if path.path.is_empty() {
+        return None;
+    }
+
+    rewrites.iter().find_map(|(from, to)| {
+        let mut rest = path.strip_prefix(&FilePath::from_realpath(from.clone()))?;
+
+        let first = &mut rest.path[0];
+        first.path = match first.path.as_os_str().is_empty() {
+            true => to.clone(),
+            false => to.join(&first.path),
+        };
+
+        Some(rest)
+    })
+}
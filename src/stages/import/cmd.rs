@@ -0,0 +1,168 @@
+use std::fs;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::SystemTime;
+use anyhow::{anyhow, Context, Result};
+use log::{error, info};
+use crate::hash::{GeneralHash, GeneralHashType};
+use crate::path::FilePath;
+use crate::stages::build::output::{HashTreeFile, HashTreeFileEntry, HashTreeFileEntryType};
+
+/// The settings for the import command.
+///
+/// # Fields
+/// * `manifest` - The checksum manifest to import, in the format of `sha256sum` and similar tools.
+/// * `base` - The directory the paths in the manifest are relative to.
+/// * `output` - The hash tree file to append the entries to. Created if it does not exist.
+/// * `hash_type` - The hash algorithm the checksums in the manifest were computed with.
+pub struct ImportSettings {
+    pub manifest: PathBuf,
+    pub base: PathBuf,
+    pub output: PathBuf,
+    pub hash_type: GeneralHashType,
+}
+
+/// Run the import command. Appends file entries to a hash tree file using the checksums
+/// of a manifest instead of hashing the files.
+///
+/// Every manifest line has the format `HASH  PATH` (`HASH *PATH` for binary mode), as
+/// written by `sha256sum` and similar tools. The size and modified time of each file are
+/// read from the file system. Malformed lines and files that can not be read are reported
+/// and not imported.
+///
+/// Only files are imported. A later build continuing the hash tree file reuses the
+/// imported hashes as long as the files did not change.
+///
+/// # Arguments
+/// * `import_settings` - The settings for the import command.
+///
+/// # Errors
+/// * If the manifest or the output file cannot be opened.
+/// * If the header of an existing output file cannot be loaded.
+/// * If the output file was created with another hash type.
+/// * If the output file cannot be written to.
+/// * If any manifest line could not be imported.
+pub fn run(import_settings: ImportSettings) -> Result<()> {
+    let manifest_file = fs::File::open(&import_settings.manifest).context("Failed to open manifest file")?;
+
+    let existed = import_settings.output.exists();
+    let output_file = fs::File::options().create(true).read(true).append(true).open(&import_settings.output)
+        .context("Failed to open output file")?;
+
+    let mut output_in = std::io::BufReader::new(&output_file);
+    let mut output_out = std::io::BufWriter::new(&output_file);
+
+    let mut save_file = HashTreeFile::new(&mut output_out, &mut output_in, import_settings.hash_type, false, false, false);
+    if existed {
+        if let Err(err) = save_file.load_header() {
+            return Err(anyhow!("Failed to load header from output file: {}", err));
+        }
+        if save_file.header.anonymized {
+            return Err(anyhow!("The output file contains anonymized paths, entries can not be added"));
+        }
+        if save_file.header.hash_type != import_settings.hash_type {
+            return Err(anyhow!("The output file uses hash type {}, but the manifest was declared as {}", save_file.header.hash_type, import_settings.hash_type));
+        }
+    } else {
+        save_file.save_header()?;
+    }
+
+    // the manifest only has content hashes, the name is mixed in like the build does
+    let file_identity = save_file.header.file_identity;
+
+    let mut imported = 0usize;
+    let mut failed = 0usize;
+
+    for (index, line) in std::io::BufReader::new(manifest_file).lines().enumerate() {
+        let line_number = index + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                return Err(anyhow!("Failed to read manifest line {}: {}", line_number, err));
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut entry = match import_line(&line, &import_settings) {
+            Ok(entry) => entry,
+            Err(err) => {
+                error!("Manifest line {}: {}", line_number, err);
+                failed += 1;
+                continue;
+            }
+        };
+
+        if !file_identity.is_content() {
+            if let Some(name) = entry.path.path.last().and_then(|component| component.path.file_name()) {
+                entry.hash.hash_with_name(name);
+            }
+        }
+
+        save_file.write_entry(&entry)?;
+        imported += 1;
+    }
+
+    save_file.flush()?;
+
+    info!("Imported {} entries", imported);
+
+    if failed > 0 {
+        return Err(anyhow!("{} manifest lines could not be imported", failed));
+    }
+
+    Ok(())
+}
+
+/// Parse a manifest line and read the metadata of the referenced file.
+///
+/// # Arguments
+/// * `line` - The manifest line.
+/// * `import_settings` - The settings for the import command.
+///
+/// # Returns
+/// The hash tree entry of the file.
+///
+/// # Errors
+/// * If the line is malformed or the hash does not match the hash type.
+/// * If the file metadata cannot be read or the path is not a file.
+fn import_line(line: &str, import_settings: &ImportSettings) -> Result<HashTreeFileEntry> {
+    let (hex, path) = line.split_once(' ').ok_or_else(|| anyhow!("Expected the format HASH  PATH"))?;
+    let path = path.strip_prefix(' ').or_else(|| path.strip_prefix('*')).unwrap_or(path);
+    if path.is_empty() {
+        return Err(anyhow!("Expected the format HASH  PATH"));
+    }
+
+    let hash = GeneralHash::from_str(format!("{}:{}", import_settings.hash_type, hex.to_lowercase()).as_str())
+        .map_err(|err| anyhow!("Invalid {} hash {}: {}", import_settings.hash_type, hex, err))?;
+
+    let path = import_settings.base.join(path);
+    let metadata = fs::symlink_metadata(&path).map_err(|err| anyhow!("Failed to read metadata of {:?}: {}", path, err))?;
+    if !metadata.is_file() {
+        return Err(anyhow!("{:?} is not a file", path));
+    }
+
+    let modified = metadata.modified()
+        .map_err(|err| anyhow!("Failed to read modified date of {:?}: {}", path, err))?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| anyhow!("Unable to convert modified date of {:?} to UNIX_EPOCH", path))?
+        .as_secs();
+
+    Ok(HashTreeFileEntry {
+        file_type: HashTreeFileEntryType::File,
+        modified,
+        size: metadata.len(),
+        hash,
+        path: FilePath::from_realpath(path),
+        children: Vec::new(),
+        subtree_bytes: None,
+        physical_size: None,
+        xattr_hash: None,
+        file_id: None,
+        created: None,
+        content_type: None,
+    })
+}
@@ -0,0 +1,434 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use anyhow::{anyhow, Context, Result};
+use log::{error, info, warn};
+use serde::Serialize;
+use crate::content_type::{detect_content_type, DETECTION_BYTES};
+use crate::fileid::HandleIdentifier;
+use crate::hash::{DirHashMode, FileIdentity, GeneralHash, GeneralHashType};
+use crate::path::FilePath;
+use crate::xattr::hash_xattrs;
+use crate::stages::build::cmd::worker::{modified_matches, worker_physical_size};
+use crate::stages::build::intermediary_build_data::{BuildFile, BuildStubInformation};
+use crate::stages::build::output::{HashTreeFile, HashTreeFileEntry, HashTreeFileEntryType, HashTreeFileHeader};
+use crate::utils::{NullWriter, ReplacementFile};
+
+/// The settings for the refresh command.
+///
+/// # Fields
+/// * `input` - The hash tree file to refresh in place.
+/// * `mtime_tolerance` - Modification times that differ from the ones of the hash tree by at most this
+///   many seconds count as unchanged, see [modified_matches].
+pub struct RefreshSettings {
+    pub input: PathBuf,
+    pub mtime_tolerance: u64,
+}
+
+/// The counts of a refresh.
+///
+/// # Fields
+/// * `unchanged` - The entries whose size and modification time did not change.
+/// * `updated` - The files and symlinks that changed and were hashed again.
+/// * `deleted` - The entries whose file does not exist anymore or changed its type. They were removed.
+/// * `failed` - The files that changed but could not be read. They are recorded as `Other` entries.
+/// * `directories` - The directories whose hash was computed again since an entry below them changed.
+/// * `stale_directories` - The directories whose modification time changed. Files added to or renamed in them
+///   are not part of the hash tree until the next build.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct RefreshSummary {
+    pub unchanged: u64,
+    pub updated: u64,
+    pub deleted: u64,
+    pub failed: u64,
+    pub directories: u64,
+    pub stale_directories: u64,
+}
+
+/// The state of an entry after it was checked against the file system.
+enum RefreshState {
+    Unchanged,
+    Updated,
+    Deleted,
+    Failed,
+}
+
+/// Run the refresh command. Updates the entries of a hash tree file in place without listing
+/// any directory: every entry is checked against the file system, files and symlinks whose size or
+/// modification time changed are hashed again, entries of deleted files are removed and the hashes of
+/// the directories above a change are computed again from their remaining children.
+///
+/// Files that were added since the build are not found, a later build adds them. For hashed again
+/// files the optional information recorded by the build (physical size, extended attributes, file id,
+/// creation time and content type) is captured again.
+///
+/// # Arguments
+/// * `refresh_settings` - The settings for the refresh command.
+///
+/// # Returns
+/// The counts of the refresh.
+///
+/// # Errors
+/// * If the hash tree file can not be read or written.
+/// * If the hash tree contains anonymized paths or was built with `--dedup-optimized`.
+pub fn run(refresh_settings: RefreshSettings) -> Result<RefreshSummary> {
+    let input_file = fs::File::open(&refresh_settings.input).context("Failed to open input file")?;
+
+    let mut input_buf_reader = std::io::BufReader::new(&input_file);
+    let mut null_out_writer = NullWriter::new();
+
+    let mut save_file = HashTreeFile::new(&mut null_out_writer, &mut input_buf_reader, GeneralHashType::NULL, false, true, true);
+    save_file.load_header()?;
+
+    if save_file.header.anonymized {
+        return Err(anyhow!("The hash tree contains anonymized paths, which do not exist on disk"));
+    }
+    if save_file.header.dedup_optimized {
+        return Err(anyhow!("The hash tree was built with --dedup-optimized and can only be updated by a new build"));
+    }
+
+    save_file.load_all_entries_no_filter()?;
+
+    let header = save_file.header.clone();
+    let order: Vec<FilePath> = save_file.all_entries.iter().map(|entry| entry.path.clone()).collect();
+    let mut entries = save_file.into_path_map();
+    drop(input_buf_reader);
+
+    let mut summary = RefreshSummary::default();
+    let mut changed_parents = HashSet::new();
+
+    for path in &order {
+        let Some(entry) = entries.get_mut(path) else {
+            continue;
+        };
+
+        let state = refresh_entry(entry, header.file_identity, refresh_settings.mtime_tolerance, &mut summary);
+        match state {
+            RefreshState::Unchanged => summary.unchanged += 1,
+            RefreshState::Updated => summary.updated += 1,
+            RefreshState::Failed => summary.failed += 1,
+            RefreshState::Deleted => {
+                summary.deleted += 1;
+                entries.remove(path);
+            },
+        }
+
+        if !matches!(state, RefreshState::Unchanged) {
+            if let Some(parent) = path.parent() {
+                changed_parents.insert(parent);
+            }
+        }
+    }
+
+    summary.directories = refresh_directories(&mut entries, changed_parents, header.hash_type, header.dir_hash_mode)?;
+
+    // write the entries in their original order to a new file and replace the hash tree with it,
+    // so that an interrupted refresh leaves the hash tree untouched
+    let output = ReplacementFile::create(&refresh_settings.input)?;
+    write_entries(output.file(), header, &order, entries)?;
+    output.persist()?;
+
+    info!("Refreshed the hash tree: {} entries unchanged, {} updated, {} deleted, {} failed, {} directories hashed again",
+        summary.unchanged, summary.updated, summary.deleted, summary.failed, summary.directories);
+    if summary.stale_directories > 0 {
+        warn!("{} directories were modified, files added to them are only found by a build", summary.stale_directories);
+    }
+
+    Ok(summary)
+}
+
+/// Write the header and the entries of a hash tree to an empty file.
+///
+/// # Arguments
+/// * `output_file` - The file to write.
+/// * `header` - The header of the hash tree.
+/// * `order` - The paths of the entries in the order to write them, paths without an entry are skipped.
+/// * `entries` - The entries by path.
+///
+/// # Errors
+/// If the file can not be written.
+fn write_entries(output_file: &fs::File, header: HashTreeFileHeader, order: &[FilePath], mut entries: HashMap<FilePath, HashTreeFileEntry>) -> Result<()> {
+    let mut output_buf_writer = std::io::BufWriter::new(output_file);
+    let mut empty_reader = std::io::empty();
+
+    let mut output = HashTreeFile::new(&mut output_buf_writer, &mut empty_reader, header.hash_type, false, false, false);
+    output.header = header;
+    output.save_header()?;
+    for path in order {
+        if let Some(entry) = entries.remove(path) {
+            output.write_entry(&entry)?;
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Check an entry against the file system and update it if it changed. Only symlink entries are
+/// checked as symlinks, the others are checked at the target of a symlink (as recorded by a build
+/// following symlinks or with `--follow-symlinks-external-as-copy`).
+///
+/// # Arguments
+/// * `entry` - The entry to check, updated in place.
+/// * `file_identity` - The file identity of the hash tree, see [refresh_file].
+/// * `mtime_tolerance` - Modification times within this many seconds of the recorded ones count as unchanged.
+/// * `summary` - The counts of the refresh, directories with a changed modification time are counted.
+///
+/// # Returns
+/// The state of the entry.
+fn refresh_entry(entry: &mut HashTreeFileEntry, file_identity: FileIdentity, mtime_tolerance: u64, summary: &mut RefreshSummary) -> RefreshState {
+    let path = match entry.path.resolve_file() {
+        Ok(path) => path,
+        Err(err) => {
+            error!("Failed to resolve {}: {}", entry.path, err);
+            return RefreshState::Unchanged;
+        }
+    };
+
+    let metadata = match entry.file_type {
+        HashTreeFileEntryType::Symlink => fs::symlink_metadata(&path),
+        _ => fs::metadata(&path),
+    };
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(_) => return RefreshState::Deleted,
+    };
+
+    let modified = metadata.modified().ok()
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    match entry.file_type {
+        HashTreeFileEntryType::File => {
+            if !metadata.is_file() {
+                return RefreshState::Deleted;
+            }
+            if modified_matches(entry.modified, modified, mtime_tolerance) && entry.size == metadata.len() {
+                return RefreshState::Unchanged;
+            }
+            refresh_file(entry, &path, &metadata, modified, file_identity)
+        },
+        HashTreeFileEntryType::Symlink => {
+            if !metadata.is_symlink() {
+                return RefreshState::Deleted;
+            }
+            if modified_matches(entry.modified, modified, mtime_tolerance) && entry.size == metadata.len() {
+                return RefreshState::Unchanged;
+            }
+            let mut hash = GeneralHash::from_type(entry.hash.hash_type());
+            match fs::read_link(&path).map_err(|err| err.into()).and_then(|target| hash.hash_path(&target)) {
+                Ok(_) => {
+                    entry.hash = hash;
+                    entry.modified = modified;
+                    entry.size = metadata.len();
+                    RefreshState::Updated
+                },
+                Err(err) => {
+                    error!("Error while hashing symlink target {:?}: {}", path, err);
+                    mark_failed(entry, modified, metadata.len());
+                    RefreshState::Failed
+                },
+            }
+        },
+        HashTreeFileEntryType::Directory => {
+            if !metadata.is_dir() {
+                return RefreshState::Deleted;
+            }
+            if !modified_matches(entry.modified, modified, mtime_tolerance) {
+                summary.stale_directories += 1;
+            }
+            RefreshState::Unchanged
+        },
+        HashTreeFileEntryType::Other => RefreshState::Unchanged,
+    }
+}
+
+/// Hash a changed file again, mixing in its name if the hash tree was built with [FileIdentity::ContentAndName].
+///
+/// # Arguments
+/// * `entry` - The entry of the file, updated in place.
+/// * `path` - The path of the file.
+/// * `metadata` - The metadata of the file.
+/// * `modified` - The new modification time of the file.
+/// * `file_identity` - The file identity of the hash tree.
+///
+/// # Returns
+/// [RefreshState::Updated], or [RefreshState::Failed] if the file could not be read.
+fn refresh_file(entry: &mut HashTreeFileEntry, path: &Path, metadata: &fs::Metadata, modified: u64, file_identity: FileIdentity) -> RefreshState {
+    let size = metadata.len();
+    let hash_type = entry.hash.hash_type();
+    let mut hash = GeneralHash::from_type(hash_type);
+
+    let content_size = match hash_type {
+        GeneralHashType::NULL => Ok(size),
+        _ => fs::File::open(path).map_err(|err| err.into())
+            .and_then(|file| hash.hash_file(std::io::BufReader::new(file))),
+    };
+
+    match content_size {
+        Ok(content_size) => {
+            if !file_identity.is_content() {
+                if let Some(name) = path.file_name() {
+                    hash.hash_with_name(name);
+                }
+            }
+            entry.hash = hash;
+            entry.modified = modified;
+            entry.size = content_size;
+            recapture_file_information(entry, path, metadata);
+            RefreshState::Updated
+        },
+        Err(err) => {
+            error!("Error while hashing file {:?}: {}", path, err);
+            mark_failed(entry, modified, size);
+            RefreshState::Failed
+        },
+    }
+}
+
+/// Capture the optional information of a hashed again file the same way the build does. Only the information
+/// the build recorded for the file is captured, since the hash tree does not tell which options it was built with.
+/// Information that can not be read anymore is removed.
+///
+/// # Arguments
+/// * `entry` - The entry of the file, updated in place.
+/// * `path` - The path of the file.
+/// * `metadata` - The metadata of the file.
+fn recapture_file_information(entry: &mut HashTreeFileEntry, path: &Path, metadata: &fs::Metadata) {
+    if entry.physical_size.is_some() {
+        entry.physical_size = worker_physical_size(metadata);
+    }
+    if let Some(xattr_hash) = &entry.xattr_hash {
+        entry.xattr_hash = match hash_xattrs(path, xattr_hash.hash_type(), true) {
+            Ok(hash) => hash,
+            Err(err) => {
+                warn!("Failed to read extended attributes of {:?}: {}", path, err);
+                None
+            }
+        };
+    }
+    if entry.file_id.is_some() {
+        // hardlinks are grouped by their file id, a rewritten file may have a new inode
+        entry.file_id = match HandleIdentifier::from_path(path) {
+            Ok(id) => Some(id),
+            Err(err) => {
+                warn!("Failed to read the file id of {:?}: {}", path, err);
+                None
+            }
+        };
+    }
+    if entry.created.is_some() {
+        entry.created = metadata.created().ok()
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs());
+    }
+    if entry.content_type.is_some() {
+        let mut bytes = Vec::with_capacity(DETECTION_BYTES);
+        entry.content_type = match fs::File::open(path).and_then(|file| file.take(DETECTION_BYTES as u64).read_to_end(&mut bytes)) {
+            Ok(_) => detect_content_type(&bytes).map(str::to_string),
+            Err(err) => {
+                warn!("Failed to read {:?} to detect its type: {}", path, err);
+                None
+            }
+        };
+    }
+}
+
+/// Turn an entry into an `Other` entry without content hash, like the build records files it can not read.
+///
+/// # Arguments
+/// * `entry` - The entry to change.
+/// * `modified` - The modification time of the file.
+/// * `size` - The size of the file.
+fn mark_failed(entry: &mut HashTreeFileEntry, modified: u64, size: u64) {
+    *entry = HashTreeFileEntry {
+        file_type: HashTreeFileEntryType::Other,
+        modified,
+        size,
+        hash: GeneralHash::NULL,
+        path: entry.path.clone(),
+        children: Vec::new(),
+        subtree_bytes: None,
+        physical_size: None,
+        xattr_hash: None,
+        file_id: None,
+        created: None,
+        content_type: None,
+    };
+}
+
+/// Compute the hashes of the directories above changed entries again, from the bottom up.
+/// The children of a directory are the remaining entries whose parent it is, hashed the same way
+/// the build hashes them.
+///
+/// # Arguments
+/// * `entries` - The entries of the hash tree by path, updated in place.
+/// * `changed_parents` - The parents of the changed and deleted entries.
+/// * `hash_type` - The hash type of the hash tree.
+/// * `dir_hash_mode` - How the directory hashes of the hash tree are computed.
+///
+/// # Returns
+/// The number of directories whose hash was computed again.
+///
+/// # Errors
+/// If hashing a directory fails.
+fn refresh_directories(entries: &mut HashMap<FilePath, HashTreeFileEntry>, changed_parents: HashSet<FilePath>, hash_type: GeneralHashType, dir_hash_mode: DirHashMode) -> Result<u64> {
+    // every directory above a change changes as well
+    let mut directories = HashSet::new();
+    for parent in changed_parents {
+        let mut current = Some(parent);
+        while let Some(path) = current {
+            if !entries.contains_key(&path) || !directories.insert(path.clone()) {
+                break;
+            }
+            current = path.parent();
+        }
+    }
+
+    let mut children_by_parent: HashMap<FilePath, Vec<FilePath>> = HashMap::new();
+    for path in entries.keys() {
+        if let Some(parent) = path.parent() {
+            if directories.contains(&parent) {
+                children_by_parent.entry(parent).or_default().push(path.clone());
+            }
+        }
+    }
+
+    // the deepest directories first, so that their parents see their new hashes
+    let mut directories: Vec<FilePath> = directories.into_iter().collect();
+    directories.sort_by_cached_key(|path| std::cmp::Reverse((path.path.len(), path.path.last().map(|component| component.path.components().count()).unwrap_or(0))));
+
+    for directory in &directories {
+        let mut children: Vec<BuildFile> = children_by_parent.remove(directory).unwrap_or_default().iter()
+            .filter_map(|path| entries.get(path))
+            .map(|child| BuildFile::Stub(BuildStubInformation {
+                path: child.path.clone(),
+                content_hash: child.hash.clone(),
+                subtree_bytes: match child.file_type {
+                    HashTreeFileEntryType::File => child.size,
+                    HashTreeFileEntryType::Directory => child.subtree_bytes.unwrap_or(0),
+                    _ => 0,
+                },
+            }))
+            .collect();
+        children.sort_by(|a, b| a.get_content_hash().partial_cmp(b.get_content_hash()).expect("Two hashes must compare to each other")
+            .then_with(|| a.get_path().path.last().map(|component| &component.path).cmp(&b.get_path().path.last().map(|component| &component.path))));
+
+        let mut hash = GeneralHash::from_type(hash_type);
+        match dir_hash_mode {
+            DirHashMode::ContentOnly => hash.hash_directory(children.iter())?,
+            DirHashMode::ContentAndNames => hash.hash_directory_with_names(children.iter())?,
+        };
+
+        if let Some(entry) = entries.get_mut(directory) {
+            entry.hash = hash;
+            entry.size = children.len() as u64;
+            entry.subtree_bytes = Some(children.iter().map(|child| child.get_subtree_bytes()).sum());
+            entry.children = children.iter().map(|child| child.get_content_hash().clone()).collect();
+        }
+    }
+
+    Ok(directories.len() as u64)
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use crate::hash::{GeneralHash, GeneralHashType};
+use crate::path::FilePath;
+use crate::stages::build::output::{HashTreeFile, HashTreeFileEntry, HashTreeFileEntryType};
+use crate::utils::NullWriter;
+
+/// The settings for the show command.
+///
+/// # Fields
+/// * `input` - The hash tree file to show.
+/// * `depth` - The maximum number of levels printed below each root. None = unlimited.
+pub struct ShowSettings {
+    pub input: PathBuf,
+    pub depth: Option<usize>,
+}
+
+/// Run the show command. Prints the hash tree as an indented tree to stdout.
+///
+/// The parent/child relationships are reconstructed from the `children` hashes of the
+/// directories, see [link_children]. Entries that are no child of any directory are
+/// printed as roots.
+///
+/// # Arguments
+/// * `show_settings` - The settings for the show command.
+///
+/// # Errors
+/// * If the input file cannot be opened.
+/// * If the header or the entries of the input file cannot be loaded.
+/// * If writing to stdout fails.
+pub fn run(show_settings: ShowSettings) -> Result<()> {
+    let input_file = fs::File::open(show_settings.input).context("Failed to open input file")?;
+
+    let mut input_buf_reader = std::io::BufReader::new(&input_file);
+    let mut null_out_writer = NullWriter::new();
+
+    let mut save_file = HashTreeFile::new(&mut null_out_writer, &mut input_buf_reader, GeneralHashType::NULL, false, true, true);
+    save_file.load_header()?;
+    save_file.load_all_entries_no_filter()?;
+
+    // a continued build appends a new entry for a changed path, only the last one is current
+    let file_by_path = save_file.file_by_path;
+    let entries: Vec<Arc<HashTreeFileEntry>> = save_file.all_entries.into_iter()
+        .filter(|entry| file_by_path.get(&entry.path).is_some_and(|last| Arc::ptr_eq(last, entry)))
+        .collect();
+
+    let (mut children, mut roots) = link_children(&entries);
+
+    for children in children.iter_mut() {
+        children.sort_by_cached_key(|index| entries[*index].path.to_string());
+    }
+    roots.sort_by_cached_key(|index| entries[*index].path.to_string());
+
+    let stdout = std::io::stdout();
+    let mut out = std::io::BufWriter::new(stdout.lock());
+
+    for root in roots {
+        write_node(&mut out, &entries, root, &children, 0, show_settings.depth, true)?;
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Link every directory to the entries listed by the hashes of its `children`. A hash
+/// is matched to an entry below the directory's path first. Hashes without such an
+/// entry are matched to any entry with this hash that is not the child of another
+/// directory, so that subtrees whose paths no longer match their directory (e.g.
+/// renamed after hashing) are still shown below it. Every entry has at most one parent.
+///
+/// # Arguments
+/// * `entries` - The entries of the hash tree.
+///
+/// # Returns
+/// The indices of the children of every entry and the indices of the entries without parent.
+fn link_children(entries: &[Arc<HashTreeFileEntry>]) -> (Vec<Vec<usize>>, Vec<usize>) {
+    let mut children = vec![Vec::new(); entries.len()];
+    let mut has_parent = vec![false; entries.len()];
+
+    // the hashes of every directory not matched to a child yet, with their multiplicity
+    let mut unmatched: Vec<HashMap<&GeneralHash, usize>> = entries.iter().map(|entry| {
+        let mut hashes = HashMap::new();
+        if entry.file_type == HashTreeFileEntryType::Directory {
+            for hash in &entry.children {
+                *hashes.entry(hash).or_insert(0) += 1;
+            }
+        }
+        hashes
+    }).collect();
+
+    let directory_by_path: HashMap<&FilePath, usize> = entries.iter().enumerate()
+        .filter(|(_, entry)| entry.file_type == HashTreeFileEntryType::Directory)
+        .map(|(index, entry)| (&entry.path, index))
+        .collect();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let Some(parent) = entry.path.parent().and_then(|parent| directory_by_path.get(&parent).copied()) else {
+            continue;
+        };
+        if let Some(count) = unmatched[parent].get_mut(&entry.hash).filter(|count| **count > 0) {
+            *count -= 1;
+            children[parent].push(index);
+            has_parent[index] = true;
+        }
+    }
+
+    let mut unlinked_by_hash: HashMap<&GeneralHash, Vec<usize>> = HashMap::new();
+    for (index, entry) in entries.iter().enumerate().rev() {
+        if !has_parent[index] {
+            unlinked_by_hash.entry(&entry.hash).or_default().push(index);
+        }
+    }
+
+    for (parent, hashes) in unmatched.iter().enumerate() {
+        for (hash, count) in hashes {
+            let Some(candidates) = unlinked_by_hash.get_mut(hash) else {
+                continue;
+            };
+            for _ in 0..*count {
+                // a directory can not contain itself
+                let Some(position) = candidates.iter().rposition(|candidate| *candidate != parent) else {
+                    break;
+                };
+                let index = candidates.remove(position);
+                children[parent].push(index);
+                has_parent[index] = true;
+            }
+        }
+    }
+
+    let roots = (0..entries.len()).filter(|index| !has_parent[*index]).collect();
+    (children, roots)
+}
+
+/// Write an entry and its children (recursively) to the output.
+///
+/// # Arguments
+/// * `out` - The output to write to.
+/// * `entries` - The entries of the hash tree.
+/// * `index` - The index of the entry to write.
+/// * `children` - The indices of the children of every entry.
+/// * `level` - The current level below the root.
+/// * `depth` - The maximum level to print. None = unlimited.
+/// * `root` - Whether the entry is a root, roots are printed with their full path.
+///
+/// # Errors
+/// If writing to the output fails.
+fn write_node<W: Write>(out: &mut W, entries: &[Arc<HashTreeFileEntry>], index: usize, children: &[Vec<usize>], level: usize, depth: Option<usize>, root: bool) -> Result<()> {
+    let entry = &entries[index];
+    let name = match root {
+        true => entry.path.to_string(),
+        false => entry.path.path.last()
+            .and_then(|component| component.path.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| entry.path.to_string()),
+    };
+
+    // hash trees of older versions do not record the size of the files below a directory
+    let size = match (&entry.file_type, entry.subtree_bytes) {
+        (HashTreeFileEntryType::Directory, Some(subtree_bytes)) => format!("{} bytes", subtree_bytes),
+        (HashTreeFileEntryType::Directory, None) => format!("{} children", entry.size),
+        _ => format!("{} bytes", entry.size),
+    };
+
+    writeln!(out, "{}{} [{:?}, {}] {}", "  ".repeat(level), name, entry.file_type, size, short_hash(entry))?;
+
+    if depth.is_some_and(|depth| level >= depth) {
+        return Ok(());
+    }
+
+    for child in &children[index] {
+        write_node(out, entries, *child, children, level + 1, depth, false)?;
+    }
+
+    Ok(())
+}
+
+/// Shorten the hash of an entry for display purposes.
+///
+/// # Arguments
+/// * `entry` - The entry to get the hash from.
+///
+/// # Returns
+/// The hash type and the first 8 hex digits of the hash.
+fn short_hash(entry: &HashTreeFileEntry) -> String {
+    let hash = entry.hash.to_string();
+    match hash.split_once(':') {
+        Some((hash_type, data)) => format!("{}:{}", hash_type, &data[..data.len().min(8)]),
+        None => hash,
+    }
+}
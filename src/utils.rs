@@ -1,7 +1,9 @@
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::{PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
-use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Context, Result};
 
 /// Trait to convert a path to a lexical absolute path.
 /// Does not require the path to exist.
@@ -87,6 +89,127 @@ pub fn get_time() -> u64 {
         .map(|d| d.as_secs()).unwrap_or(0)
 }
 
+/// Parse a point in time given as seconds since the Unix epoch or as ISO 8601 date (`2024-05-01`)
+/// or date and time (`2024-05-01T12:30:00`, optionally followed by `Z` or an offset like `+02:00`).
+/// Dates and times without an offset are in UTC.
+///
+/// # Arguments
+/// * `s` - The string to parse.
+///
+/// # Returns
+/// The point in time in seconds since the Unix epoch.
+///
+/// # Errors
+/// If the string is neither a number nor a valid ISO 8601 date or date and time, or is before the Unix epoch.
+///
+/// # Example
+/// ```
+/// use backup_deduplicator::utils::parse_timestamp;
+///
+/// assert_eq!(parse_timestamp("1714566600").unwrap(), 1714566600);
+/// assert_eq!(parse_timestamp("2024-05-01").unwrap(), 1714521600);
+/// assert_eq!(parse_timestamp("2024-05-01T12:30:00Z").unwrap(), 1714566600);
+/// assert_eq!(parse_timestamp("2024-05-01 14:30:00+02:00").unwrap(), 1714566600);
+/// assert!(parse_timestamp("2024-02-30").is_err());
+/// assert!(parse_timestamp("yesterday").is_err());
+/// ```
+pub fn parse_timestamp(s: &str) -> Result<u64> {
+    let s = s.trim();
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(seconds);
+    }
+
+    let invalid = || anyhow!("Invalid timestamp {:?}, expected seconds since the Unix epoch or an ISO 8601 date like 2024-05-01T12:30:00Z", s);
+    let number = |part: Option<&str>, digits: usize| -> Result<i64> {
+        match part {
+            Some(part) if part.len() == digits && part.bytes().all(|byte| byte.is_ascii_digit()) => part.parse().map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    };
+
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+
+    let mut date_parts = date.split('-');
+    let year = number(date_parts.next(), 4)?;
+    let month = number(date_parts.next(), 2)?;
+    let day = number(date_parts.next(), 2)?;
+    if date_parts.next().is_some() || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+
+    let mut seconds = days_from_civil(year, month, day) * 86400;
+
+    if let Some(time) = time {
+        let (time, offset) = match time.find(['Z', '+', '-']) {
+            Some(index) => (&time[..index], Some(&time[index..])),
+            None => (time, None),
+        };
+
+        let mut time_parts = time.split(':');
+        let hour = number(time_parts.next(), 2)?;
+        let minute = number(time_parts.next(), 2)?;
+        let second = match time_parts.next() {
+            Some(second) => number(Some(second), 2)?,
+            None => 0,
+        };
+        if time_parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+            return Err(invalid());
+        }
+        seconds += hour * 3600 + minute * 60 + second;
+
+        match offset {
+            None | Some("Z") => {},
+            Some(offset) => {
+                let sign = if offset.starts_with('-') { -1 } else { 1 };
+                let (hours, minutes) = offset[1..].split_once(':').ok_or_else(invalid)?;
+                let offset = number(Some(hours), 2)? * 3600 + number(Some(minutes), 2)? * 60;
+                seconds -= sign * offset;
+            },
+        }
+    }
+
+    u64::try_from(seconds).map_err(|_| anyhow!("The timestamp {:?} is before the Unix epoch", s))
+}
+
+/// Get the number of days of a month in the proleptic Gregorian calendar.
+///
+/// # Arguments
+/// * `year` - The year.
+/// * `month` - The month, 1 to 12.
+///
+/// # Returns
+/// The number of days of the month.
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Get the number of days between the Unix epoch and a date of the proleptic Gregorian calendar.
+///
+/// # Arguments
+/// * `year` - The year.
+/// * `month` - The month, 1 to 12.
+/// * `day` - The day of the month, starting at 1.
+///
+/// # Returns
+/// The number of days since 1970-01-01, negative for earlier dates.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // shift the year to start in March, so that the leap day is the last day of the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
 /// A writer that discards all data.
 /// 
 /// # Example
@@ -128,11 +251,343 @@ impl Write for NullWriter {
     fn flush(&mut self) -> std::io::Result<()> {Ok(())}
 }
 
+/// A new file written next to a file it replaces once it is complete, so that an interrupted or
+/// failed write leaves the replaced file untouched. The new file is removed if it is dropped
+/// without being persisted.
+///
+/// # Example
+/// ```
+/// use std::fs;
+/// use std::io::Write;
+/// use backup_deduplicator::utils::ReplacementFile;
+///
+/// let path = std::env::temp_dir().join(format!("bdd-replacement-example-{}.bdd", std::process::id()));
+/// fs::write(&path, "old").unwrap();
+///
+/// let replacement = ReplacementFile::create(&path).unwrap();
+/// replacement.file().write_all(b"new").unwrap();
+/// drop(replacement);
+/// assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+///
+/// let replacement = ReplacementFile::create(&path).unwrap();
+/// replacement.file().write_all(b"new").unwrap();
+/// replacement.persist().unwrap();
+/// assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+/// # fs::remove_file(&path).unwrap();
+/// ```
+pub struct ReplacementFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    file: File,
+    persisted: bool,
+}
+
+impl ReplacementFile {
+    /// Create the new file next to the file to replace, named like it with a `.tmp` suffix.
+    /// It takes the permissions of the file to replace, if that exists.
+    ///
+    /// # Arguments
+    /// * `path` - The file to replace, it does not need to exist.
+    ///
+    /// # Returns
+    /// The new, empty file.
+    ///
+    /// # Errors
+    /// If the new file can not be created.
+    pub fn create(path: &Path) -> Result<Self> {
+        let temp_path = Self::temp_path(path);
+        let file = File::create(&temp_path).with_context(|| format!("Failed to create {:?}", temp_path))?;
+        let replacement = ReplacementFile { path: path.to_path_buf(), temp_path, file, persisted: false };
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&replacement.temp_path, metadata.permissions()).context("Failed to copy the permissions of the replaced file")?;
+        }
+        Ok(replacement)
+    }
+
+    /// The path of the new file that replaces a file.
+    ///
+    /// # Arguments
+    /// * `path` - The file to replace.
+    ///
+    /// # Returns
+    /// The path next to it with a `.tmp` suffix.
+    pub fn temp_path(path: &Path) -> PathBuf {
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        path.with_file_name(temp_name)
+    }
+
+    /// The new file to write to.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Sync the new file to disk and move it over the replaced file.
+    ///
+    /// # Errors
+    /// If syncing or renaming fails, the new file is removed and the replaced file is untouched.
+    pub fn persist(mut self) -> Result<()> {
+        self.file.sync_all().with_context(|| format!("Failed to write {:?}", self.temp_path))?;
+        fs::rename(&self.temp_path, &self.path).with_context(|| format!("Failed to replace {:?}", self.path))?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for ReplacementFile {
+    /// Remove the new file if it was not persisted.
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// A counting semaphore. Bounds the number of threads holding a permit at the same time.
+/// 
+/// # Example
+/// ```
+/// use backup_deduplicator::utils::Semaphore;
+/// 
+/// let semaphore = Semaphore::new(2);
+/// let first = semaphore.acquire();
+/// let second = semaphore.acquire();
+/// assert_eq!(semaphore.available(), 0);
+/// 
+/// drop(first);
+/// assert_eq!(semaphore.available(), 1);
+/// drop(second);
+/// assert_eq!(semaphore.available(), 2);
+/// ```
+#[derive(Debug)]
+pub struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A permit of a [Semaphore]. The permit is returned when dropped.
+#[derive(Debug)]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Create a new semaphore.
+    /// 
+    /// # Arguments
+    /// * `permits` - The number of permits that can be held at the same time.
+    /// 
+    /// # Returns
+    /// The created semaphore.
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Acquire a permit, blocking until one is available.
+    /// 
+    /// # Returns
+    /// The permit, it is released when dropped.
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        let mut permits = self.permits.lock().expect("Failed to lock semaphore");
+        while *permits == 0 {
+            permits = self.released.wait(permits).expect("Failed to lock semaphore");
+        }
+        *permits -= 1;
+        
+        SemaphorePermit {
+            semaphore: self,
+        }
+    }
+
+    /// Get the number of permits that are currently available.
+    /// 
+    /// # Returns
+    /// The number of available permits.
+    pub fn available(&self) -> usize {
+        *self.permits.lock().expect("Failed to lock semaphore")
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    /// Release the permit and wake up a waiting thread.
+    fn drop(&mut self) {
+        let mut permits = self.semaphore.permits.lock().expect("Failed to lock semaphore");
+        *permits += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+/// Get the soft limit of open file descriptors of this process.
+/// 
+/// # Returns
+/// The soft limit (`RLIMIT_NOFILE`). None if it is unlimited, can not be read or the platform has no such limit.
+#[cfg(unix)]
+pub fn open_files_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    
+    // SAFETY: getrlimit only writes to the given struct
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 || limit.rlim_cur == libc::RLIM_INFINITY {
+        return None;
+    }
+    
+    Some(limit.rlim_cur)
+}
+
+/// Get the soft limit of open file descriptors of this process.
+/// 
+/// # Returns
+/// None, the limit is only available on Unix.
+#[cfg(not(unix))]
+pub fn open_files_limit() -> Option<u64> {
+    None
+}
+
+/// Limits the number of events per second, e.g. log lines. Events beyond the limit are counted
+/// as suppressed and reported when the next second starts.
+///
+/// # Example
+/// ```
+/// use std::time::{Duration, Instant};
+/// use backup_deduplicator::utils::RateLimit;
+///
+/// let start = Instant::now();
+/// let mut limit = RateLimit::new(2, start);
+/// assert_eq!(limit.allow(start), (true, 0));
+/// assert_eq!(limit.allow(start), (true, 0));
+/// assert_eq!(limit.allow(start), (false, 0));
+/// assert_eq!(limit.allow(start + Duration::from_millis(500)), (false, 0));
+/// // a new second starts, the suppressed events are reported once
+/// assert_eq!(limit.allow(start + Duration::from_secs(1)), (true, 2));
+/// assert_eq!(limit.allow(start + Duration::from_secs(1)), (true, 0));
+/// ```
+#[derive(Debug)]
+pub struct RateLimit {
+    per_second: u64,
+    window_start: Instant,
+    allowed: u64,
+    suppressed: u64,
+}
+
+impl RateLimit {
+    /// Create a rate limit.
+    ///
+    /// # Arguments
+    /// * `per_second` - The maximum number of events per second.
+    /// * `now` - The current time, the first second starts at it.
+    ///
+    /// # Returns
+    /// The rate limit.
+    pub fn new(per_second: u64, now: Instant) -> Self {
+        RateLimit {
+            per_second,
+            window_start: now,
+            allowed: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Count an event and decide whether it is allowed.
+    ///
+    /// # Arguments
+    /// * `now` - The time of the event.
+    ///
+    /// # Returns
+    /// Whether the event is allowed, and the number of events suppressed in the previous second
+    /// if this event starts a new second, 0 otherwise.
+    pub fn allow(&mut self, now: Instant) -> (bool, u64) {
+        let mut previously_suppressed = 0;
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            previously_suppressed = self.suppressed;
+            self.window_start = now;
+            self.allowed = 0;
+            self.suppressed = 0;
+        }
+
+        if self.allowed < self.per_second {
+            self.allowed += 1;
+            (true, previously_suppressed)
+        } else {
+            self.suppressed += 1;
+            (false, previously_suppressed)
+        }
+    }
+}
+
 /// Utility functions for the main function of `backup-deduplicator`.
 pub mod main {
     use std::env;
     use std::path::PathBuf;
-    use crate::utils::LexicalAbsolute;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+    use log::{Level, Log, Metadata, Record};
+    use crate::utils::{LexicalAbsolute, RateLimit};
+
+    /// A logger writing through `env_logger`, with trace lines limited to a number per second, see [RateLimit].
+    /// Lines of other levels are never suppressed.
+    struct TraceLimitedLogger {
+        inner: env_logger::Logger,
+        limit: Mutex<RateLimit>,
+    }
+
+    impl Log for TraceLimitedLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            self.inner.enabled(metadata)
+        }
+
+        fn log(&self, record: &Record) {
+            if record.level() == Level::Trace && self.inner.matches(record) {
+                let (allowed, suppressed) = match self.limit.lock() {
+                    Ok(mut limit) => limit.allow(Instant::now()),
+                    Err(_) => (true, 0),
+                };
+                if suppressed > 0 {
+                    self.inner.log(&Record::builder()
+                        .args(format_args!("Suppressed {} trace lines in the last second", suppressed))
+                        .level(Level::Trace)
+                        .target(module_path!())
+                        .build());
+                }
+                if !allowed {
+                    return;
+                }
+            }
+
+            self.inner.log(record);
+        }
+
+        fn flush(&self) {
+            self.inner.flush();
+        }
+    }
+
+    /// Initialize the logger from the `RUST_LOG` environment variable.
+    ///
+    /// # Arguments
+    /// * `trace_rate_limit` - If set, at most this many trace lines are written per second, the others
+    ///   are counted and reported as suppressed. Lines of other levels are unaffected.
+    pub fn init_logger(trace_rate_limit: Option<u64>) {
+        match trace_rate_limit {
+            None => env_logger::init(),
+            Some(per_second) => {
+                let inner = env_logger::Builder::from_default_env().build();
+                let max_level = inner.filter();
+                let logger = TraceLimitedLogger {
+                    inner,
+                    limit: Mutex::new(RateLimit::new(per_second, Instant::now())),
+                };
+                if log::set_boxed_logger(Box::new(logger)).is_ok() {
+                    log::set_max_level(max_level);
+                }
+            },
+        }
+    }
 
     /// Changes the working directory to the given path.
     ///
@@ -164,6 +619,63 @@ pub mod main {
         })
     }
 
+    /// Exit code if a build completed, but some files or directories could not be read.
+    /// Their entries are kept as `Other` entries in the hash tree.
+    pub const EXIT_PARTIAL: i32 = 3;
+
+    /// Exit code if an analysis completed without finding any duplicates, e.g. to skip further processing.
+    pub const EXIT_NO_DUPLICATES: i32 = 4;
+
+    /// Exit code if a build was stopped with Ctrl-C, like a shell reports a process terminated by SIGINT.
+    pub const EXIT_INTERRUPTED: i32 = 130;
+
+    /// Cancel on Ctrl-C instead of terminating the process, so that a command can stop at a point where
+    /// its output is consistent. A second Ctrl-C terminates the process right away.
+    ///
+    /// # Returns
+    /// The flag that is set on the first Ctrl-C. If the handler can not be installed, Ctrl-C terminates
+    /// the process as before and the flag is never set.
+    pub fn cancel_on_interrupt() -> Arc<AtomicBool> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let handler_cancel = Arc::clone(&cancel);
+        let installed = ctrlc::set_handler(move || {
+            if handler_cancel.swap(true, Ordering::Relaxed) {
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+            eprintln!("Stopping after the current step, press Ctrl-C again to stop immediately");
+        });
+        if let Err(err) = installed {
+            log::warn!("Failed to install the Ctrl-C handler: {}", err);
+        }
+        cancel
+    }
+
+    /// Determine the exit code of a failed command.
+    ///
+    /// # Arguments
+    /// * `err` - The error the command failed with.
+    ///
+    /// # Returns
+    /// `exitcode::IOERR` if the error was caused by an I/O error (e.g. reading or writing a
+    /// file failed), else `exitcode::SOFTWARE`.
+    ///
+    /// # Example
+    /// ```
+    /// use anyhow::anyhow;
+    /// use backup_deduplicator::utils::main::error_exit_code;
+    ///
+    /// let err = anyhow::Error::new(std::io::Error::new(std::io::ErrorKind::NotFound, "missing"))
+    ///     .context("Failed to open the hash tree file");
+    /// assert_eq!(error_exit_code(&err), exitcode::IOERR);
+    /// assert_eq!(error_exit_code(&anyhow!("A worker panicked")), exitcode::SOFTWARE);
+    /// ```
+    pub fn error_exit_code(err: &anyhow::Error) -> exitcode::ExitCode {
+        match err.chain().any(|cause| cause.is::<std::io::Error>()) {
+            true => exitcode::IOERR,
+            false => exitcode::SOFTWARE,
+        }
+    }
+
     /// Option how to parse a path.
     ///
     /// # See also
@@ -0,0 +1,116 @@
+mod common;
+
+use common::{entry_line, load_sets, run, run_ok, temp_dir, write_tree};
+
+#[test]
+fn hashes_of_different_sizes_follow_the_collision_policy() {
+    let directory = temp_dir("analyze-collisions");
+    write_tree(&directory.join("tree.bdd"), "XXH32", &[
+        entry_line("File", "a", 3, "XXH32:0a0b0c0d", ""),
+        entry_line("File", "b", 4, "XXH32:0a0b0c0d", ""),
+        entry_line("File", "c", 4, "XXH32:0a0b0c0d", ""),
+        entry_line("File", "d", 5, "XXH32:01020304", ""),
+        entry_line("File", "e", 5, "XXH32:01020304", ""),
+    ]);
+
+    // by default the collided hash is only logged
+    let analyze = run_ok(&directory, ["-v", "analyze", "-i", "tree.bdd", "-o", "warn.json"]);
+    assert!(analyze.stderr.contains("Files with hash XXH32:0a0b0c0d differ in size"), "{}", analyze.stderr);
+    assert_eq!(load_sets(&directory.join("warn.json")), vec![vec!["d", "e"]]);
+
+    // grouped by size, the files of equal size still form a set
+    run_ok(&directory, ["analyze", "--on-collision", "ignore", "-i", "tree.bdd", "-o", "ignore.json"]);
+    let mut sets = load_sets(&directory.join("ignore.json"));
+    sets.sort();
+    assert_eq!(sets, vec![vec!["b", "c"], vec!["d", "e"]]);
+
+    let analyze = run(&directory, ["analyze", "--on-collision", "error", "-i", "tree.bdd", "-o", "error.json"]);
+    assert!(!analyze.status.success());
+    assert!(analyze.stderr.contains("1 hashes are shared by files of different sizes, the first is XXH32:0a0b0c0d"), "{}", analyze.stderr);
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn hardlinks_are_grouped_within_a_set() {
+    let directory = temp_dir("analyze-hardlinks");
+    write_tree(&directory.join("tree.bdd"), "XXH32", &[
+        entry_line("File", "a", 3, "XXH32:01020304", r#","file_id":{"inode":12,"drive":1}"#),
+        entry_line("File", "b", 3, "XXH32:01020304", r#","file_id":{"inode":13,"drive":1}"#),
+        entry_line("File", "c", 3, "XXH32:01020304", r#","file_id":{"inode":12,"drive":1}"#),
+        entry_line("File", "d", 3, "XXH32:01020304", ""),
+    ]);
+
+    run_ok(&directory, ["analyze", "--group-hardlinks", "-i", "tree.bdd", "-o", "sets.json"]);
+    let content = std::fs::read_to_string(directory.join("sets.json")).unwrap();
+    let set: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+    // files sharing an inode form one group, files without a file id are assumed to be copies
+    let groups: Vec<Vec<&str>> = set["hardlinks"].as_array().unwrap().iter()
+        .map(|group| group.as_array().unwrap().iter().map(|file| file["path"][0]["path"].as_str().unwrap()).collect())
+        .collect();
+    assert_eq!(groups, vec![vec!["a", "c"], vec!["b"], vec!["d"]]);
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn null_hashes_are_only_grouped_on_request() {
+    let directory = temp_dir("analyze-null-hashes");
+    // a tree built with the NULL hash type records every file without a content hash
+    write_tree(&directory.join("tree.bdd"), "NULL", &[
+        entry_line("File", "a", 3, "NULL", ""),
+        entry_line("File", "b", 3, "NULL", ""),
+    ]);
+
+    let analyze = run(&directory, ["analyze", "-i", "tree.bdd", "-o", "default.json"]);
+    assert!(analyze.stdout.contains("No duplicates found"), "{}", analyze.stdout);
+
+    run_ok(&directory, ["analyze", "--include-null-hashes", "-i", "tree.bdd", "-o", "included.json"]);
+    assert_eq!(load_sets(&directory.join("included.json")), vec![vec!["a", "b"]]);
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn io_errors_exit_with_their_own_code() {
+    let directory = temp_dir("analyze-io-errors");
+    write_tree(&directory.join("tree.bdd"), "XXH32", &[]);
+    // a socket can not be opened as a file, not even by root
+    let _socket = std::os::unix::net::UnixListener::bind(directory.join("socket.bdd")).unwrap();
+
+    let analyze = run(&directory, ["analyze", "-i", "socket.bdd", "-o", "result.json"]);
+    assert_eq!(analyze.code(), 74, "{}", analyze.stderr);
+    assert!(analyze.stderr.contains("Failed to open input file"), "{}", analyze.stderr);
+
+    let analyze = run(&directory, ["analyze", "-i", "tree.bdd", "-o", "missing/result.json"]);
+    assert_eq!(analyze.code(), 74, "{}", analyze.stderr);
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn trees_of_different_hash_types_are_compared_by_metadata_on_request() {
+    let directory = temp_dir("analyze-mixed-hash");
+    write_tree(&directory.join("new.bdd"), "XXH32", &[
+        entry_line("File", "a", 3, "XXH32:01020304", ""),
+        entry_line("File", "b", 4, "XXH32:0a0b0c0d", ""),
+    ]);
+    write_tree(&directory.join("old.bdd"), "XXH64", &[
+        entry_line("File", "a", 3, "XXH64:0102030405060708", ""),
+        entry_line("File", "b", 5, "XXH64:0a0b0c0d0a0b0c0d", ""),
+    ]);
+
+    let analyze = run(&directory, ["analyze", "-i", "new.bdd", "--unique-to", "old.bdd", "-o", "refused.json"]);
+    assert_eq!(analyze.code(), 70, "{}", analyze.stderr);
+    assert!(analyze.stderr.contains("The hash tree files use different hash types (XXH32 and XXH64)"), "{}", analyze.stderr);
+
+    // a matches by path, size and modification time, the size of b changed
+    run_ok(&directory, ["analyze", "-i", "new.bdd", "--unique-to", "old.bdd", "--allow-mixed-hash", "-o", "unique.json"]);
+    let unique: Vec<serde_json::Value> = std::fs::read_to_string(directory.join("unique.json")).unwrap()
+        .lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(unique.len(), 1);
+    assert_eq!(unique[0]["path"]["path"][0]["path"], "b");
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
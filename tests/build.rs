@@ -0,0 +1,293 @@
+mod common;
+
+use std::fs;
+use common::{find_entry, load_entries, run_ok, temp_dir};
+#[cfg(unix)]
+use common::run_with_file_size_limit;
+use backup_deduplicator::hash::GeneralHash;
+use backup_deduplicator::stages::build::output::HashTreeFileEntryType;
+
+#[test]
+fn empty_directories_are_hashed() {
+    let directory = temp_dir("empty-directories");
+    fs::create_dir_all(directory.join("only/empty")).unwrap();
+    fs::create_dir_all(directory.join("mixed/empty")).unwrap();
+    fs::write(directory.join("mixed/file"), "content").unwrap();
+
+    // a directory whose only child is an empty directory used to hang the build
+    run_ok(&directory, ["build", "-o", "only.bdd", "only"]);
+    let entries = load_entries(&directory.join("only.bdd"));
+    let empty = find_entry(&entries, "only/empty").expect("The empty directory is missing");
+    assert_eq!(empty.file_type, HashTreeFileEntryType::Directory);
+    assert_eq!(empty.size, 0);
+    assert_eq!(find_entry(&entries, "only").unwrap().children, vec![empty.hash.clone()]);
+
+    // next to other children, the empty directory used to be left out of its parent
+    run_ok(&directory, ["build", "-o", "mixed.bdd", "mixed"]);
+    let entries = load_entries(&directory.join("mixed.bdd"));
+    assert!(find_entry(&entries, "mixed/empty").is_some());
+    assert_eq!(find_entry(&entries, "mixed").unwrap().size, 2);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn subtree_bytes_add_up_through_nested_directories() {
+    let directory = temp_dir("subtree-bytes");
+    fs::create_dir_all(directory.join("tree/a/b/c")).unwrap();
+    fs::create_dir_all(directory.join("tree/a/empty")).unwrap();
+    fs::write(directory.join("tree/f1"), "1").unwrap();
+    fs::write(directory.join("tree/a/f2"), "2".repeat(10)).unwrap();
+    fs::write(directory.join("tree/a/b/f3"), "3".repeat(100)).unwrap();
+    fs::write(directory.join("tree/a/b/c/f4"), "4".repeat(1000)).unwrap();
+
+    run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+    let entries = load_entries(&directory.join("tree.bdd"));
+    let subtree_bytes = |path: &str| find_entry(&entries, path).unwrap().subtree_bytes;
+    assert_eq!(subtree_bytes("tree/a/b/c"), Some(1000));
+    assert_eq!(subtree_bytes("tree/a/b"), Some(1100));
+    assert_eq!(subtree_bytes("tree/a/empty"), Some(0));
+    assert_eq!(subtree_bytes("tree/a"), Some(1110));
+    assert_eq!(subtree_bytes("tree"), Some(1111));
+    assert_eq!(subtree_bytes("tree/a/b/c/f4"), None);
+
+    // a continued build takes the sums of unchanged directories from the previous output
+    fs::write(directory.join("tree/a/b/f3"), "3".repeat(200)).unwrap();
+    run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+    let entries = load_entries(&directory.join("tree.bdd"));
+    let subtree_bytes = |path: &str| find_entry(&entries, path).unwrap().subtree_bytes;
+    assert_eq!(subtree_bytes("tree/a/b/c"), Some(1000));
+    assert_eq!(subtree_bytes("tree/a/b"), Some(1200));
+    assert_eq!(subtree_bytes("tree/a"), Some(1210));
+    assert_eq!(subtree_bytes("tree"), Some(1211));
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn skipped_devices_are_recorded_and_checked_through_symlinks() {
+    let directory = temp_dir("skipped-devices");
+    fs::create_dir_all(directory.join("tree/sub")).unwrap();
+    fs::create_dir_all(directory.join("outside")).unwrap();
+    fs::write(directory.join("tree/file"), "content").unwrap();
+    fs::write(directory.join("tree/sub/file"), "content").unwrap();
+    fs::write(directory.join("outside/file"), "content").unwrap();
+    std::os::unix::fs::symlink("../outside", directory.join("tree/link")).unwrap();
+
+    // excluding the device of the tree itself skips every directory below the root
+    run_ok(&directory, ["build", "--follow-symlinks", "--exclude-device", "tree", "-o", "tree.bdd", "tree"]);
+    let entries = load_entries(&directory.join("tree.bdd"));
+    let sub = find_entry(&entries, "tree/sub").expect("The skipped directory is missing");
+    assert_eq!(sub.file_type, HashTreeFileEntryType::Other);
+    assert!(find_entry(&entries, "tree/sub/file").is_none());
+    // the followed symlink leads to a directory on the excluded device as well
+    assert_eq!(find_entry(&entries, "tree/link").unwrap().file_type, HashTreeFileEntryType::Other);
+    assert!(find_entry(&entries, "tree/link/file").is_none());
+    assert_eq!(find_entry(&entries, "tree/file").unwrap().file_type, HashTreeFileEntryType::File);
+    assert_eq!(find_entry(&entries, "tree").unwrap().size, 3);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn continued_build_records_physical_size() {
+    let directory = temp_dir("continued-physical-size");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    fs::write(directory.join("tree/file"), "content").unwrap();
+
+    run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+    assert_eq!(find_entry(&load_entries(&directory.join("tree.bdd")), "tree/file").unwrap().physical_size, None);
+
+    // the file is unchanged, but the previous build did not record its allocated size
+    run_ok(&directory, ["build", "--capture-physical-size", "-o", "tree.bdd", "tree"]);
+    let entries = load_entries(&directory.join("tree.bdd"));
+    assert!(find_entry(&entries, "tree/file").unwrap().physical_size.is_some());
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn continued_build_detects_content_type() {
+    let directory = temp_dir("continued-content-type");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    fs::write(directory.join("tree/document.pdf"), "%PDF-1.7 content").unwrap();
+    fs::write(directory.join("tree/notes"), "plain text").unwrap();
+
+    run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+    let previous = load_entries(&directory.join("tree.bdd"));
+    assert_eq!(find_entry(&previous, "tree/document.pdf").unwrap().content_type, None);
+
+    // the files are unchanged, their type is detected without hashing them again
+    run_ok(&directory, ["build", "--detect-content-type", "-o", "tree.bdd", "tree"]);
+    let entries = load_entries(&directory.join("tree.bdd"));
+    let document = find_entry(&entries, "tree/document.pdf").unwrap();
+    assert_eq!(document.content_type.as_deref(), Some("application/pdf"));
+    assert_eq!(document.hash, find_entry(&previous, "tree/document.pdf").unwrap().hash);
+    assert_eq!(find_entry(&entries, "tree/notes").unwrap().content_type, None);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn roots_report_their_own_progress() {
+    let directory = temp_dir("root-progress");
+    fs::create_dir_all(directory.join("disk1/sub")).unwrap();
+    fs::create_dir_all(directory.join("disk2")).unwrap();
+    fs::write(directory.join("disk1/a"), "a").unwrap();
+    fs::write(directory.join("disk1/sub/b"), "b").unwrap();
+    fs::write(directory.join("disk2/c"), "c").unwrap();
+    fs::write(directory.join("single"), "d").unwrap();
+    fs::write(directory.join("roots.txt"), "disk1\ndisk2\nsingle\n").unwrap();
+
+    let build = run_ok(&directory, ["-v", "build", "--files-from", "roots.txt", "-o", "roots.bdd"]);
+    // files given as roots are only counted in total
+    assert!(build.stderr.contains("Progress: 4 files"), "{}", build.stderr);
+    assert!(build.stderr.contains(r#"Root "disk1": 2 files (0 MB) processed, finished"#), "{}", build.stderr);
+    assert!(build.stderr.contains(r#"Root "disk2": 1 files (0 MB) processed, finished"#), "{}", build.stderr);
+    assert!(!build.stderr.contains(r#"Root "single""#), "{}", build.stderr);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_writes_keep_the_written_entries() {
+    let directory = temp_dir("failed-writes");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    for i in 0..100 {
+        fs::write(directory.join("tree").join(format!("file{}", i)), i.to_string()).unwrap();
+    }
+
+    // the result file can not grow beyond 4 KiB, like a file on a full disk
+    let build = run_with_file_size_limit(&directory, 4096, ["build", "-o", "tree.bdd", "tree"]);
+    assert_eq!(build.code(), 74, "{}", build.stderr);
+    assert!(build.stderr.contains("The result file reached the size limit of its file system. The entries written so far are kept"), "{}", build.stderr);
+    let written = fs::read_to_string(directory.join("tree.bdd")).unwrap();
+    assert!(written.len() <= 4096);
+    assert!(written.starts_with(r#"{"version":"V1""#), "{}", written);
+    assert!(written.lines().count() > 2, "{}", written);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn since_hashes_files_modified_at_or_after_the_time() {
+    let directory = temp_dir("since");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    for (name, modified) in [("before", 999_999), ("equal", 1_000_000), ("after", 1_000_001)] {
+        fs::write(directory.join("tree").join(name), name).unwrap();
+        filetime::set_file_mtime(directory.join("tree").join(name), filetime::FileTime::from_unix_time(modified, 0)).unwrap();
+    }
+
+    run_ok(&directory, ["build", "--since", "1000000", "-o", "tree.bdd", "tree"]);
+    // the older file stays in the hash tree after the clean of the build and after another clean
+    run_ok(&directory, ["clean", "-i", "tree.bdd", "-o", "clean.bdd"]);
+    for output in ["tree.bdd", "clean.bdd"] {
+        let entries = load_entries(&directory.join(output));
+        let before = find_entry(&entries, "tree/before").expect("The older file is missing");
+        assert_eq!(before.file_type, HashTreeFileEntryType::Other);
+        assert_eq!(before.hash, GeneralHash::NULL);
+        assert_eq!(find_entry(&entries, "tree/equal").unwrap().file_type, HashTreeFileEntryType::File);
+        assert_eq!(find_entry(&entries, "tree/after").unwrap().file_type, HashTreeFileEntryType::File);
+    }
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn dry_run_leaves_out_the_result_file() {
+    let directory = temp_dir("dry-run-excluded");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    fs::write(directory.join("tree/file"), "content").unwrap();
+    run_ok(&directory, ["build", "-o", "tree/tree.bdd", "tree"]);
+
+    // like the build, the dry run does not count the result file below the directory
+    let dry_run = run_ok(&directory, ["build", "--dry-run", "--list-files", "-o", "tree/tree.bdd", "tree"]);
+    assert!(dry_run.stdout.contains("Files: 1 (7 bytes)"), "{}", dry_run.stdout);
+    assert!(!dry_run.stdout.contains("tree.bdd"), "{}", dry_run.stdout);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn continued_build_truncates_a_torn_last_line() {
+    let directory = temp_dir("torn-last-line");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    fs::write(directory.join("tree/a"), "a").unwrap();
+    run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+
+    // a checkpoint marker or an entry cut off by a power loss
+    for (torn, file) in [("# checkp", "b"), ("{", "c")] {
+        let mut content = fs::read_to_string(directory.join("tree.bdd")).unwrap();
+        content += torn;
+        fs::write(directory.join("tree.bdd"), content).unwrap();
+        fs::write(directory.join("tree").join(file), file).unwrap();
+
+        run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+        let entries = load_entries(&directory.join("tree.bdd"));
+        assert!(find_entry(&entries, &format!("tree/{}", file)).is_some(), "The appended entry of {} is missing", file);
+        assert!(find_entry(&entries, "tree/a").is_some());
+    }
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn watch_updates_on_changes_and_stops_on_interrupt() {
+    let directory = temp_dir("watch");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    fs::write(directory.join("tree/a"), "a").unwrap();
+    let stderr_path = directory.with_extension("stderr");
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_backup-deduplicator"))
+        .args(["-v", "build", "--watch", "1", "-o", "tree.bdd", "tree"])
+        .current_dir(&directory)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(fs::File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    let wait_for_updates = |count: usize| {
+        let start = std::time::Instant::now();
+        while fs::read_to_string(&stderr_path).unwrap().matches("Watching for changes").count() < count {
+            assert!(start.elapsed() < std::time::Duration::from_secs(30), "{}", fs::read_to_string(&stderr_path).unwrap());
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+    };
+
+    wait_for_updates(1);
+    fs::write(directory.join("tree/b"), "b").unwrap();
+    wait_for_updates(2);
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT); }
+    let status = child.wait().unwrap();
+    assert_eq!(status.code(), Some(130), "{}", fs::read_to_string(&stderr_path).unwrap());
+
+    // the cleaned hash tree replaced the previous one without leaving its temporary file behind
+    let entries = load_entries(&directory.join("tree.bdd"));
+    assert!(find_entry(&entries, "tree/b").is_some());
+    assert!(!directory.join("tree.bdd.tmp").exists());
+
+    fs::remove_file(stderr_path).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn fewer_shards_remove_the_stale_shard_files() {
+    let directory = temp_dir("stale-shards");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    for i in 0..20 {
+        fs::write(directory.join("tree").join(format!("file{}", i)), i.to_string()).unwrap();
+    }
+
+    run_ok(&directory, ["build", "--shards", "4", "-o", "tree.bdd", "tree"]);
+    assert!(directory.join("tree.3.bdd").exists());
+
+    // the shards 2 and 3 of the earlier build are outdated and no part of this split
+    run_ok(&directory, ["build", "--shards", "2", "-o", "tree.bdd", "tree"]);
+    assert!(directory.join("tree.1.bdd").exists());
+    assert!(!directory.join("tree.2.bdd").exists());
+    assert!(!directory.join("tree.3.bdd").exists());
+
+    fs::remove_dir_all(&directory).unwrap();
+}
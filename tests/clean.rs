@@ -0,0 +1,62 @@
+mod common;
+
+use std::fs;
+use common::{find_entry, load_entries, run_ok, temp_dir};
+use backup_deduplicator::stages::build::output::HashTreeFileEntryType;
+
+#[test]
+fn dedupe_identical_lines_keeps_versions() {
+    let directory = temp_dir("clean-dedupe");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    fs::write(directory.join("tree/a"), "a").unwrap();
+    fs::write(directory.join("tree/b"), "b").unwrap();
+    run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+
+    let content = fs::read_to_string(directory.join("tree.bdd")).unwrap();
+    let mut lines: Vec<&str> = content.lines().collect();
+    let entry_a = *lines.iter().find(|line| line.contains("\"tree/a\"")).unwrap();
+    let entry_b = *lines.iter().find(|line| line.contains("\"tree/b\"")).unwrap();
+    let changed_b = entry_b.replace("\"size\":1", "\"size\":2");
+    // a repeated twice, b changed and changed back (repeating it), as appended by continued builds
+    lines.extend([entry_a, changed_b.as_str(), entry_b, entry_a]);
+    fs::write(directory.join("tree.bdd"), lines.join("\n") + "\n").unwrap();
+    fs::remove_dir_all(directory.join("tree")).unwrap();
+
+    let clean = run_ok(&directory, ["-v", "clean", "-i", "tree.bdd", "-o", "tree.bdd", "--overwrite", "--dedupe-identical-lines"]);
+    assert!(clean.stderr.contains("Removed 3 byte-identical repeated entries"), "{}", clean.stderr);
+
+    // the deleted files and the older version of b stay, the last repetition is kept
+    let entries = load_entries(&directory.join("tree.bdd"));
+    let paths: Vec<String> = entries.iter().map(|entry| format!("{} {}", entry.path, entry.size)).collect();
+    assert_eq!(paths, vec!["tree 2", "tree/b 2", "tree/b 1", "tree/a 1"]);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_are_kept_without_following_them() {
+    let directory = temp_dir("clean-symlinks");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    fs::create_dir_all(directory.join("outside")).unwrap();
+    fs::write(directory.join("outside/file"), "content").unwrap();
+    std::os::unix::fs::symlink("missing", directory.join("tree/dangling")).unwrap();
+    std::os::unix::fs::symlink("missing", directory.join("tree/dangling2")).unwrap();
+    std::os::unix::fs::symlink("../outside", directory.join("tree/directory")).unwrap();
+
+    // the build cleans its output as well
+    run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+    run_ok(&directory, ["clean", "-i", "tree.bdd", "-o", "clean.bdd"]);
+
+    for output in ["tree.bdd", "clean.bdd"] {
+        let entries = load_entries(&directory.join(output));
+        let dangling = find_entry(&entries, "tree/dangling").expect("The dangling symlink is missing");
+        assert_eq!(dangling.file_type, HashTreeFileEntryType::Symlink);
+        // links to the same target share a hash and are grouped by the analysis
+        assert_eq!(dangling.hash, find_entry(&entries, "tree/dangling2").unwrap().hash);
+        assert_eq!(find_entry(&entries, "tree/directory").expect("The symlink to a directory is missing").file_type, HashTreeFileEntryType::Symlink);
+        assert!(find_entry(&entries, "tree/directory/file").is_none());
+    }
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,223 @@
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::BufReader;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use backup_deduplicator::hash::GeneralHashType;
+use backup_deduplicator::stages::build::output::{HashTreeFile, HashTreeFileEntry};
+
+/// Time after which a run of the binary is considered hanging.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The result of running the binary.
+///
+/// # Fields
+/// * `status` - The exit status.
+/// * `stdout` - The standard output.
+/// * `stderr` - The standard error output.
+pub struct Run {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Run {
+    /// Get the exit code, panics if the process was killed by a signal.
+    pub fn code(&self) -> i32 {
+        self.status.code().expect("The process was terminated by a signal")
+    }
+}
+
+/// Create an empty directory for a test below the temporary directory.
+///
+/// # Arguments
+/// * `name` - The name of the test, must be unique within the test binaries.
+///
+/// # Returns
+/// The path of the directory.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("bdd-test-{}-{}", name, std::process::id()));
+    if directory.exists() {
+        fs::remove_dir_all(&directory).expect("Failed to clean the test directory");
+    }
+    fs::create_dir_all(&directory).expect("Failed to create the test directory");
+    directory
+}
+
+/// Run the binary in a directory. The output is captured in files next to the
+/// directory, so that a chatty process can not block on a full pipe.
+///
+/// # Arguments
+/// * `directory` - The working directory.
+/// * `args` - The command line arguments.
+///
+/// # Returns
+/// The exit status and the output. Panics if the process does not exit within [TIMEOUT].
+pub fn run<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(directory: &Path, args: I) -> Run {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_backup-deduplicator"));
+    command.args(args);
+    run_command(directory, command)
+}
+
+/// Run the binary with a limit on the size of the files it writes. Writes beyond the limit
+/// fail like on a file system without free space, but with `EFBIG` instead of `ENOSPC`.
+///
+/// # Arguments
+/// * `directory` - The working directory.
+/// * `limit` - The maximum size of a written file in bytes.
+/// * `args` - The command line arguments.
+///
+/// # Returns
+/// The exit status and the output. Panics if the process does not exit within [TIMEOUT].
+#[cfg(unix)]
+pub fn run_with_file_size_limit<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(directory: &Path, limit: u64, args: I) -> Run {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_backup-deduplicator"));
+    command.args(args);
+    // the output files are written by the process as well, keep its error short
+    command.env("RUST_BACKTRACE", "0");
+    unsafe {
+        command.pre_exec(move || {
+            // by default, the process is killed when it exceeds the limit
+            libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+            let limit = libc::rlimit { rlim_cur: limit, rlim_max: limit };
+            match libc::setrlimit(libc::RLIMIT_FSIZE, &limit) {
+                0 => Ok(()),
+                _ => Err(std::io::Error::last_os_error()),
+            }
+        });
+    }
+    run_command(directory, command)
+}
+
+/// Run a command in a directory, see [run].
+fn run_command(directory: &Path, mut command: Command) -> Run {
+    let stdout_path = directory.with_extension("stdout");
+    let stderr_path = directory.with_extension("stderr");
+
+    let mut child = command
+        .current_dir(directory)
+        .stdin(Stdio::null())
+        .stdout(fs::File::create(&stdout_path).expect("Failed to create the stdout file"))
+        .stderr(fs::File::create(&stderr_path).expect("Failed to create the stderr file"))
+        .spawn()
+        .expect("Failed to start the binary");
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().expect("Failed to wait for the binary") {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            panic!("The binary did not exit within {:?}", TIMEOUT);
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    let stdout = fs::read_to_string(&stdout_path).unwrap_or_default();
+    let stderr = fs::read_to_string(&stderr_path).unwrap_or_default();
+    let _ = fs::remove_file(stdout_path);
+    let _ = fs::remove_file(stderr_path);
+
+    Run { status, stdout, stderr }
+}
+
+/// Run the binary and panic with its output if it does not exit successfully.
+///
+/// # Arguments
+/// * `directory` - The working directory.
+/// * `args` - The command line arguments.
+///
+/// # Returns
+/// The exit status and the output.
+pub fn run_ok<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(directory: &Path, args: I) -> Run {
+    let run = run(directory, args);
+    assert!(run.status.success(), "The binary failed with {}:\n{}", run.status, run.stderr);
+    run
+}
+
+/// Load every entry of a hash tree file, in file order.
+///
+/// # Arguments
+/// * `path` - The path of the hash tree file.
+///
+/// # Returns
+/// The entries of the file.
+pub fn load_entries(path: &Path) -> Vec<Arc<HashTreeFileEntry>> {
+    let file = fs::File::open(path).expect("Failed to open the hash tree file");
+    let mut reader = BufReader::new(file);
+    let mut writer = std::io::sink();
+    let mut hash_tree = HashTreeFile::new(&mut writer, &mut reader, GeneralHashType::NULL, false, false, true);
+    hash_tree.load_header().expect("Failed to load the header");
+    hash_tree.load_all_entries_no_filter().expect("Failed to load the entries");
+    hash_tree.all_entries
+}
+
+/// Find the entry of a path in a hash tree file.
+///
+/// # Arguments
+/// * `entries` - The entries of the hash tree file.
+/// * `path` - The path as given to the build, relative to its working directory.
+///
+/// # Returns
+/// The last entry with this path, if any.
+pub fn find_entry<'a>(entries: &'a [Arc<HashTreeFileEntry>], path: &str) -> Option<&'a HashTreeFileEntry> {
+    entries.iter().rev().find(|entry| entry.path.to_string() == path).map(|entry| entry.as_ref())
+}
+
+/// Format a hash tree entry with a path of a single component as a line of a hash tree file.
+///
+/// # Arguments
+/// * `file_type` - The file type, e.g. `File`.
+/// * `path` - The path of the entry.
+/// * `size` - The size of the entry.
+/// * `hash` - The hash, e.g. `XXH32:01020304` or `NULL`.
+/// * `extra` - Further fields, each starting with a comma, e.g. `,"file_id":{"inode":1,"drive":1}`.
+///
+/// # Returns
+/// The entry as JSON.
+pub fn entry_line(file_type: &str, path: &str, size: u64, hash: &str, extra: &str) -> String {
+    format!(
+        r#"{{"file_type":"{}","modified":0,"size":{},"hash":"{}","path":{{"path":[{{"path":"{}","target":"File"}}]}},"children":[]{}}}"#,
+        file_type, size, hash, path, extra,
+    )
+}
+
+/// Write a hash tree file from entry lines, see [entry_line].
+///
+/// # Arguments
+/// * `path` - The path of the hash tree file.
+/// * `hash_type` - The hash type of the header, e.g. `XXH32`.
+/// * `entries` - The entry lines.
+pub fn write_tree(path: &Path, hash_type: &str, entries: &[String]) {
+    let mut content = format!(r#"{{"version":"V1","hash_type":"{}","creation_date":0}}"#, hash_type) + "\n";
+    for entry in entries {
+        content += entry;
+        content += "\n";
+    }
+    fs::write(path, content).expect("Failed to write the hash tree file");
+}
+
+/// Load the duplicate sets of a JSON analysis result.
+///
+/// # Arguments
+/// * `path` - The path of the analysis result.
+///
+/// # Returns
+/// Every set with its paths in the order of the result file.
+pub fn load_sets(path: &Path) -> Vec<Vec<String>> {
+    let content = fs::read_to_string(path).expect("Failed to read the analysis result");
+    content.lines().map(|line| {
+        let set: serde_json::Value = serde_json::from_str(line).expect("Failed to parse a duplicate set");
+        set["conflicting"].as_array().expect("The set has no files").iter()
+            .map(|file| file["path"][0]["path"].as_str().expect("The file has no path").to_string())
+            .collect()
+    }).collect()
+}
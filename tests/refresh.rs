@@ -0,0 +1,56 @@
+mod common;
+
+use std::fs;
+use filetime::FileTime;
+use common::{find_entry, load_entries, run_ok, temp_dir};
+
+#[test]
+fn refresh_honours_the_mtime_tolerance() {
+    let directory = temp_dir("refresh-mtime-tolerance");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    fs::write(directory.join("tree/file"), "content").unwrap();
+    filetime::set_file_mtime(directory.join("tree/file"), FileTime::from_unix_time(1_000_000, 0)).unwrap();
+
+    run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+    let modified = |path: &str| find_entry(&load_entries(&directory.join("tree.bdd")), path).unwrap().modified;
+    assert_eq!(modified("tree/file"), 1_000_000);
+
+    // e.g. a copy to a file system with a coarser time resolution
+    filetime::set_file_mtime(directory.join("tree/file"), FileTime::from_unix_time(1_000_002, 0)).unwrap();
+    run_ok(&directory, ["refresh", "--mtime-tolerance", "2", "-i", "tree.bdd"]);
+    assert_eq!(modified("tree/file"), 1_000_000);
+
+    run_ok(&directory, ["refresh", "-i", "tree.bdd"]);
+    assert_eq!(modified("tree/file"), 1_000_002);
+    // the refreshed hash tree replaced the input file
+    assert!(!directory.join("tree.bdd.tmp").exists());
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[test]
+fn refresh_keeps_the_file_ids_of_hardlinks() {
+    let directory = temp_dir("refresh-hardlinks");
+    fs::create_dir_all(directory.join("tree")).unwrap();
+    fs::write(directory.join("tree/a"), "content").unwrap();
+    fs::hard_link(directory.join("tree/a"), directory.join("tree/b")).unwrap();
+    fs::write(directory.join("tree/c"), "content").unwrap();
+    run_ok(&directory, ["build", "--capture-file-ids", "-o", "tree.bdd", "tree"]);
+
+    // the change of a is seen through its hardlink b as well
+    fs::write(directory.join("tree/a"), "changed content").unwrap();
+    fs::write(directory.join("tree/c"), "changed content").unwrap();
+    run_ok(&directory, ["refresh", "-i", "tree.bdd"]);
+    let entries = load_entries(&directory.join("tree.bdd"));
+    assert!(find_entry(&entries, "tree/a").unwrap().file_id.is_some());
+
+    run_ok(&directory, ["analyze", "--group-hardlinks", "-i", "tree.bdd", "-o", "sets.json"]);
+    let content = fs::read_to_string(directory.join("sets.json")).unwrap();
+    let set: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+    let groups: Vec<Vec<&str>> = set["hardlinks"].as_array().unwrap().iter()
+        .map(|group| group.as_array().unwrap().iter().map(|file| file["path"][0]["path"].as_str().unwrap()).collect())
+        .collect();
+    assert_eq!(groups, vec![vec!["tree/a", "tree/b"], vec!["tree/c"]]);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use std::fs;
+use common::{run_ok, temp_dir};
+
+#[test]
+fn show_links_children_by_hash() {
+    let directory = temp_dir("show-links");
+    fs::create_dir_all(directory.join("tree/a/sub")).unwrap();
+    fs::create_dir_all(directory.join("tree/b")).unwrap();
+    fs::write(directory.join("tree/a/sub/x"), "1").unwrap();
+    fs::write(directory.join("tree/a/y"), "2").unwrap();
+    fs::write(directory.join("tree/b/x"), "1").unwrap();
+    run_ok(&directory, ["build", "-o", "tree.bdd", "tree"]);
+
+    let expected = [
+        "tree [Directory, 3 bytes]",
+        "  a [Directory, 2 bytes]",
+        "    {sub} [Directory, 1 bytes]",
+        "      x [File, 1 bytes]",
+        "    y [File, 1 bytes]",
+        "  b [Directory, 1 bytes]",
+        "    x [File, 1 bytes]",
+    ];
+    let without_hashes = |stdout: &str| -> Vec<String> {
+        stdout.lines().map(|line| line.rsplit_once(' ').unwrap().0.to_string()).collect()
+    };
+
+    let shown = run_ok(&directory, ["show", "-i", "tree.bdd"]);
+    assert_eq!(without_hashes(&shown.stdout), expected.map(|line| line.replace("{sub}", "sub")));
+
+    // rename only the directory entry: its child keeps the old path and is linked by its hash
+    let renamed = fs::read_to_string(directory.join("tree.bdd")).unwrap().replace("\"tree/a/sub\"", "\"tree/a/renamed\"");
+    fs::write(directory.join("renamed.bdd"), renamed).unwrap();
+    let shown = run_ok(&directory, ["show", "-i", "renamed.bdd"]);
+    assert_eq!(without_hashes(&shown.stdout), expected.map(|line| line.replace("{sub}", "renamed")));
+
+    let shown = run_ok(&directory, ["show", "-i", "renamed.bdd", "--depth", "1"]);
+    assert_eq!(without_hashes(&shown.stdout), vec![expected[0], expected[1], expected[5]]);
+
+    // without the sizes below the directories, like a hash tree of an older version, their children are counted
+    let older: String = fs::read_to_string(directory.join("tree.bdd")).unwrap().lines()
+        .map(|line| {
+            let mut entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            entry.as_object_mut().unwrap().remove("subtree_bytes");
+            entry.to_string() + "\n"
+        })
+        .collect();
+    fs::write(directory.join("older.bdd"), older).unwrap();
+    let shown = run_ok(&directory, ["show", "-i", "older.bdd", "--depth", "1"]);
+    assert_eq!(without_hashes(&shown.stdout), vec!["tree [Directory, 2 children]", "  a [Directory, 2 children]", "  b [Directory, 1 children]"]);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
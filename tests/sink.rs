@@ -0,0 +1,95 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::Result;
+use backup_deduplicator::hash::{DirHashMode, FileIdentity, GeneralHashType};
+use backup_deduplicator::stages::build::cmd::{run, BuildSettings};
+use backup_deduplicator::stages::build::cmd::output_order::OutputOrder;
+use backup_deduplicator::stages::build::output::HashTreeFileEntry;
+use backup_deduplicator::stages::build::output::sink::EntrySink;
+use common::{load_entries, temp_dir};
+
+/// Collects the entries passed to it and whether the build finished it.
+struct CollectingSink {
+    entries: Arc<Mutex<Vec<HashTreeFileEntry>>>,
+    finished: Arc<Mutex<bool>>,
+}
+
+impl EntrySink for CollectingSink {
+    fn write(&mut self, entry: &HashTreeFileEntry) -> Result<()> {
+        self.entries.lock().unwrap().push(entry.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        *self.finished.lock().unwrap() = true;
+        Ok(())
+    }
+}
+
+#[test]
+fn sinks_receive_the_written_entries() {
+    let directory = temp_dir("sink");
+    fs::create_dir_all(directory.join("tree/sub")).unwrap();
+    fs::write(directory.join("tree/a"), "a").unwrap();
+    fs::write(directory.join("tree/sub/b"), "b").unwrap();
+    let output = directory.join("tree.bdd");
+
+    let entries = Arc::new(Mutex::new(Vec::new()));
+    let finished = Arc::new(Mutex::new(false));
+    let settings = BuildSettings {
+        directory: directory.join("tree"),
+        files: None,
+        follow_symlinks: false,
+        output: output.clone(),
+        threads: Some(2),
+        hash_type: GeneralHashType::SHA256,
+        continue_file: true,
+        hash_type_by_extension: HashMap::new(),
+        dir_hash_mode: DirHashMode::ContentOnly,
+        file_identity: FileIdentity::Content,
+        eta: false,
+        progress_bar: false,
+        progress_interval: Duration::from_secs(10),
+        checkpoint_interval: None,
+        verify_after: false,
+        one_filesystem: false,
+        exclude_devices: Vec::new(),
+        exclude_files: Vec::new(),
+        capture_physical_size: false,
+        max_open_files: None,
+        max_memory: None,
+        capture_xattrs: false,
+        capture_file_ids: false,
+        capture_btime: false,
+        dedup_optimized: false,
+        detect_content_type: false,
+        mtime_tolerance: 0,
+        since: None,
+        mmap_threshold: None,
+        external_symlinks_as_copy: false,
+        preallocate: false,
+        pin_threads: false,
+        snapshot: None,
+        output_order: OutputOrder::Arrival,
+        sinks: vec![Box::new(CollectingSink { entries: Arc::clone(&entries), finished: Arc::clone(&finished) })],
+        warnings: None,
+        cancel: None,
+    };
+
+    // the settings with their sinks can be moved to the thread running the build
+    let outcome = std::thread::spawn(move || run(settings)).join().unwrap().unwrap();
+    assert_eq!(outcome.summary.files, 2);
+    assert!(*finished.lock().unwrap());
+
+    // the sink received the entries of the result file in the same order
+    let collected: Vec<String> = entries.lock().unwrap().iter().map(|entry| format!("{} {}", entry.path, entry.hash)).collect();
+    let written: Vec<String> = load_entries(&output).iter().map(|entry| format!("{} {}", entry.path, entry.hash)).collect();
+    assert_eq!(collected.len(), 4);
+    assert_eq!(collected, written);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+use backup_deduplicator::hash::{DirHashMode, FileIdentity, GeneralHashType};
+use backup_deduplicator::path::FilePath;
+use backup_deduplicator::pool::ThreadPool;
+use backup_deduplicator::stages::build::cmd::cache::BuildCache;
+use backup_deduplicator::stages::build::cmd::device_filter::DeviceFilter;
+use backup_deduplicator::stages::build::cmd::job::{BuildJob, JobResult};
+use backup_deduplicator::stages::build::cmd::progress::BuildProgress;
+use backup_deduplicator::stages::build::cmd::worker::{worker_run, WorkerArgument};
+use backup_deduplicator::stages::build::output::HashTreeFileEntry;
+use common::temp_dir;
+
+/// A cache that panics on every lookup, standing in for any bug hit while processing a job.
+struct PanickingCache;
+
+impl BuildCache for PanickingCache {
+    fn get(&self, path: &FilePath) -> Option<Arc<HashTreeFileEntry>> {
+        panic!("crafted failure for {}", path);
+    }
+}
+
+fn worker_argument(cache: Arc<dyn BuildCache>) -> WorkerArgument {
+    WorkerArgument {
+        follow_symlinks: false,
+        hash_type: GeneralHashType::SHA256,
+        save_file_by_path: cache,
+        hash_type_by_extension: Arc::new(HashMap::new()),
+        dir_hash_mode: DirHashMode::ContentOnly,
+        file_identity: FileIdentity::Content,
+        progress: Arc::new(BuildProgress::new(false)),
+        device_filter: Arc::new(DeviceFilter::default()),
+        capture_physical_size: false,
+        open_files: None,
+        capture_xattrs: false,
+        capture_file_ids: false,
+        capture_btime: false,
+        shared_sizes: None,
+        detect_content_type: false,
+        mtime_tolerance: 0,
+        since: None,
+        mmap_threshold: None,
+        external_roots: None,
+        snapshots: None,
+        excluded: Arc::new(Vec::new()),
+        warnings: None,
+        unreadable: Arc::new(Mutex::new(Vec::new())),
+        cancel: Arc::new(AtomicBool::new(false)),
+    }
+}
+
+#[test]
+fn panicking_job_becomes_a_result() {
+    let directory = temp_dir("worker-panic");
+    fs::write(directory.join("file"), "content").unwrap();
+    let path = FilePath::from_realpath(directory.join("file"));
+
+    let pool: ThreadPool<BuildJob, JobResult> = ThreadPool::new(vec![worker_argument(Arc::new(PanickingCache))], worker_run);
+
+    // the single worker survives the panic and processes the next job as well
+    for _ in 0..2 {
+        pool.publish(BuildJob::new(None, path.clone()));
+        match pool.receive_timeout(Duration::from_secs(30)) {
+            Ok(JobResult::Panicked(info)) => {
+                assert_eq!(info.path, path);
+                assert!(info.message.starts_with("crafted failure for"), "{}", info.message);
+            },
+            Ok(result) => panic!("Expected a panicked result, got {:?}", result),
+            Err(err) => panic!("No result for the panicking job: {}", err),
+        }
+    }
+
+    drop(pool);
+    fs::remove_dir_all(&directory).unwrap();
+}